@@ -0,0 +1,270 @@
+// Auto-shift: holding a key past a threshold types its shifted variant.
+//
+// An eligible key press is held back until it either gets released, which types it as usual,
+// or stays down past its timeout, which types it with Shift. Times are the millisecond
+// timestamps input events carry, so a key released after the timeout still comes out shifted
+// even if the timer thread hasn't got round to it yet.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AutoShiftLayer {
+    Base,
+    // The pipe/backslash layer; only keys typing a single unshifted character take part
+    Pipe,
+}
+
+pub struct AutoShiftConfig {
+    pub enabled: bool,
+    // How long a key needs to be held for it to be shifted, in milliseconds
+    pub timeout_ms: u32,
+    pub layers: &'static [AutoShiftLayer],
+    // Per-key overrides, by the virtual key being typed: Some(timeout), or None to leave it alone.
+    // Without an override, letters, digits and punctuation use `timeout_ms`.
+    pub keys: &'static [(char, Option<u32>)],
+}
+
+pub const AUTO_SHIFT_CONFIG: AutoShiftConfig = AutoShiftConfig {
+    enabled: false,
+    timeout_ms: 175,
+    layers: &[AutoShiftLayer::Base, AutoShiftLayer::Pipe],
+    keys: &[],
+};
+
+// Letters, digits, and the OEM punctuation keys
+fn is_shiftable(vk: u8) -> bool {
+    match vk {
+        b'A'..=b'Z' | b'0'..=b'9' => true,
+        // VK_OEM_1 (;) through VK_OEM_3 (`), including VK_OEM_PLUS, COMMA, MINUS and PERIOD
+        0xBA..=0xC0 => true,
+        // VK_OEM_4 ([) through VK_OEM_7 (')
+        0xDB..=0xDE => true,
+        _ => false,
+    }
+}
+
+// None if `vk` doesn't auto-shift on `layer`
+pub fn timeout_for(cfg: &AutoShiftConfig, layer: AutoShiftLayer, vk: u8) -> Option<u32> {
+    if !cfg.enabled || !cfg.layers.contains(&layer) {
+        return None;
+    }
+
+    match cfg.keys.iter().find(|&&(key, _)| key as u32 == vk as u32) {
+        Some(&(_, timeout)) => timeout,
+        None if is_shiftable(vk) => Some(cfg.timeout_ms),
+        None => None,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AutoShiftTap {
+    pub vk: u8,
+    pub shifted: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct PendingKey {
+    // Key on the keyboard, which its release will be reported for
+    physical: u8,
+    // Key to be typed
+    output: u8,
+    pressed_at: u32,
+    timeout: u32,
+}
+
+impl PendingKey {
+    fn tap(&self, now: u32) -> AutoShiftTap {
+        AutoShiftTap {
+            vk: self.output,
+            shifted: now.wrapping_sub(self.pressed_at) >= self.timeout,
+        }
+    }
+}
+
+pub struct AutoShiftState {
+    pending: Option<PendingKey>,
+    // Keys which were typed shifted while still down; their auto-repeat and release get swallowed
+    fired: Vec<u8>,
+}
+
+impl AutoShiftState {
+    pub fn new() -> AutoShiftState {
+        AutoShiftState {
+            pending: None,
+            fired: Vec::new(),
+        }
+    }
+
+    // Any other key going down settles the held-back one right away, as it was only
+    // still down because of rolling over from one key to the next while typing
+    pub fn interrupt(&mut self, physical: u8, now: u32) -> Option<AutoShiftTap> {
+        match self.pending {
+            Some(pending) if pending.physical != physical => {
+                self.pending = None;
+                Some(pending.tap(now))
+            }
+            _ => None,
+        }
+    }
+
+    // Returns true if the key press got held back, or is auto-repeat of one which was
+    pub fn press(&mut self, physical: u8, output: u8, timeout: Option<u32>, now: u32) -> bool {
+        if self.fired.contains(&physical) {
+            return true;
+        }
+
+        if let Some(pending) = self.pending {
+            if pending.physical == physical {
+                return true;
+            }
+        }
+
+        match timeout {
+            Some(timeout) => {
+                self.pending = Some(PendingKey {
+                    physical,
+                    output,
+                    pressed_at: now,
+                    timeout,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Returns None if the release isn't ours to handle. Some(None) means it's to be swallowed,
+    // and Some(Some(tap)) that the held-back key needs typing.
+    pub fn release(&mut self, physical: u8, now: u32) -> Option<Option<AutoShiftTap>> {
+        if let Some(pending) = self.pending {
+            if pending.physical == physical {
+                self.pending = None;
+                return Some(Some(pending.tap(now)));
+            }
+        }
+
+        let count = self.fired.len();
+        self.fired.retain(|&key| key != physical);
+        if self.fired.len() != count {
+            Some(None)
+        } else {
+            None
+        }
+    }
+
+    // Timer thread: types the held-back key shifted once it's been down long enough
+    pub fn tick(&mut self, now: u32) -> Option<AutoShiftTap> {
+        let pending = self.pending?;
+        let tap = pending.tap(now);
+
+        if tap.shifted {
+            self.pending = None;
+            self.fired.push(pending.physical);
+            Some(tap)
+        } else {
+            None
+        }
+    }
+
+    // Types whatever is held back as is, and forgets about keys still down
+    pub fn flush(&mut self) -> Option<AutoShiftTap> {
+        self.fired.clear();
+        self.pending.take().map(|pending| AutoShiftTap {
+            vk: pending.output,
+            shifted: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: AutoShiftConfig = AutoShiftConfig {
+        enabled: true,
+        ..AUTO_SHIFT_CONFIG
+    };
+
+    fn tap(vk: u8, shifted: bool) -> Option<Option<AutoShiftTap>> {
+        Some(Some(AutoShiftTap { vk, shifted }))
+    }
+
+    #[test]
+    fn hold_types_shifted() {
+        let mut state = AutoShiftState::new();
+        assert!(state.press(b'A', b'A', Some(175), 1000));
+        assert_eq!(state.release(b'A', 1175), tap(b'A', true));
+    }
+
+    #[test]
+    fn tap_types_plain() {
+        let mut state = AutoShiftState::new();
+        // Typed as what it's remapped to
+        assert!(state.press(b'K', b'E', Some(175), 1000));
+        assert_eq!(state.release(b'K', 1174), tap(b'E', false));
+        assert_eq!(state.release(b'K', 1200), None);
+    }
+
+    #[test]
+    fn overrides() {
+        let cfg = AutoShiftConfig {
+            layers: &[AutoShiftLayer::Base],
+            keys: &[('A', Some(300)), ('1', None), (' ', Some(250))],
+            ..CFG
+        };
+        assert_eq!(timeout_for(&cfg, AutoShiftLayer::Base, b'A'), Some(300));
+        assert_eq!(timeout_for(&cfg, AutoShiftLayer::Base, b'B'), Some(175));
+        assert_eq!(timeout_for(&cfg, AutoShiftLayer::Base, b'1'), None);
+        // Not shiftable, unless listed
+        assert_eq!(timeout_for(&cfg, AutoShiftLayer::Base, b' '), Some(250));
+        assert_eq!(timeout_for(&cfg, AutoShiftLayer::Base, 0x0D), None);
+        assert_eq!(timeout_for(&cfg, AutoShiftLayer::Pipe, b'A'), None);
+        assert_eq!(
+            timeout_for(&AUTO_SHIFT_CONFIG, AutoShiftLayer::Base, b'A'),
+            None
+        );
+    }
+
+    #[test]
+    fn no_timeout_passes_through() {
+        // As with real modifiers held
+        let mut state = AutoShiftState::new();
+        assert!(!state.press(b'C', b'C', None, 1000));
+        assert_eq!(state.tick(2000), None);
+        assert_eq!(state.release(b'C', 2000), None);
+    }
+
+    #[test]
+    fn tick_types_without_release() {
+        let mut state = AutoShiftState::new();
+        state.press(b'A', b'A', Some(175), u32::max_value() - 100);
+        assert_eq!(state.tick(u32::max_value()), None);
+        // Across GetTickCount wrapping around
+        assert_eq!(
+            state.tick(74),
+            Some(AutoShiftTap {
+                vk: b'A',
+                shifted: true
+            })
+        );
+        assert_eq!(state.tick(100), None);
+
+        // Auto-repeat and the release get swallowed
+        assert!(state.press(b'A', b'A', Some(175), 150));
+        assert_eq!(state.release(b'A', 200), Some(None));
+        assert_eq!(state.release(b'A', 250), None);
+    }
+
+    #[test]
+    fn next_key_settles_held_one() {
+        let mut state = AutoShiftState::new();
+        state.press(b'A', b'A', Some(175), 1000);
+        assert_eq!(state.interrupt(b'A', 1050), None);
+        assert_eq!(
+            state.interrupt(b'B', 1050),
+            Some(AutoShiftTap {
+                vk: b'A',
+                shifted: false
+            })
+        );
+        assert_eq!(state.release(b'A', 1100), None);
+    }
+}
@@ -0,0 +1,149 @@
+// Real Caps Lock and caps word, with the Caps key taken over by the Caps layer.
+//
+// Caps word is the friendlier Caps Lock: it upper-cases letters and turns dashes into underscores
+// until the identifier being typed ends, and then turns itself off, as with QMK's Caps Word.
+// Word boundaries go by the characters keys type, after any layout remapping.
+
+pub struct CapsLockConfig {
+    // Caps+Shift toggles the real Caps Lock
+    pub shift_toggles_caps_lock: bool,
+    // Turn Caps Lock off on startup, since the Caps key alone can't any more
+    pub reset_on_startup: bool,
+    // Tapping Caps on its own turns caps word on or off. Off by default, as a quick Caps tap
+    // is also how a Caps layer shortcut gets started and abandoned.
+    pub caps_word_on_tap: bool,
+    // Longest Caps press counting as a tap, in milliseconds
+    pub tap_ms: u32,
+}
+
+pub const CAPS_LOCK_CONFIG: CapsLockConfig = CapsLockConfig {
+    shift_toggles_caps_lock: true,
+    reset_on_startup: true,
+    caps_word_on_tap: false,
+    tap_ms: 200,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CapsWordAction {
+    // Type the key with Shift
+    Shift,
+    // Type an underscore instead
+    Underscore,
+    // Type the key as it is; the word goes on
+    PassThrough,
+    // Type the key as it is; the word is over
+    End,
+}
+
+// VK_BACK, for fixing typos, plus VK_SHIFT, VK_CONTROL, VK_MENU, and their left and right variants
+fn keeps_word_going(vk: u8) -> bool {
+    match vk {
+        0x08 | 0x10..=0x12 | 0xA0..=0xA5 => true,
+        _ => false,
+    }
+}
+
+pub struct CapsWord {
+    on: bool,
+}
+
+impl CapsWord {
+    pub fn new() -> CapsWord {
+        CapsWord { on: false }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    pub fn set(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    // `vk` is the key about to be typed, and `typed` the character it types,
+    // or None if it doesn't type one
+    pub fn key(&mut self, vk: u8, typed: Option<char>) -> CapsWordAction {
+        if !self.on {
+            return CapsWordAction::PassThrough;
+        }
+
+        let action = match typed {
+            _ if keeps_word_going(vk) => CapsWordAction::PassThrough,
+            Some(c) if c.is_lowercase() => CapsWordAction::Shift,
+            Some('-') => CapsWordAction::Underscore,
+            Some(c) if c.is_alphanumeric() || '_' == c => CapsWordAction::PassThrough,
+            // Space, punctuation, Escape, Enter, navigation...
+            _ => CapsWordAction::End,
+        };
+
+        if CapsWordAction::End == action {
+            self.on = false;
+        }
+
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Types `text` key by key, with the virtual key of each character's uppercase form
+    fn type_text(word: &mut CapsWord, text: &str) -> Vec<CapsWordAction> {
+        text.chars()
+            .map(|c| word.key(c.to_ascii_uppercase() as u8, Some(c)))
+            .collect()
+    }
+
+    #[test]
+    fn off_passes_through() {
+        let mut word = CapsWord::new();
+        assert_eq!(word.key(b'A', Some('a')), CapsWordAction::PassThrough);
+    }
+
+    #[test]
+    fn shouts_an_identifier() {
+        let mut word = CapsWord::new();
+        word.set(true);
+        assert_eq!(
+            type_text(&mut word, "a-B_1"),
+            vec![
+                CapsWordAction::Shift,
+                CapsWordAction::Underscore,
+                CapsWordAction::PassThrough,
+                CapsWordAction::PassThrough,
+                CapsWordAction::PassThrough,
+            ]
+        );
+        assert!(word.is_on());
+    }
+
+    #[test]
+    fn ends_on_other_keys() {
+        let mut word = CapsWord::new();
+        word.set(true);
+        assert_eq!(word.key(b' ', Some(' ')), CapsWordAction::End);
+        assert!(!word.is_on());
+        assert_eq!(word.key(b'A', Some('a')), CapsWordAction::PassThrough);
+
+        // Keys typing nothing, like the arrows
+        word.set(true);
+        assert_eq!(word.key(0x25, None), CapsWordAction::End);
+        assert!(!word.is_on());
+    }
+
+    #[test]
+    fn backspace_and_modifiers_keep_it_going() {
+        for &vk in &[0x08, 0x10, 0x11, 0x12, 0xA0, 0xA5] {
+            assert!(keeps_word_going(vk));
+        }
+        assert!(!keeps_word_going(0x09));
+        assert!(!keeps_word_going(0x5B));
+
+        let mut word = CapsWord::new();
+        word.set(true);
+        assert_eq!(word.key(0x08, Some('\u{8}')), CapsWordAction::PassThrough);
+        assert_eq!(word.key(0xA0, None), CapsWordAction::PassThrough);
+        assert!(word.is_on());
+    }
+}
@@ -0,0 +1,420 @@
+// Clipboard history, cycled through with Caps+B.
+//
+// Text copied from anywhere gets recorded here, newest first. The history never touches
+// a clipboard itself; pasting goes through `ClipboardBackend`, so it runs the same
+// against an in-memory clipboard as against the system one.
+
+pub struct ClipboardHistoryConfig {
+    // Unpinned entries kept; pinned ones don't count towards the limit
+    pub max_entries: usize,
+    // Save unpinned entries to disk as well, and not just the pinned ones
+    pub persist_unpinned: bool,
+    // Text copied from these executables is never recorded, e.g. password managers
+    pub excluded_apps: &'static [&'static str],
+    // Characters of the selected entry shown while cycling
+    pub preview_len: usize,
+    // Saved once the history has been left alone this long, in milliseconds, so copying a lot in
+    // a row writes the file once
+    pub save_delay_ms: u32,
+}
+
+pub const CLIPBOARD_HISTORY_CONFIG: ClipboardHistoryConfig = ClipboardHistoryConfig {
+    max_entries: 25,
+    persist_unpinned: false,
+    excluded_apps: &[
+        "KeePass.exe",
+        "KeePassXC.exe",
+        "1Password.exe",
+        "Bitwarden.exe",
+    ],
+    preview_len: 80,
+    save_delay_ms: 2000,
+};
+
+pub trait ClipboardBackend {
+    fn get_text(&mut self) -> Option<String>;
+    // Returns false if the clipboard couldn't be written to
+    fn set_text(&mut self, text: &str) -> bool;
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ClipboardEntry {
+    pub text: String,
+    // Kept regardless of `max_entries`, and always saved to disk
+    pub pinned: bool,
+}
+
+pub struct ClipboardHistory {
+    // Newest first
+    entries: Vec<ClipboardEntry>,
+    // Entry picked while cycling
+    selected: Option<usize>,
+    // Changed since `needs_saving` last looked
+    changed: bool,
+    // When `needs_saving` noticed the last unsaved change
+    changed_at: Option<u32>,
+}
+
+// One entry per line, with line breaks and backslashes escaped
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if '\\' == c {
+            match chars.next() {
+                Some('n') => res.push('\n'),
+                Some('r') => res.push('\r'),
+                Some(other) => res.push(other),
+                None => (),
+            }
+        } else {
+            res.push(c);
+        }
+    }
+
+    res
+}
+
+impl ClipboardHistory {
+    pub fn new() -> ClipboardHistory {
+        ClipboardHistory {
+            entries: Vec::new(),
+            selected: None,
+            changed: false,
+            changed_at: None,
+        }
+    }
+
+    // `source_app` is the executable which put the text on the clipboard.
+    // Returns true if the history changed.
+    pub fn record(
+        &mut self,
+        cfg: &ClipboardHistoryConfig,
+        text: &str,
+        source_app: Option<&str>,
+    ) -> bool {
+        if text.trim().is_empty() {
+            return false;
+        }
+
+        if let Some(app) = source_app {
+            if cfg
+                .excluded_apps
+                .iter()
+                .any(|excluded| excluded.eq_ignore_ascii_case(app))
+            {
+                return false;
+            }
+        }
+
+        // Copying something already in the history moves it to the front, pin and all
+        let pinned = match self.entries.iter().position(|entry| entry.text == text) {
+            Some(0) => return false,
+            Some(idx) => self.entries.remove(idx).pinned,
+            None => false,
+        };
+
+        self.entries.insert(
+            0,
+            ClipboardEntry {
+                text: text.to_owned(),
+                pinned,
+            },
+        );
+
+        let mut unpinned = 0;
+        self.entries.retain(|entry| {
+            if !entry.pinned {
+                unpinned += 1;
+            }
+            entry.pinned || unpinned <= cfg.max_entries
+        });
+
+        self.selected = None;
+        self.changed = true;
+        true
+    }
+
+    pub fn is_cycling(&self) -> bool {
+        self.selected.is_some()
+    }
+
+    // Starts at the entry before the current clipboard contents, and goes further back from there
+    pub fn select_older(&mut self) {
+        let count = self.entries.len();
+        self.selected = match self.selected {
+            _ if 0 == count => None,
+            Some(idx) => Some((idx + 1) % count),
+            None => Some(if count > 1 { 1 } else { 0 }),
+        };
+    }
+
+    pub fn select_newer(&mut self) {
+        let count = self.entries.len();
+        self.selected = match self.selected {
+            _ if 0 == count => None,
+            Some(idx) => Some((idx + count - 1) % count),
+            None => Some(0),
+        };
+    }
+
+    pub fn cancel(&mut self) {
+        self.selected = None;
+    }
+
+    // Pins or unpins the selected entry; returns the new state
+    pub fn toggle_pin(&mut self) -> Option<bool> {
+        let entry = &mut self.entries[self.selected?];
+        entry.pinned = !entry.pinned;
+        self.changed = true;
+        Some(entry.pinned)
+    }
+
+    // Ends cycling by putting the selected entry on the clipboard.
+    // Returns false if nothing was selected, or the clipboard refused it.
+    pub fn paste_selection<B: ClipboardBackend>(&mut self, backend: &mut B) -> bool {
+        match self.selected.take() {
+            Some(idx) => backend.set_text(&self.entries[idx].text),
+            None => false,
+        }
+    }
+
+    // What's shown while cycling
+    pub fn describe(&self, cfg: &ClipboardHistoryConfig) -> String {
+        let idx = match self.selected {
+            Some(idx) => idx,
+            None => return "Clipboard history is empty".to_owned(),
+        };
+
+        let entry = &self.entries[idx];
+        let mut preview: String = entry
+            .text
+            .trim()
+            .chars()
+            .map(|c| if c.is_whitespace() { ' ' } else { c })
+            .take(cfg.preview_len)
+            .collect();
+        if entry.text.trim().chars().count() > cfg.preview_len {
+            preview.push('\u{2026}');
+        }
+
+        format!(
+            "Paste {}/{}{}\n{}",
+            idx + 1,
+            self.entries.len(),
+            if entry.pinned { " (pinned)" } else { "" },
+            preview
+        )
+    }
+
+    // Gets called regularly; true once the history hasn't changed for `save_delay_ms`
+    pub fn needs_saving(&mut self, cfg: &ClipboardHistoryConfig, now: u32) -> bool {
+        if self.changed {
+            self.changed = false;
+            self.changed_at = Some(now);
+        }
+
+        self.changed_at
+            .map_or(false, |at| now.wrapping_sub(at) >= cfg.save_delay_ms)
+    }
+
+    pub fn save(&mut self, cfg: &ClipboardHistoryConfig) -> String {
+        self.changed = false;
+        self.changed_at = None;
+
+        self.entries
+            .iter()
+            .filter(|entry| entry.pinned || cfg.persist_unpinned)
+            .map(|entry| {
+                format!(
+                    "{} {}\n",
+                    if entry.pinned { 'P' } else { '-' },
+                    escape(&entry.text)
+                )
+            })
+            .collect()
+    }
+
+    // Replaces the history with what `save` returned; malformed lines are skipped
+    pub fn load(&mut self, data: &str) {
+        self.entries = data
+            .lines()
+            .filter_map(|line| {
+                let pinned = match line.get(..2) {
+                    Some("P ") => true,
+                    Some("- ") => false,
+                    _ => return None,
+                };
+
+                Some(ClipboardEntry {
+                    text: unescape(&line[2..]),
+                    pinned,
+                })
+            })
+            .collect();
+        self.selected = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stands in for the system clipboard
+    struct MemoryClipboard {
+        text: Option<String>,
+    }
+
+    impl ClipboardBackend for MemoryClipboard {
+        fn get_text(&mut self) -> Option<String> {
+            self.text.clone()
+        }
+
+        fn set_text(&mut self, text: &str) -> bool {
+            self.text = Some(text.to_owned());
+            true
+        }
+    }
+
+    const CFG: ClipboardHistoryConfig = ClipboardHistoryConfig {
+        max_entries: 3,
+        excluded_apps: &["KeePass.exe"],
+        ..CLIPBOARD_HISTORY_CONFIG
+    };
+
+    fn texts(history: &ClipboardHistory) -> Vec<&str> {
+        history
+            .entries
+            .iter()
+            .map(|entry| &entry.text[..])
+            .collect()
+    }
+
+    #[test]
+    fn dedup_moves_to_front() {
+        let mut history = ClipboardHistory::new();
+        assert!(history.record(&CFG, "a", None));
+        assert!(history.record(&CFG, "b", None));
+        // Copying the newest entry again changes nothing
+        assert!(!history.record(&CFG, "b", None));
+        assert!(history.record(&CFG, "a", None));
+        assert_eq!(texts(&history), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn skips_blank_and_excluded() {
+        let mut history = ClipboardHistory::new();
+        assert!(!history.record(&CFG, " \n", None));
+        assert!(!history.record(&CFG, "hunter2", Some("keepass.exe")));
+        assert!(history.record(&CFG, "text", Some("notepad.exe")));
+        assert_eq!(texts(&history), vec!["text"]);
+    }
+
+    #[test]
+    fn limit_spares_pinned() {
+        let mut history = ClipboardHistory::new();
+        history.record(&CFG, "pinned", None);
+        history.select_newer();
+        assert_eq!(history.toggle_pin(), Some(true));
+
+        for text in &["a", "b", "c", "d"] {
+            history.record(&CFG, text, None);
+        }
+        assert_eq!(texts(&history), vec!["d", "c", "b", "pinned"]);
+
+        // Copied again, it keeps its pin
+        history.record(&CFG, "pinned", None);
+        history.record(&CFG, "e", None);
+        assert_eq!(texts(&history), vec!["e", "pinned", "d", "c"]);
+    }
+
+    #[test]
+    fn cycle_and_paste() {
+        let mut history = ClipboardHistory::new();
+        let mut clipboard = MemoryClipboard { text: None };
+        for text in &["a", "b", "c"] {
+            history.record(&CFG, text, None);
+        }
+
+        // Starts with the entry before the current clipboard contents
+        history.select_older();
+        history.select_older();
+        assert_eq!(history.describe(&CFG), "Paste 3/3\na");
+        history.select_older();
+        history.select_newer();
+        assert!(history.paste_selection(&mut clipboard));
+        assert_eq!(clipboard.get_text(), Some("a".to_owned()));
+        assert!(!history.is_cycling());
+        assert!(!history.paste_selection(&mut clipboard));
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let cfg = ClipboardHistoryConfig {
+            persist_unpinned: true,
+            ..CFG
+        };
+        let mut history = ClipboardHistory::new();
+        history.record(&cfg, "C:\\temp\\new", None);
+        history.record(&cfg, "two\r\nlines", None);
+        history.select_newer();
+        history.toggle_pin();
+
+        let saved = history.save(&cfg);
+        assert_eq!(saved.lines().count(), 2);
+
+        let mut loaded = ClipboardHistory::new();
+        loaded.load(&saved);
+        assert_eq!(loaded.entries, history.entries);
+    }
+
+    #[test]
+    fn save_leaves_out_unpinned() {
+        let mut history = ClipboardHistory::new();
+        history.record(&CFG, "kept", None);
+        history.select_newer();
+        history.toggle_pin();
+        history.record(&CFG, "dropped", None);
+
+        let mut loaded = ClipboardHistory::new();
+        loaded.load(&history.save(&CFG));
+        assert_eq!(texts(&loaded), vec!["kept"]);
+    }
+
+    #[test]
+    fn save_waits_for_a_pause() {
+        let mut history = ClipboardHistory::new();
+        assert!(!history.needs_saving(&CFG, 0));
+
+        history.record(&CFG, "a", None);
+        assert!(!history.needs_saving(&CFG, 1000));
+        history.record(&CFG, "b", None);
+        assert!(!history.needs_saving(&CFG, 2500));
+        assert!(!history.needs_saving(&CFG, 4499));
+        assert!(history.needs_saving(&CFG, 4500));
+
+        history.save(&CFG);
+        assert!(!history.needs_saving(&CFG, 9000));
+
+        // Across GetTickCount wrapping around
+        history.select_newer();
+        history.toggle_pin();
+        assert!(!history.needs_saving(&CFG, u32::max_value() - 999));
+        assert!(history.needs_saving(&CFG, 1000));
+    }
+
+    #[test]
+    fn load_skips_malformed_lines() {
+        let mut history = ClipboardHistory::new();
+        history.load("P pinned\nnonsense\n- unpinned\n");
+        assert_eq!(texts(&history), vec!["pinned", "unpinned"]);
+        assert!(history.entries[0].pinned);
+    }
+}
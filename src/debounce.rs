@@ -0,0 +1,236 @@
+// Chatter filter for worn switches, ahead of all the remapping.
+//
+// A worn switch bouncing on release shows up as the key going up and straight back down.
+// A press coming within the debounce window of the same key's release gets dropped, along
+// with its auto-repeat and release, and counted, to tell which keys need replacing. The counts
+// are kept across runs.
+
+use std::collections::{HashMap, HashSet};
+
+pub struct DebounceConfig {
+    pub enabled: bool,
+    // Shortest time between releasing a key and pressing it again, in milliseconds
+    pub window_ms: u32,
+    // Per-key overrides, by the physical virtual key
+    pub keys: &'static [(char, u32)],
+}
+
+pub const DEBOUNCE_CONFIG: DebounceConfig = DebounceConfig {
+    enabled: false,
+    window_ms: 30,
+    keys: &[],
+};
+
+fn window_for(cfg: &DebounceConfig, vk: u8) -> u32 {
+    cfg.keys
+        .iter()
+        .find(|&&(key, _)| key as u32 == vk as u32)
+        .map_or(cfg.window_ms, |&(_, window)| window)
+}
+
+// Letters and digits as they are, anything else by its virtual key code
+fn key_name(vk: u8) -> String {
+    match vk {
+        b'A'..=b'Z' | b'0'..=b'9' => (vk as char).to_string(),
+        _ => format!("0x{:02X}", vk),
+    }
+}
+
+pub struct Debounce {
+    // Times of the last releases
+    released_at: HashMap<u8, u32>,
+    // Keys whose press got dropped, and which are still down
+    dropped: HashSet<u8>,
+    // Presses dropped so far, per key
+    stats: HashMap<u8, u32>,
+    // Presses dropped since the last save
+    unsaved: bool,
+}
+
+impl Debounce {
+    pub fn new() -> Debounce {
+        Debounce {
+            released_at: HashMap::new(),
+            dropped: HashSet::new(),
+            stats: HashMap::new(),
+            unsaved: false,
+        }
+    }
+
+    // Returns true if the event is chatter, and must be dropped
+    pub fn key(&mut self, cfg: &DebounceConfig, vk: u8, key_pressed: bool, time: u32) -> bool {
+        if !cfg.enabled {
+            return false;
+        }
+
+        if !key_pressed {
+            if self.dropped.remove(&vk) {
+                return true;
+            }
+
+            self.released_at.insert(vk, time);
+            return false;
+        }
+
+        // Auto-repeat
+        if self.dropped.contains(&vk) {
+            return true;
+        }
+
+        match self.released_at.remove(&vk) {
+            Some(released) if time.wrapping_sub(released) < window_for(cfg, vk) => {
+                self.dropped.insert(vk);
+                *self.stats.entry(vk).or_insert(0) += 1;
+                self.unsaved = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Keys with the most presses dropped first
+    pub fn describe(&self) -> String {
+        if self.stats.is_empty() {
+            return "No key chatter".to_owned();
+        }
+
+        let mut stats: Vec<(u8, u32)> = self.stats.iter().map(|(&vk, &n)| (vk, n)).collect();
+        stats.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let keys: Vec<String> = stats
+            .iter()
+            .map(|&(vk, n)| format!("{} {}", key_name(vk), n))
+            .collect();
+
+        format!("Key chatter dropped\n{}", keys.join(", "))
+    }
+    // Chatter is rare enough for every dropped press to get saved
+    pub fn needs_saving(&self) -> bool {
+        self.unsaved
+    }
+
+    // One count per line, sorted so the file diffs nicely
+    pub fn save(&mut self) -> String {
+        self.unsaved = false;
+
+        let mut stats: Vec<(u8, u32)> = self.stats.iter().map(|(&vk, &n)| (vk, n)).collect();
+        stats.sort();
+        stats
+            .iter()
+            .map(|&(vk, n)| format!("key {} {}\n", vk, n))
+            .collect()
+    }
+
+    // Adds what `save` returned to the counts; malformed lines are skipped
+    pub fn load(&mut self, data: &str) {
+        for line in data.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            if let ["key", vk, n] = fields[..] {
+                if let (Ok(vk), Ok(n)) = (vk.parse::<u8>(), n.parse::<u32>()) {
+                    *self.stats.entry(vk).or_insert(0) += n;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: DebounceConfig = DebounceConfig {
+        enabled: true,
+        ..DEBOUNCE_CONFIG
+    };
+
+    // (vk, pressed, time); returns which events got dropped
+    fn run(debounce: &mut Debounce, cfg: &DebounceConfig, events: &[(u8, bool, u32)]) -> Vec<bool> {
+        events
+            .iter()
+            .map(|&(vk, pressed, time)| debounce.key(cfg, vk, pressed, time))
+            .collect()
+    }
+
+    #[test]
+    fn drops_press_inside_window() {
+        let mut debounce = Debounce::new();
+        let events = [
+            (b'A', true, 1000),
+            (b'A', false, 1100),
+            (b'A', true, 1129),
+            // Its auto-repeat and release go too
+            (b'A', true, 1200),
+            (b'A', false, 1250),
+        ];
+        assert_eq!(
+            run(&mut debounce, &CFG, &events),
+            vec![false, false, true, true, true]
+        );
+    }
+
+    #[test]
+    fn keeps_press_past_window() {
+        let mut debounce = Debounce::new();
+        let events = [(b'A', false, 1100), (b'A', true, 1130), (b'A', false, 1200)];
+        assert_eq!(run(&mut debounce, &CFG, &events), vec![false; 3]);
+        assert!(!debounce.needs_saving());
+    }
+
+    #[test]
+    fn keys_are_independent() {
+        let mut debounce = Debounce::new();
+        let cfg = DebounceConfig {
+            keys: &[('B', 100)],
+            ..CFG
+        };
+        let events = [
+            (b'A', false, 1000),
+            (b'B', false, 1000),
+            (b'B', true, 1010),
+            (b'A', true, 1050),
+            (b'B', false, 1020),
+            (b'B', true, 1080),
+        ];
+        assert_eq!(
+            run(&mut debounce, &cfg, &events),
+            vec![false, false, true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn counts_dropped_presses() {
+        let mut debounce = Debounce::new();
+        assert_eq!(debounce.describe(), "No key chatter");
+
+        let events = [
+            (0x20, false, 0),
+            (0x20, true, 10),
+            (0x20, false, 20),
+            (b'E', false, 100),
+            (b'E', true, 110),
+            (b'E', false, 120),
+            (b'E', true, 130),
+            (b'E', false, 140),
+            (b'E', true, 150),
+        ];
+        run(&mut debounce, &CFG, &events);
+        assert_eq!(debounce.describe(), "Key chatter dropped\nE 2, 0x20 1");
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let mut debounce = Debounce::new();
+        run(&mut debounce, &CFG, &[(b'E', false, 0), (b'E', true, 10)]);
+        assert!(debounce.needs_saving());
+
+        let saved = debounce.save();
+        assert_eq!(saved, "key 69 1\n");
+        assert!(!debounce.needs_saving());
+
+        // Adds up, and skips malformed lines
+        let mut loaded = Debounce::new();
+        loaded.load(&saved);
+        loaded.load("key 69 2\nkey 300 1\nnonsense\n");
+        assert_eq!(loaded.describe(), "Key chatter dropped\nE 3");
+    }
+}
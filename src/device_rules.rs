@@ -0,0 +1,207 @@
+// Which keyboards get remapped, e.g. just the built-in one, with an external ergonomic
+// keyboard left to type as it is.
+//
+// Rules are checked in order, and the first one matching a keyboard decides.
+// Keyboards which don't match any rule get remapped.
+//
+// Windows only tells which keyboard a key came from through Raw Input, which arrives after the
+// keyboard hook had to decide about the key already, and only for keys the hook let through.
+// Each WM_INPUT gets matched up with the keystroke the hook saw by key and time, and keys go by
+// the keyboard which sent the latest keystroke matched: the first key pressed after switching
+// keyboards gets the other keyboard's treatment, e.g. comes out in Colemak from a keyboard which
+// isn't remapped. Keys the hook swallows can't tell, so switching to a remapped keyboard only
+// gets noticed once a key it doesn't remap, like Space or Shift, goes through.
+
+use std::collections::VecDeque;
+
+pub struct DeviceInfo {
+    // Raw Input device interface path, e.g. "\\?\HID#VID_046D&PID_C31C&MI_00#7&1b6e..."
+    pub path: String,
+}
+
+pub struct DeviceRule {
+    // Substring of the device path, ignoring case: "VID_046D&PID_C31C" for a given USB keyboard,
+    // "HID#" for any USB or Bluetooth one, or "ACPI#" for most built-in laptop keyboards
+    pub path: &'static str,
+    // Otherwise Colemak, the layers and everything else stay off for the keyboard
+    pub remap: bool,
+}
+
+// Only the built-in keyboard of a laptop gets remapped. Without any rules, all keyboards do,
+// as is best for desktops.
+pub const DEVICE_RULES: &[DeviceRule] = &[
+    DeviceRule {
+        path: "ACPI#",
+        remap: true,
+    },
+    // USB and Bluetooth keyboards
+    DeviceRule {
+        path: "HID#",
+        remap: false,
+    },
+];
+
+pub fn is_device_remapped(rules: &[DeviceRule], info: &DeviceInfo) -> bool {
+    let path = info.path.to_lowercase();
+
+    rules
+        .iter()
+        .find(|rule| path.contains(&rule.path.to_lowercase()))
+        .map_or(true, |rule| rule.remap)
+}
+
+// How far apart the hook's and Raw Input's times for a keystroke can be, in milliseconds
+const MATCH_MS: u32 = 50;
+// Keystrokes kept waiting for their Raw Input; the ones the hook swallowed never get any
+const MAX_PENDING: usize = 16;
+
+// Raw Input reports Shift, Ctrl and Alt without telling left from right
+fn same_key(hook_vk: u8, raw_vk: u8) -> bool {
+    let generic = match hook_vk {
+        0xA0 | 0xA1 => 0x10,
+        0xA2 | 0xA3 => 0x11,
+        0xA4 | 0xA5 => 0x12,
+        vk => vk,
+    };
+
+    raw_vk == hook_vk || raw_vk == generic
+}
+
+fn close_in_time(a: u32, b: u32) -> bool {
+    a.wrapping_sub(b) <= MATCH_MS || b.wrapping_sub(a) <= MATCH_MS
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Keystroke {
+    vk: u8,
+    pressed: bool,
+    time: u32,
+}
+
+// Which keyboard keys come from, by the Raw Input device handle
+pub struct KeyboardTracker {
+    // Seen by the hook, oldest first, and waiting for their Raw Input
+    pending: VecDeque<Keystroke>,
+    // Keyboard which sent the latest keystroke matched
+    latest: Option<usize>,
+}
+
+impl KeyboardTracker {
+    pub fn new() -> KeyboardTracker {
+        KeyboardTracker {
+            pending: VecDeque::new(),
+            latest: None,
+        }
+    }
+
+    // A keystroke reaching the hook. Returns the keyboard it's taken to come from,
+    // or None until the first keystroke got matched.
+    pub fn hook_key(&mut self, vk: u8, pressed: bool, time: u32) -> Option<usize> {
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(Keystroke { vk, pressed, time });
+
+        self.latest
+    }
+
+    // Raw Input for a keystroke, with the time of its WM_INPUT. Returns false if it doesn't match
+    // any the hook saw.
+    pub fn raw_key(&mut self, keyboard: usize, vk: u8, pressed: bool, time: u32) -> bool {
+        let idx = self.pending.iter().position(|key| {
+            key.pressed == pressed && same_key(key.vk, vk) && close_in_time(key.time, time)
+        });
+
+        match idx {
+            Some(idx) => {
+                // Raw Input comes in order, so the ones before won't get theirs any more
+                self.pending.drain(..=idx);
+                self.latest = Some(keyboard);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(path: &str) -> DeviceInfo {
+        DeviceInfo {
+            path: path.to_owned(),
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let built_in =
+            device(r"\\?\ACPI#PNP0303#4&1d401fb5&0#{884b96c3-56ef-11d1-bc8c-00a0c91405dd}");
+        let usb = device(r"\\?\HID#VID_046D&PID_C31C&MI_00#7&1b6e0a4&0&0000#{884b96c3}");
+        assert!(is_device_remapped(DEVICE_RULES, &built_in));
+        assert!(!is_device_remapped(DEVICE_RULES, &usb));
+
+        let rules = [
+            DeviceRule {
+                path: "vid_046d&pid_c31c",
+                remap: true,
+            },
+            DeviceRule {
+                path: "HID#",
+                remap: false,
+            },
+        ];
+        assert!(is_device_remapped(&rules, &usb));
+        // Matching nothing
+        assert!(is_device_remapped(&rules, &built_in));
+        assert!(is_device_remapped(&[], &usb));
+    }
+
+    #[test]
+    fn matches_raw_input_to_keystrokes() {
+        let mut tracker = KeyboardTracker::new();
+        assert_eq!(tracker.hook_key(b'A', true, 1000), None);
+        // A different key, or too far apart
+        assert!(!tracker.raw_key(1, b'B', true, 1000));
+        assert!(!tracker.raw_key(1, b'A', true, 1100));
+        assert!(!tracker.raw_key(1, b'A', false, 1000));
+        assert!(tracker.raw_key(1, b'A', true, 1010));
+        // Matched once only
+        assert!(!tracker.raw_key(2, b'A', true, 1010));
+
+        assert_eq!(tracker.hook_key(b'A', false, 1080), Some(1));
+        // Left Shift comes in as Shift
+        assert_eq!(tracker.hook_key(0xA0, true, 1100), Some(1));
+        assert!(tracker.raw_key(2, 0x10, true, 1100));
+        assert_eq!(tracker.hook_key(b'B', true, 1150), Some(2));
+    }
+
+    #[test]
+    fn late_raw_input() {
+        let mut tracker = KeyboardTracker::new();
+        tracker.hook_key(b'A', true, 1000);
+        tracker.raw_key(1, b'A', true, 1000);
+
+        // Typing fast on another keyboard, the next hook call can come before the last WM_INPUT
+        tracker.hook_key(b'B', true, 2000);
+        assert_eq!(tracker.hook_key(b'C', true, 2010), Some(1));
+        assert!(tracker.raw_key(2, b'B', true, 2000));
+        assert!(tracker.raw_key(2, b'C', true, 2010));
+        assert_eq!(tracker.hook_key(b'D', true, 2020), Some(2));
+    }
+
+    #[test]
+    fn swallowed_keystrokes_get_dropped() {
+        let mut tracker = KeyboardTracker::new();
+        for time in 0..20 {
+            tracker.hook_key(b'A', true, time * 100);
+        }
+        assert_eq!(tracker.pending.len(), MAX_PENDING);
+
+        // Those before a match never get any Raw Input
+        assert!(tracker.raw_key(1, b'A', true, 1500));
+        assert_eq!(tracker.pending.len(), 4);
+        assert!(!tracker.raw_key(1, b'A', true, 1400));
+    }
+}
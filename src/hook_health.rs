@@ -0,0 +1,171 @@
+// Noticing when Windows drops the keyboard or mouse hook.
+//
+// Nothing tells a program its low-level hook got removed, after a timeout or otherwise; the keys
+// just stop coming. So every few seconds the input the system saw (GetLastInputInfo) gets
+// compared with what the hooks saw. Input the hooks missed makes for a suspicion, and then
+// a probe event gets sent: whichever hook doesn't see its probe by the next check gets reinstalled.
+
+pub struct HookHealthConfig {
+    // How often to check, in milliseconds
+    pub check_interval_ms: u32,
+    // Input this much newer than the last the hooks saw is suspicious, in milliseconds
+    pub grace_ms: u32,
+}
+
+pub const HOOK_HEALTH_CONFIG: HookHealthConfig = HookHealthConfig {
+    check_interval_ms: 2000,
+    grace_ms: 500,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HookCheck {
+    Fine,
+    // Send a probe event through each hook
+    SendProbes,
+    // These hooks missed their probe
+    Reinstall { keyboard: bool, mouse: bool },
+}
+
+pub struct HookHealth {
+    // Tick count of the last event either hook saw
+    last_event_at: u32,
+    probes_sent: bool,
+    key_probe_seen: bool,
+    mouse_probe_seen: bool,
+}
+
+impl HookHealth {
+    pub fn new(now: u32) -> HookHealth {
+        HookHealth {
+            last_event_at: now,
+            probes_sent: false,
+            key_probe_seen: false,
+            mouse_probe_seen: false,
+        }
+    }
+
+    // Any event reaching a hook, probes included
+    pub fn event(&mut self, now: u32) {
+        self.last_event_at = now;
+    }
+
+    pub fn key_probe_seen(&mut self, now: u32) {
+        self.key_probe_seen = true;
+        self.event(now);
+    }
+
+    pub fn mouse_probe_seen(&mut self, now: u32) {
+        self.mouse_probe_seen = true;
+        self.event(now);
+    }
+
+    // The probes couldn't be sent, or wouldn't reach the hooks: with the secure desktop up or an
+    // elevated window in front, when the hooks don't see any input anyway
+    pub fn probes_failed(&mut self, now: u32) {
+        self.probes_sent = false;
+        self.event(now);
+    }
+
+    // The hooks were just installed, so they're fine until proven otherwise
+    pub fn reinstalled(&mut self, now: u32) {
+        self.probes_sent = false;
+        self.event(now);
+    }
+
+    // `last_input` is the tick count of the last input the system saw
+    pub fn check(&mut self, cfg: &HookHealthConfig, last_input: u32) -> HookCheck {
+        if self.probes_sent {
+            self.probes_sent = false;
+
+            let keyboard = !self.key_probe_seen;
+            let mouse = !self.mouse_probe_seen;
+            return if keyboard || mouse {
+                HookCheck::Reinstall { keyboard, mouse }
+            } else {
+                HookCheck::Fine
+            };
+        }
+
+        // Tick counts wrap around every 49 days
+        let missed = last_input.wrapping_sub(self.last_event_at) as i32;
+        if missed > cfg.grace_ms as i32 {
+            self.probes_sent = true;
+            self.key_probe_seen = false;
+            self.mouse_probe_seen = false;
+            return HookCheck::SendProbes;
+        }
+
+        HookCheck::Fine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: HookHealthConfig = HOOK_HEALTH_CONFIG;
+
+    #[test]
+    fn input_the_hooks_saw_is_fine() {
+        let mut health = HookHealth::new(1000);
+        assert_eq!(health.check(&CFG, 1500), HookCheck::Fine);
+        // Older than the last event, e.g. input on the secure desktop earlier
+        assert_eq!(health.check(&CFG, 500), HookCheck::Fine);
+    }
+
+    #[test]
+    fn suspicion_sends_probes() {
+        let mut health = HookHealth::new(1000);
+        assert_eq!(health.check(&CFG, 1501), HookCheck::SendProbes);
+        health.key_probe_seen(1510);
+        health.mouse_probe_seen(1520);
+        assert_eq!(health.check(&CFG, 1520), HookCheck::Fine);
+    }
+
+    #[test]
+    fn missed_probe_reinstalls() {
+        let mut health = HookHealth::new(1000);
+        assert_eq!(health.check(&CFG, 2000), HookCheck::SendProbes);
+        health.mouse_probe_seen(2010);
+        assert_eq!(
+            health.check(&CFG, 2010),
+            HookCheck::Reinstall {
+                keyboard: true,
+                mouse: false,
+            }
+        );
+
+        // Probes seen last time don't count for the next round
+        health.reinstalled(3000);
+        assert_eq!(health.check(&CFG, 4000), HookCheck::SendProbes);
+        assert_eq!(
+            health.check(&CFG, 4000),
+            HookCheck::Reinstall {
+                keyboard: true,
+                mouse: true,
+            }
+        );
+    }
+
+    #[test]
+    fn probes_failed_and_reinstalled_reset() {
+        let mut health = HookHealth::new(1000);
+        assert_eq!(health.check(&CFG, 2000), HookCheck::SendProbes);
+        health.probes_failed(2000);
+        assert_eq!(health.check(&CFG, 2000), HookCheck::Fine);
+
+        assert_eq!(health.check(&CFG, 3000), HookCheck::SendProbes);
+        health.reinstalled(3000);
+        assert_eq!(health.check(&CFG, 3000), HookCheck::Fine);
+    }
+
+    #[test]
+    fn tick_count_wraps_around() {
+        let mut health = HookHealth::new(u32::max_value() - 100);
+        assert_eq!(health.check(&CFG, 300), HookCheck::Fine);
+        assert_eq!(health.check(&CFG, 500), HookCheck::SendProbes);
+
+        let mut health = HookHealth::new(100);
+        assert_eq!(health.check(&CFG, u32::max_value() - 100), HookCheck::Fine);
+    }
+}
@@ -0,0 +1,94 @@
+// Commands for the running instance, e.g. `h3keys3 status` from a script or a terminal.
+//
+// Starting h3keys3 with arguments doesn't start another instance, but sends them to the running
+// one as a WM_COPYDATA message, and prints its reply.
+
+use logging::{self, Level, LEVELS};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IpcCommand {
+    // Whether input is suspended
+    Status,
+    Suspend,
+    Resume,
+    // Writes the latency report, and replies with a summary
+    Latency,
+    // Replies with the log level, after changing it if one is given
+    LogLevel(Option<Level>),
+}
+
+const COMMANDS: &[(IpcCommand, &str)] = &[
+    (IpcCommand::Status, "status"),
+    (IpcCommand::Suspend, "suspend"),
+    (IpcCommand::Resume, "resume"),
+    (IpcCommand::Latency, "latency"),
+    (IpcCommand::LogLevel(None), "log-level"),
+];
+
+// Words are separated by whitespace, and case doesn't matter
+pub fn parse(text: &str) -> Option<IpcCommand> {
+    let words: Vec<String> = text.split_whitespace().map(|w| w.to_lowercase()).collect();
+    let (name, args) = words.split_first()?;
+    let command = COMMANDS
+        .iter()
+        .find(|&&(_, n)| n == name)
+        .map(|&(command, _)| command)?;
+
+    match (command, args) {
+        (_, []) => Some(command),
+        (IpcCommand::LogLevel(_), [level]) => {
+            logging::parse_level(level).map(|level| IpcCommand::LogLevel(Some(level)))
+        }
+        _ => None,
+    }
+}
+
+pub fn usage() -> String {
+    let names: Vec<String> = COMMANDS
+        .iter()
+        .map(|&(command, name)| match command {
+            IpcCommand::LogLevel(_) => {
+                let levels: Vec<&str> = LEVELS.iter().map(|&l| logging::level_name(l)).collect();
+                format!("{} [{}]", name, levels.join("|"))
+            }
+            _ => name.to_owned(),
+        })
+        .collect();
+    format!("Usage: h3keys3 [{}]", names.join(" | "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse("status"), Some(IpcCommand::Status));
+        assert_eq!(parse(" Suspend\n"), Some(IpcCommand::Suspend));
+        assert_eq!(parse("RESUME"), Some(IpcCommand::Resume));
+        assert_eq!(parse("latency"), Some(IpcCommand::Latency));
+        assert_eq!(parse("log-level"), Some(IpcCommand::LogLevel(None)));
+        assert_eq!(
+            parse("log-level Debug"),
+            Some(IpcCommand::LogLevel(Some(Level::Debug)))
+        );
+    }
+
+    #[test]
+    fn rejects_the_rest() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("stat"), None);
+        assert_eq!(parse("status now"), None);
+        assert_eq!(parse("log-level loud"), None);
+        assert_eq!(parse("log-level info debug"), None);
+    }
+
+    #[test]
+    fn usage_lists_commands() {
+        assert_eq!(
+            usage(),
+            "Usage: h3keys3 [status | suspend | resume | latency | \
+             log-level [off|error|warn|info|debug]]"
+        );
+    }
+}
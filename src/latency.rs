@@ -0,0 +1,324 @@
+// Time spent on the hook path: in the keyboard and mouse hooks, and in SendInput.
+//
+// Windows silently removes a low-level hook which doesn't return within LowLevelHooksTimeout,
+// after which keys go through unmapped. Times go into histograms, the slowest events get kept
+// with what they were, and a warning comes up when an event takes a good share of the timeout.
+
+use std::collections::VecDeque;
+
+pub struct LatencyConfig {
+    pub enabled: bool,
+    // Events taking at least this long get kept for the report, in microseconds
+    pub slow_us: u32,
+    // Warn when a hook takes this share of the hook timeout, in percent
+    pub warn_percent: u32,
+    // Most recent slow events kept
+    pub max_slow_events: usize,
+}
+
+pub const LATENCY_CONFIG: LatencyConfig = LatencyConfig {
+    enabled: false,
+    slow_us: 10_000,
+    warn_percent: 50,
+    max_slow_events: 50,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Probe {
+    KeyHook,
+    MouseHook,
+    SendInput,
+}
+
+const PROBES: [Probe; 3] = [Probe::KeyHook, Probe::MouseHook, Probe::SendInput];
+
+fn probe_name(probe: Probe) -> &'static str {
+    match probe {
+        Probe::KeyHook => "Key hook",
+        Probe::MouseHook => "Mouse hook",
+        Probe::SendInput => "SendInput",
+    }
+}
+
+// Upper bounds of the histogram buckets, in microseconds; one more bucket takes the rest
+const BUCKETS: [u32; 9] = [50, 100, 250, 500, 1000, 2500, 5000, 10_000, 50_000];
+
+fn describe_us(us: u32) -> String {
+    if us < 1000 {
+        format!("{} \u{b5}s", us)
+    } else {
+        format!("{:.1} ms", us as f32 / 1000.0)
+    }
+}
+
+struct Histogram {
+    counts: [u32; BUCKETS.len() + 1],
+    total_us: u64,
+    max_us: u32,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            counts: [0; BUCKETS.len() + 1],
+            total_us: 0,
+            max_us: 0,
+        }
+    }
+
+    fn add(&mut self, us: u32) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|&bound| us < bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.total_us += us as u64;
+        self.max_us = self.max_us.max(us);
+    }
+
+    fn count(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+
+    // Upper bound of the bucket the given share of events falls in, or None for the last one
+    fn percentile(&self, percent: u32) -> Option<u32> {
+        let wanted = (self.count() as u64 * percent as u64 + 99) / 100;
+        let mut seen = 0;
+        for (i, &n) in self.counts.iter().enumerate() {
+            seen += n as u64;
+            if seen >= wanted {
+                return BUCKETS.get(i).cloned();
+            }
+        }
+        None
+    }
+
+    fn describe_percentile(&self, percent: u32) -> String {
+        match self.percentile(percent) {
+            Some(bound) => format!("<{}", describe_us(bound)),
+            None => format!(">={}", describe_us(BUCKETS[BUCKETS.len() - 1])),
+        }
+    }
+
+    // e.g. "1234 events, median <250 µs, 99% <1.0 ms, max 3.2 ms"
+    fn describe(&self) -> String {
+        let count = self.count();
+        if 0 == count {
+            return "no events".to_owned();
+        }
+
+        format!(
+            "{} events, median {}, 99% {}, max {}",
+            count,
+            self.describe_percentile(50),
+            self.describe_percentile(99),
+            describe_us(self.max_us)
+        )
+    }
+}
+
+struct SlowEvent {
+    probe: Probe,
+    us: u32,
+    what: String,
+}
+
+pub struct Latency {
+    // LowLevelHooksTimeout, in milliseconds
+    timeout_ms: u32,
+    histograms: [Histogram; 3],
+    slow_events: VecDeque<SlowEvent>,
+    // Warned about getting close to the timeout already; once a run is enough
+    warned: bool,
+}
+
+impl Latency {
+    pub fn new(timeout_ms: u32) -> Latency {
+        Latency {
+            timeout_ms,
+            histograms: [Histogram::new(), Histogram::new(), Histogram::new()],
+            slow_events: VecDeque::new(),
+            warned: false,
+        }
+    }
+
+    // `what` describes the event, and only gets called for slow ones.
+    // Returns a warning to show if a hook got close to the timeout.
+    pub fn record<F>(
+        &mut self,
+        cfg: &LatencyConfig,
+        probe: Probe,
+        us: u32,
+        what: F,
+    ) -> Option<String>
+    where
+        F: FnOnce() -> String,
+    {
+        if !cfg.enabled {
+            return None;
+        }
+
+        let idx = PROBES.iter().position(|&p| p == probe).unwrap_or(0);
+        self.histograms[idx].add(us);
+
+        if us < cfg.slow_us {
+            return None;
+        }
+
+        if self.slow_events.len() >= cfg.max_slow_events {
+            self.slow_events.pop_front();
+        }
+        self.slow_events.push_back(SlowEvent {
+            probe,
+            us,
+            what: what(),
+        });
+
+        let warn_us = self.timeout_ms as u64 * 10 * cfg.warn_percent as u64;
+        if Probe::SendInput == probe || self.warned || (us as u64) < warn_us {
+            return None;
+        }
+
+        self.warned = true;
+        Some(format!(
+            "{} took {} of the {} ms hook timeout",
+            probe_name(probe),
+            describe_us(us),
+            self.timeout_ms
+        ))
+    }
+
+    // Short summary, for a toast
+    pub fn describe(&self) -> String {
+        let lines: Vec<String> = PROBES
+            .iter()
+            .zip(self.histograms.iter())
+            .map(|(&probe, histogram)| format!("{}: {}", probe_name(probe), histogram.describe()))
+            .collect();
+
+        format!(
+            "{}\n{} slow events, hook timeout {} ms",
+            lines.join("\n"),
+            self.slow_events.len(),
+            self.timeout_ms
+        )
+    }
+
+    // Full histograms and the slow events, for a file
+    pub fn report(&self) -> String {
+        let mut res = format!("Hook timeout: {} ms\n", self.timeout_ms);
+
+        for (&probe, histogram) in PROBES.iter().zip(self.histograms.iter()) {
+            res += &format!("\n{}: {}\n", probe_name(probe), histogram.describe());
+
+            let count = histogram.count();
+            if 0 == count {
+                continue;
+            }
+
+            res += &format!(
+                "  average {}\n",
+                describe_us((histogram.total_us / count as u64) as u32)
+            );
+            for (i, &n) in histogram.counts.iter().enumerate() {
+                let bucket = match BUCKETS.get(i) {
+                    Some(&bound) => format!("<{}", describe_us(bound)),
+                    None => format!(">={}", describe_us(BUCKETS[BUCKETS.len() - 1])),
+                };
+                res += &format!("  {:>10} {}\n", bucket, n);
+            }
+        }
+
+        res += "\nSlow events\n";
+        for event in self.slow_events.iter() {
+            res += &format!(
+                "  {} {}: {}\n",
+                probe_name(event.probe),
+                describe_us(event.us),
+                event.what
+            );
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(times: &[u32]) -> Histogram {
+        let mut histogram = Histogram::new();
+        for &us in times {
+            histogram.add(us);
+        }
+        histogram
+    }
+
+    #[test]
+    fn bucket_bounds_are_exclusive() {
+        let histogram = histogram(&[0, 49, 50, 99, 100, 49_999, 50_000]);
+        assert_eq!(&histogram.counts[..3], &[2, 2, 1]);
+        assert_eq!(histogram.counts[BUCKETS.len() - 1], 1);
+        assert_eq!(histogram.counts[BUCKETS.len()], 1);
+        assert_eq!(histogram.max_us, 50_000);
+    }
+
+    #[test]
+    fn empty_histogram() {
+        assert_eq!(Histogram::new().describe(), "no events");
+
+        let latency = Latency::new(300);
+        assert_eq!(
+            latency.describe(),
+            "Key hook: no events\nMouse hook: no events\nSendInput: no events\n\
+             0 slow events, hook timeout 300 ms"
+        );
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut times = vec![10; 98];
+        times.extend_from_slice(&[3000, 3000]);
+        let histogram = histogram(&times);
+        assert_eq!(histogram.percentile(50), Some(50));
+        // The 99th event is the first slow one
+        assert_eq!(histogram.percentile(99), Some(5000));
+        assert_eq!(histogram.percentile(100), Some(5000));
+        assert_eq!(
+            histogram.describe(),
+            "100 events, median <50 \u{b5}s, 99% <5.0 ms, max 3.0 ms"
+        );
+    }
+
+    #[test]
+    fn overflow_bucket() {
+        let histogram = histogram(&[10, 60_000, 70_000]);
+        assert_eq!(histogram.percentile(33), Some(50));
+        assert_eq!(histogram.percentile(50), None);
+        assert_eq!(
+            histogram.describe(),
+            "3 events, median >=50.0 ms, 99% >=50.0 ms, max 70.0 ms"
+        );
+    }
+
+    #[test]
+    fn warns_once_near_the_timeout() {
+        let cfg = LatencyConfig {
+            enabled: true,
+            ..LATENCY_CONFIG
+        };
+        let mut latency = Latency::new(200);
+        let what = || "A".to_owned();
+
+        assert_eq!(latency.record(&cfg, Probe::KeyHook, 20_000, what), None);
+        // Slow, but SendInput isn't subject to the timeout
+        assert_eq!(latency.record(&cfg, Probe::SendInput, 150_000, what), None);
+        assert_eq!(
+            latency.record(&cfg, Probe::MouseHook, 100_000, what),
+            Some("Mouse hook took 100.0 ms of the 200 ms hook timeout".to_owned())
+        );
+        assert_eq!(latency.record(&cfg, Probe::KeyHook, 150_000, what), None);
+        assert_eq!(latency.slow_events.len(), 4);
+    }
+}
@@ -0,0 +1,169 @@
+// AltGr run-or-raise bindings.
+//
+// AltGr plus a configured key focuses the application's window if it's running, and launches
+// it otherwise. Pressing it again while the app is focused cycles through its other windows.
+
+use window_switcher::SwitcherEntry;
+
+pub struct LaunchTarget {
+    // Pressed together with AltGr; the keys AltGr already uses (U, 4, M) take precedence
+    pub key: char,
+    // Executable whose windows get raised, e.g. "firefox.exe"
+    pub process_name: &'static str,
+    // Launched when none of the app's windows are open; a full path,
+    // or anything the Run dialog would understand
+    pub command: &'static str,
+    pub args: &'static str,
+    // None to inherit h3keys3's own working directory
+    pub working_dir: Option<&'static str>,
+}
+
+pub const LAUNCH_TARGETS: &[LaunchTarget] = &[
+    LaunchTarget {
+        key: 'E',
+        process_name: "explorer.exe",
+        command: "explorer.exe",
+        args: "",
+        working_dir: None,
+    },
+    LaunchTarget {
+        key: 'T',
+        process_name: "WindowsTerminal.exe",
+        command: "wt.exe",
+        args: "",
+        working_dir: None,
+    },
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RunOrRaise<W> {
+    Launch,
+    Raise(W),
+}
+
+pub fn target_for_key(
+    targets: &'static [LaunchTarget],
+    key: char,
+) -> Option<&'static LaunchTarget> {
+    targets.iter().find(|target| target.key == key)
+}
+
+// `windows` must be in most recently used order, like the window switcher lists them
+pub fn run_or_raise<W: Copy + PartialEq>(
+    target: &LaunchTarget,
+    windows: &[SwitcherEntry<W>],
+    foreground: Option<W>,
+) -> RunOrRaise<W> {
+    let app_windows: Vec<W> = windows
+        .iter()
+        .filter(|entry| entry.process_name.eq_ignore_ascii_case(target.process_name))
+        .map(|entry| entry.window)
+        .collect();
+
+    let in_app = foreground.map_or(false, |w| app_windows.contains(&w));
+
+    // Raising a window moves it to the front, so going for the least recently used one
+    // each time walks through all of the app's windows in turn
+    let window = if in_app {
+        app_windows.last()
+    } else {
+        app_windows.first()
+    };
+
+    match window {
+        Some(&w) => RunOrRaise::Raise(w),
+        None => RunOrRaise::Launch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGETS: &[LaunchTarget] = &[
+        LaunchTarget {
+            key: 'F',
+            process_name: "firefox.exe",
+            command: "firefox.exe",
+            args: "",
+            working_dir: None,
+        },
+        LaunchTarget {
+            key: 'N',
+            process_name: "notepad.exe",
+            command: "notepad.exe",
+            args: "",
+            working_dir: None,
+        },
+    ];
+
+    fn entry(window: u32, process_name: &str) -> SwitcherEntry<u32> {
+        SwitcherEntry {
+            window,
+            title: String::new(),
+            process_name: process_name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn finds_target_by_key() {
+        assert_eq!(
+            target_for_key(TARGETS, 'N').map(|t| t.command),
+            Some("notepad.exe")
+        );
+        assert!(target_for_key(TARGETS, 'X').is_none());
+    }
+
+    #[test]
+    fn launches_when_not_running() {
+        let windows = vec![entry(1, "notepad.exe")];
+        assert_eq!(
+            run_or_raise(&TARGETS[0], &windows, Some(1)),
+            RunOrRaise::Launch
+        );
+    }
+
+    #[test]
+    fn raises_most_recent_window() {
+        // Process names match regardless of case
+        let windows = vec![
+            entry(1, "notepad.exe"),
+            entry(2, "Firefox.exe"),
+            entry(3, "firefox.exe"),
+        ];
+        assert_eq!(
+            run_or_raise(&TARGETS[0], &windows, Some(1)),
+            RunOrRaise::Raise(2)
+        );
+        assert_eq!(
+            run_or_raise(&TARGETS[0], &windows, None),
+            RunOrRaise::Raise(2)
+        );
+    }
+
+    #[test]
+    fn cycles_through_app_windows() {
+        let mut windows = vec![
+            entry(1, "firefox.exe"),
+            entry(2, "notepad.exe"),
+            entry(3, "firefox.exe"),
+            entry(4, "firefox.exe"),
+        ];
+
+        // Raising a window brings it to the front of the list, as Windows would
+        let mut raised = Vec::new();
+        for _ in 0..4 {
+            let foreground = Some(windows[0].window);
+            match run_or_raise(&TARGETS[0], &windows, foreground) {
+                RunOrRaise::Raise(w) => {
+                    let idx = windows.iter().position(|e| e.window == w).unwrap();
+                    let entry = windows.remove(idx);
+                    windows.insert(0, entry);
+                    raised.push(w);
+                }
+                RunOrRaise::Launch => panic!("launched"),
+            }
+        }
+        assert_eq!(raised, vec![4, 3, 1, 4]);
+    }
+}
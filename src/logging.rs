@@ -0,0 +1,167 @@
+// Log file, for when something misbehaves: the app has no console to print to.
+//
+// Lines go to %APPDATA%\h3keys3\h3keys3.log, which gets renamed to h3keys3.1.log and so on when
+// it grows too big. Each line has a level and what it's about. With redaction on, which keys
+// got pressed is left out, so the log can't be read back as text.
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    // Every key and what it got remapped to. The lines get written out on a thread of their
+    // own, but are still put together inside the keyboard hook, adding to its time.
+    Debug,
+}
+
+// In order, for cycling through on the admin layer
+pub const LEVELS: [Level; 5] = [
+    Level::Off,
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Category {
+    // Installing and checking the input hooks, and their timing
+    Hooks,
+    // What keys got remapped to, and mode changes
+    Engine,
+    // Moving and resizing windows
+    Window,
+    Notifications,
+}
+
+pub struct LogConfig {
+    // Level at startup; Caps+Esc+V or `h3keys3 log-level <level>` changes it
+    pub level: Level,
+    // Log "key" instead of which key
+    pub redact_keys: bool,
+    // The log gets rotated when it would grow past this
+    pub max_file_bytes: u64,
+    // Rotated logs kept
+    pub max_old_files: u32,
+}
+
+pub const LOG_CONFIG: LogConfig = LogConfig {
+    level: Level::Warn,
+    redact_keys: true,
+    max_file_bytes: 1_000_000,
+    max_old_files: 3,
+};
+
+pub fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Off => "off",
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+    }
+}
+
+fn category_name(category: Category) -> &'static str {
+    match category {
+        Category::Hooks => "hooks",
+        Category::Engine => "engine",
+        Category::Window => "window",
+        Category::Notifications => "notify",
+    }
+}
+
+// The other way round from level_name
+pub fn parse_level(name: &str) -> Option<Level> {
+    LEVELS
+        .iter()
+        .cloned()
+        .find(|&level| level_name(level) == name)
+}
+
+// Wraps around from Debug back to Off
+pub fn next_level(level: Level) -> Level {
+    let idx = LEVELS.iter().position(|&l| l == level).unwrap_or(0);
+    LEVELS[(idx + 1) % LEVELS.len()]
+}
+
+// e.g. "2018-03-14 15:09:26.535 WARN  hooks  Keyboard hook lost"
+pub fn format_line(date_time: &str, level: Level, category: Category, message: &str) -> String {
+    // Multi-line messages, like toasts, stay on one line
+    let message = message.replace('\n', " / ");
+
+    format!(
+        "{} {:<5} {:<6} {}\r\n",
+        date_time,
+        level_name(level).to_uppercase(),
+        category_name(category),
+        message
+    )
+}
+
+// Virtual key, as shown in the log
+pub fn key_name(cfg: &LogConfig, vk: u8) -> String {
+    if cfg.redact_keys {
+        return "key".to_owned();
+    }
+
+    match vk {
+        b'A'..=b'Z' | b'0'..=b'9' => format!("key {}", vk as char),
+        _ => format!("key 0x{:02X}", vk),
+    }
+}
+
+// h3keys3.log for the current log, h3keys3.1.log for the one before, and so on
+pub fn file_name(age: u32) -> String {
+    if 0 == age {
+        "h3keys3.log".to_owned()
+    } else {
+        format!("h3keys3.{}.log", age)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name(0), "h3keys3.log");
+        assert_eq!(file_name(1), "h3keys3.1.log");
+        assert_eq!(file_name(3), "h3keys3.3.log");
+    }
+
+    #[test]
+    fn redacts_keys() {
+        assert_eq!(key_name(&LOG_CONFIG, b'A'), "key");
+        assert_eq!(key_name(&LOG_CONFIG, 0x0D), "key");
+
+        let cfg = LogConfig {
+            redact_keys: false,
+            ..LOG_CONFIG
+        };
+        assert_eq!(key_name(&cfg, b'A'), "key A");
+        assert_eq!(key_name(&cfg, b'7'), "key 7");
+        assert_eq!(key_name(&cfg, 0x0D), "key 0x0D");
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(next_level(Level::Info), Level::Debug);
+        assert_eq!(next_level(Level::Debug), Level::Off);
+        assert_eq!(parse_level("warn"), Some(Level::Warn));
+        assert_eq!(parse_level("verbose"), None);
+    }
+
+    #[test]
+    fn formats_lines() {
+        let line = format_line(
+            "2018-03-14 15:09:26.535",
+            Level::Warn,
+            Category::Hooks,
+            "a\nb",
+        );
+        assert_eq!(line, "2018-03-14 15:09:26.535 WARN  hooks  a / b\r\n");
+    }
+}
@@ -1,22 +1,84 @@
 #![windows_subsystem = "windows"]
 extern crate kernel32;
 extern crate user32;
+#[macro_use]
 extern crate winapi;
 extern crate winrt;
+
+mod auto_shift;
+mod caps_lock;
+mod clipboard_history;
+mod debounce;
+mod device_rules;
+mod hook_health;
+mod ipc;
+mod latency;
+mod launcher;
+mod logging;
+mod media_keys;
+mod mouse_keys;
+mod scroll_emu;
+mod suspend;
+mod tap_dance;
+mod text_expander;
+mod typing_stats;
+mod vim_nav;
+mod window_geometry;
+mod window_manager;
+mod window_rules;
+mod window_switcher;
+
 use kernel32::GetModuleHandleA;
+use winapi::shared::guiddef::LPCGUID;
 use winapi::shared::minwindef::*;
 use winapi::shared::ntdef::LPCSTR;
-use winapi::shared::windef::{HBRUSH, HCURSOR, HICON, HMENU, HWND, POINT, RECT};
-use winapi::um::winuser;
+use winapi::shared::windef::{
+    HBRUSH, HCURSOR, HDC, HHOOK, HICON, HMENU, HMONITOR, HWND, LPRECT, POINT, RECT,
+};
+use winapi::shared::winerror::HRESULT;
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use winapi::um::{
+    combaseapi, dwmapi, handleapi, minwinbase, mmdeviceapi, processthreadsapi, shellapi,
+    shellscalingapi, sysinfoapi, winbase, wincon, winnt, winreg, winuser,
+};
+use winapi::Interface;
 
 use winrt::windows::data::xml::dom::*;
 use winrt::windows::ui::notifications::*;
 use winrt::*;
 
-use std::cell::RefCell;
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
-use std::{f32, mem, ptr, str, thread, time};
+use auto_shift::{AutoShiftLayer, AutoShiftState, AutoShiftTap, AUTO_SHIFT_CONFIG};
+use caps_lock::{CapsWord, CapsWordAction, CAPS_LOCK_CONFIG};
+use clipboard_history::{ClipboardBackend, ClipboardHistory, CLIPBOARD_HISTORY_CONFIG};
+use debounce::{Debounce, DEBOUNCE_CONFIG};
+use device_rules::{DeviceInfo, KeyboardTracker, DEVICE_RULES};
+use hook_health::{HookCheck, HookHealth, HOOK_HEALTH_CONFIG};
+use ipc::IpcCommand;
+use latency::{Latency, Probe, LATENCY_CONFIG};
+use launcher::{LaunchTarget, RunOrRaise, LAUNCH_TARGETS};
+use logging::{Category, Level, LEVELS, LOG_CONFIG};
+use media_keys::{MediaKey, MEDIA_CONFIG};
+use mouse_keys::{MouseButton, MouseKeysMotion, MouseKeysState, MOUSE_KEYS_CONFIG};
+use scroll_emu::{ScrollEmuState, SCROLL_EMU_CONFIG};
+use suspend::{ChordKey, SuspendChord, SUSPEND_CONFIG};
+use tap_dance::{TapDance, TapDanceState, TAP_DANCE_CONFIG};
+use text_expander::{Placeholders, TextExpander, CLIPBOARD_PLACEHOLDER, SNIPPETS};
+use typing_stats::{StatsLayer, StatsLayout, TypingStats, TYPING_STATS_CONFIG};
+use vim_nav::{NavStroke, VimMode, VimNav};
+use window_geometry::{FrameMargins, Monitor, Rect, ResizeEdges, Tile, SNAP_CONFIG};
+use window_manager::{WindowCommand, WindowManager, NUDGE_STEP};
+use window_rules::{WindowInfo, WINDOW_RULES};
+use window_switcher::{SwitcherEntry, WindowSwitcher, SWITCHER_CONFIG};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::{cmp, f32, mem, ptr, slice, thread, time};
 
 const ESCAPE: char = winuser::VK_ESCAPE as u8 as char;
 const SEMICOLON: char = winuser::VK_OEM_1 as u8 as char;
@@ -29,6 +91,10 @@ const CTRL: char = winuser::VK_CONTROL as u8 as char;
 const LEFTALT: char = winuser::VK_LMENU as u8 as char;
 const ALT: char = winuser::VK_MENU as u8 as char;
 const BACKSPACE: char = winuser::VK_BACK as u8 as char;
+const TAB: char = winuser::VK_TAB as u8 as char;
+const ENTER: char = winuser::VK_RETURN as u8 as char;
+const LEFTSHIFT: char = winuser::VK_LSHIFT as u8 as char;
+const RIGHTSHIFT: char = winuser::VK_RSHIFT as u8 as char;
 const COMMA: char = winuser::VK_OEM_COMMA as u8 as char;
 const PERIOD: char = winuser::VK_OEM_PERIOD as u8 as char;
 const FWD_SLASH: char = winuser::VK_OEM_2 as u8 as char;
@@ -38,6 +104,38 @@ const PLUS: char = winuser::VK_OEM_PLUS as u8 as char;
 // Used to distinguish input events generated by this app, and avoid recursion in input generation
 const H3KEYS_MAGIC: usize = 666;
 
+// Not mapped to anything, so it can be pressed just for its side effects
+const UNASSIGNED_VK: u8 = 0xE8;
+
+// Marks the events sent to check the hooks are still there; the hooks swallow them
+const HOOK_PROBE_MAGIC: usize = 667;
+
+const HOOK_HEALTH_TIMER: usize = 1;
+
+// Class of the app's window, which other instances find it by
+const WINDOW_CLASS: &str = "h3keys3\0";
+
+// Marks WM_COPYDATA messages carrying commands from other instances, and the replies
+const IPC_COPY_DATA: usize = 0x6833;
+
+// Posted by the hooks for the switcher's toast to get shown once they've returned
+const WM_REFRESH_SWITCHER: UINT = winuser::WM_APP;
+// Comes with a boxed String to show
+const WM_SHOW_TOAST: UINT = winuser::WM_APP + 1;
+const WM_HIDE_TOAST: UINT = winuser::WM_APP + 2;
+// Comes with the &'static LaunchTarget to run or raise
+const WM_RUN_OR_RAISE: UINT = winuser::WM_APP + 3;
+
+// The app's window, which gets the messages posted to get things done outside the hooks
+static MESSAGE_HWND: AtomicUsize = AtomicUsize::new(0);
+
+// Returns false if the message couldn't be posted, e.g. before the window exists. A null window
+// would make it a thread message, which the message loop doesn't pick up.
+fn post_message(msg: UINT, lparam: LPARAM) -> bool {
+    let hwnd = MESSAGE_HWND.load(Ordering::Relaxed) as HWND;
+    hwnd != ptr::null_mut() && 0 != unsafe { winuser::PostMessageW(hwnd, msg, 0, lparam) }
+}
+
 #[derive(PartialEq)]
 enum KeyAction {
     Down(i32),
@@ -133,33 +231,14 @@ fn remap_colemak(vk: u8) -> i32 {
     res as u8 as i32
 }
 
+// The top-level window under the cursor, if the window rules allow moving it around
 fn get_window_under_cursor(cursor_pos: (i32, i32)) -> HWND {
-    unsafe {
-        let w = winuser::WindowFromPoint(POINT {
-            x: cursor_pos.0,
-            y: cursor_pos.1,
-        });
-        let w = winuser::GetAncestor(w, 2 /* GA_ROOT */);
-
-        let win_name = [0u8; 256];
-        winuser::GetWindowTextA(w, mem::transmute(&win_name), 256);
-
-        let nul_pos = win_name.iter().position(|&x| x == 0u8);
-        let nul_pos = match nul_pos {
-            Some(pos) => pos,
-            None => return ptr::null_mut(),
-        };
-
-        let win_name = str::from_utf8(&win_name[..nul_pos]);
-
-        match win_name {
-            // Let's not move the Desktop...
-            Ok("Program Manager") => return ptr::null_mut(),
-            Ok(name) => name,
-            Err(_) => return ptr::null_mut(),
-        };
+    let w = get_root_window_at(cursor_pos);
 
+    if w != ptr::null_mut() && is_window_allowed(w) {
         w
+    } else {
+        ptr::null_mut()
     }
 }
 
@@ -176,226 +255,2788 @@ fn get_window_rect(hwnd: HWND) -> RECT {
     rect
 }
 
-struct ScrollEmuState {
-    scroll_emu_on: bool,
-    scroll_emu_from: (i32, i32),
-    scroll_emu_acc: (f32, f32),
+fn to_rect(rect: RECT) -> Rect {
+    Rect::new(rect.left, rect.top, rect.right, rect.bottom)
 }
 
-struct InputHookState {
-    colemak_on: bool,
+// GetWindowRect includes the invisible resize borders which Windows 10 adds around most windows,
+// whereas the extended frame bounds are what's actually visible on screen
+fn get_visible_window_rect(hwnd: HWND) -> Rect {
+    unsafe {
+        let mut rect: RECT = mem::zeroed();
+        let res = dwmapi::DwmGetWindowAttribute(
+            hwnd,
+            dwmapi::DWMWA_EXTENDED_FRAME_BOUNDS,
+            &mut rect as *mut _ as LPVOID,
+            mem::size_of::<RECT>() as DWORD,
+        );
 
-    mod1_on: bool,
-    mod2_on: bool,
-    ctrlmod_on: bool,
-    winkey_on: bool,
-    admin_on: bool,
+        if 0 == res {
+            to_rect(rect)
+        } else {
+            to_rect(get_window_rect(hwnd))
+        }
+    }
+}
 
-    leftalt_on: bool,
-    leftctrl_on: bool,
+fn get_frame_margins(hwnd: HWND) -> FrameMargins {
+    window_geometry::frame_margins(
+        &to_rect(get_window_rect(hwnd)),
+        &get_visible_window_rect(hwnd),
+    )
+}
 
-    window_move_hwnd: HWND,
-    mouse_move_from: (i32, i32),
-    window_move_from: (i32, i32),
+// Puts the visible frame of the window at `rect`
+fn set_window_rect(hwnd: HWND, rect: &Rect) {
+    let rect = window_geometry::add_frame_margins(rect, &get_frame_margins(hwnd));
 
-    window_resize_hwnd: HWND,
-    mouse_resize_from: (i32, i32),
-    window_resize_from: (i32, i32),
+    unsafe {
+        winuser::SetWindowPos(
+            hwnd,
+            ptr::null_mut(),
+            rect.left,
+            rect.top,
+            rect.width(),
+            rect.height(),
+            winuser::SWP_NOACTIVATE | winuser::SWP_NOOWNERZORDER | winuser::SWP_NOZORDER,
+        );
+    }
+}
 
-    scroll_emu_state: Arc<Mutex<ScrollEmuState>>,
+// Moves the visible frame of the window to `pos`, keeping its size
+fn set_window_pos(hwnd: HWND, pos: (i32, i32)) {
+    let margins = get_frame_margins(hwnd);
 
-    mod1_keys_down: HashSet<i32>,
+    unsafe {
+        winuser::SetWindowPos(
+            hwnd,
+            ptr::null_mut(),
+            pos.0 - margins.left,
+            pos.1 - margins.top,
+            0,
+            0,
+            winuser::SWP_NOACTIVATE
+                | winuser::SWP_NOOWNERZORDER
+                | winuser::SWP_NOSIZE
+                | winuser::SWP_NOZORDER,
+        );
+    }
 }
 
-impl InputHookState {
-    fn new() -> InputHookState {
-        InputHookState {
-            colemak_on: true,
+fn get_monitor(hmonitor: HMONITOR) -> Option<Monitor> {
+    unsafe {
+        let mut info: winuser::MONITORINFO = mem::zeroed();
+        info.cbSize = mem::size_of::<winuser::MONITORINFO>() as DWORD;
 
-            mod1_on: false,
-            mod2_on: false,
-            ctrlmod_on: false,
-            winkey_on: false,
-            admin_on: false, // global option control mode, colemak enable/disable, etc.
+        if 0 != winuser::GetMonitorInfoW(hmonitor, &mut info) {
+            let mut dpi: (UINT, UINT) = (0, 0);
+            let res = shellscalingapi::GetDpiForMonitor(
+                hmonitor,
+                shellscalingapi::MDT_EFFECTIVE_DPI,
+                &mut dpi.0,
+                &mut dpi.1,
+            );
 
-            leftalt_on: false,
-            leftctrl_on: false,
+            Some(Monitor {
+                bounds: to_rect(info.rcMonitor),
+                work_area: to_rect(info.rcWork),
+                dpi: if 0 == res { dpi.0 } else { 96 },
+            })
+        } else {
+            None
+        }
+    }
+}
 
-            window_move_hwnd: ptr::null_mut(),
-            mouse_move_from: (0, 0),
-            window_move_from: (0, 0),
+unsafe extern "system" fn enum_monitor(
+    hmonitor: HMONITOR,
+    _hdc: HDC,
+    _rect: LPRECT,
+    lparam: LPARAM,
+) -> BOOL {
+    let monitors = &mut *(lparam as *mut Vec<Monitor>);
+    monitors.extend(get_monitor(hmonitor));
+    TRUE
+}
 
-            window_resize_hwnd: ptr::null_mut(),
-            mouse_resize_from: (0, 0),
-            window_resize_from: (0, 0),
+fn get_monitors() -> Vec<Monitor> {
+    let mut monitors: Vec<Monitor> = Vec::new();
+    unsafe {
+        winuser::EnumDisplayMonitors(
+            ptr::null_mut(),
+            ptr::null(),
+            Some(enum_monitor),
+            &mut monitors as *mut _ as LPARAM,
+        );
+    }
+    monitors
+}
 
-            scroll_emu_state: Arc::new(Mutex::new(ScrollEmuState::new())),
+unsafe extern "system" fn enum_visible_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let windows = &mut *(lparam as *mut Vec<(HWND, Rect)>);
 
-            mod1_keys_down: HashSet::new(),
+    // The desktop and cloaked windows are visible only as far as IsWindowVisible is concerned
+    if 0 != winuser::IsWindowVisible(hwnd)
+        && 0 == winuser::IsIconic(hwnd)
+        && hwnd != winuser::GetShellWindow()
+        && !is_window_cloaked(hwnd)
+    {
+        let rect = get_visible_window_rect(hwnd);
+        if rect.width() > 0 && rect.height() > 0 {
+            windows.push((hwnd, rect));
         }
     }
 
-    fn extended_key_flag(key: i32) -> DWORD {
-        match key {
-            winuser::VK_UP | winuser::VK_DOWN | winuser::VK_LEFT | winuser::VK_RIGHT => {
-                winuser::KEYEVENTF_EXTENDEDKEY
-            }
-            _ => 0,
+    TRUE
+}
+
+// Visible, non-minimized top-level windows on the current virtual desktop, front to back
+fn get_visible_windows() -> Vec<(HWND, Rect)> {
+    let mut windows: Vec<(HWND, Rect)> = Vec::new();
+    unsafe {
+        winuser::EnumWindows(Some(enum_visible_window), &mut windows as *mut _ as LPARAM);
+    }
+    windows
+}
+
+// Snap targets for a dragged window, collected on a thread of their own
+struct SnapWindows {
+    // The window being dragged, as a HWND can't be sent between threads
+    grabbed: usize,
+    rects: Vec<Rect>,
+}
+
+// Hidden by the shell, like suspended UWP apps and windows on other virtual desktops
+fn is_window_cloaked(hwnd: HWND) -> bool {
+    unsafe {
+        let mut cloaked: DWORD = 0;
+        let res = dwmapi::DwmGetWindowAttribute(
+            hwnd,
+            dwmapi::DWMWA_CLOAKED,
+            &mut cloaked as *mut _ as LPVOID,
+            mem::size_of::<DWORD>() as DWORD,
+        );
+
+        0 == res && 0 != cloaked
+    }
+}
+
+// Collects the windows Alt+Tab would list
+unsafe extern "system" fn enum_switcher_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let windows = &mut *(lparam as *mut Vec<SwitcherEntry<HWND>>);
+
+    let ex_style = winuser::GetWindowLongW(hwnd, winuser::GWL_EXSTYLE) as DWORD;
+    if 0 != winuser::IsWindowVisible(hwnd)
+        && hwnd != winuser::GetShellWindow()
+        && winuser::GetWindow(hwnd, winuser::GW_OWNER) == ptr::null_mut()
+        && 0 == ex_style & winuser::WS_EX_TOOLWINDOW
+        && !is_window_cloaked(hwnd)
+    {
+        let title = get_window_title(hwnd);
+        if !title.is_empty() {
+            windows.push(SwitcherEntry {
+                window: hwnd,
+                title,
+                process_name: get_window_process_name(hwnd).unwrap_or_default(),
+            });
         }
     }
 
-    fn send_key(key: u8, down: bool) {
-        unsafe {
-            let mut input = winuser::INPUT {
-                type_: winuser::INPUT_KEYBOARD,
-                u: mem::uninitialized(),
-            };
+    TRUE
+}
 
-            let ext_flag = Self::extended_key_flag(key as _);
-            let scancode = winuser::MapVirtualKeyA(key as u32, winuser::MAPVK_VK_TO_VSC) as u16;
+// Activating a window brings it to the front, so the front to back order
+// EnumWindows goes in doubles as the most recently used order
+fn get_switcher_windows() -> Vec<SwitcherEntry<HWND>> {
+    let mut windows: Vec<SwitcherEntry<HWND>> = Vec::new();
+    unsafe {
+        winuser::EnumWindows(Some(enum_switcher_window), &mut windows as *mut _ as LPARAM);
+    }
+    windows
+}
 
-            *input.u.ki_mut() = winuser::KEYBDINPUT {
-                wVk: key as u16,
-                wScan: scancode,
-                dwFlags: ext_flag | if down { 0 } else { winuser::KEYEVENTF_KEYUP },
-                time: 0,
-                dwExtraInfo: H3KEYS_MAGIC,
-            };
+fn activate_window(hwnd: HWND) {
+    unsafe {
+        if 0 == winuser::IsWindow(hwnd) {
+            return;
+        }
 
-            winuser::SendInput(1, &mut input, mem::size_of::<winuser::INPUT>() as i32);
+        if 0 != winuser::IsIconic(hwnd) {
+            winuser::ShowWindow(hwnd, winuser::SW_RESTORE);
         }
 
-        //unsafe { winuser::keybd_event(key, 0, if down {0} else {winuser::KEYEVENTF_KEYUP}, H3KEYS_MAGIC); }
+        // The foreground lock only lets whoever got the last input event take the foreground.
+        // A synthesized press of an unassigned key counts as one.
+        if 0 == winuser::SetForegroundWindow(hwnd) {
+            InputHookState::send_key(UNASSIGNED_VK, true);
+            InputHookState::send_key(UNASSIGNED_VK, false);
+            winuser::SetForegroundWindow(hwnd);
+        }
     }
+}
 
-    fn key_hook(&mut self, code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-        if winuser::HC_ACTION == code {
-            let input_key = unsafe { *(lparam as winuser::PKBDLLHOOKSTRUCT) };
-            if input_key.dwExtraInfo == H3KEYS_MAGIC {
-                return unsafe { winuser::CallNextHookEx(ptr::null_mut(), code, wparam, lparam) };
-            }
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
 
-            let key_pressed =
-                winuser::WM_KEYDOWN == wparam as u32 || winuser::WM_SYSKEYDOWN == wparam as u32;
-            let key_released =
-                winuser::WM_KEYUP == wparam as u32 || winuser::WM_SYSKEYUP == wparam as u32;
+fn launch(target: &LaunchTarget) -> bool {
+    let command = to_wide(target.command);
+    let args = to_wide(target.args);
+    let working_dir = target.working_dir.map(to_wide);
 
-            let down_only = |rt: RemapTarget| {
-                if key_pressed {
-                    rt
-                } else {
-                    key(0)
+    let res = unsafe {
+        shellapi::ShellExecuteW(
+            ptr::null_mut(),
+            to_wide("open").as_ptr(),
+            command.as_ptr(),
+            args.as_ptr(),
+            working_dir.as_ref().map_or(ptr::null(), |dir| dir.as_ptr()),
+            winuser::SW_SHOWNORMAL,
+        )
+    };
+
+    // Anything above 32 means success
+    res as usize > 32
+}
+
+// Listing the windows takes too long for the hook, so the window does the rest
+fn post_run_or_raise(target: &'static LaunchTarget) {
+    // Pressing anything while the Windows key is down stops it from opening the Start menu
+    InputHookState::send_key(UNASSIGNED_VK, true);
+    InputHookState::send_key(UNASSIGNED_VK, false);
+
+    post_message(WM_RUN_OR_RAISE, target as *const LaunchTarget as LPARAM);
+}
+
+// WM_RUN_OR_RAISE
+fn run_or_raise(target: &'static LaunchTarget) {
+    let foreground = unsafe { winuser::GetForegroundWindow() };
+    let foreground = if foreground == ptr::null_mut() {
+        None
+    } else {
+        Some(foreground)
+    };
+
+    match launcher::run_or_raise(target, &get_switcher_windows(), foreground) {
+        RunOrRaise::Raise(w) => activate_window(w),
+        RunOrRaise::Launch => {
+            // ShellExecute can take a while, and the hooks run on this thread
+            thread::spawn(move || {
+                let rt = RuntimeContext::init();
+                if !launch(target) {
+                    toast_notification(&format!("Could not launch {}", target.command));
                 }
-            };
+                rt.uninit();
+            });
+        }
+    }
+}
 
-            if key_pressed {
-                //println!("key: 0x{:x}", input_key.vkCode);
+struct DesktopWindowManager;
+
+impl WindowManager for DesktopWindowManager {
+    type Window = HWND;
+
+    fn foreground_window(&self) -> Option<HWND> {
+        unsafe {
+            let w = winuser::GetForegroundWindow();
+            if w == ptr::null_mut() || !is_window_allowed(w) {
+                None
+            } else {
+                Some(w)
             }
+        }
+    }
 
-            if key_pressed || key_released {
-                // Enable caps-lock layer
-                if winuser::VK_CAPITAL == input_key.vkCode as i32 {
-                    // If disabling, make sure all remapped keys get released
-                    if key_released {
-                        for &key in self.mod1_keys_down.iter() {
-                            Self::send_key(key as u8, false);
-                        }
-                        self.mod1_keys_down.clear();
-                        self.ctrlmod_on = false;
-                        self.window_move_hwnd = ptr::null_mut();
-                        self.window_resize_hwnd = ptr::null_mut();
-                        self.admin_on = false;
-
-                        let scroll = &mut self.scroll_emu_state.lock().unwrap();
-                        scroll.scroll_emu_on = false;
-                    }
+    fn window_rect(&self, window: HWND) -> Rect {
+        get_visible_window_rect(window)
+    }
 
-                    self.mod1_on = key_pressed;
-                    return 1;
-                }
+    fn set_window_rect(&mut self, window: HWND, rect: &Rect) {
+        log(Level::Debug, Category::Window, || {
+            let process_name = get_window_process_name(window);
+            format!("{} to {:?}", process_name.unwrap_or_default(), rect)
+        });
+        set_window_rect(window, rect);
+    }
 
-                // Enable pipe/backslash layer
-                if winuser::VK_OEM_102 == input_key.vkCode as i32 {
-                    self.mod2_on = key_pressed;
-                    return 1;
-                }
+    fn is_maximized(&self, window: HWND) -> bool {
+        0 != unsafe { winuser::IsZoomed(window) }
+    }
 
-                // Colemak
-                let remap = if self.colemak_on {
-                    key(remap_colemak(input_key.vkCode as u8))
+    fn set_maximized(&mut self, window: HWND, maximized: bool) {
+        unsafe {
+            winuser::ShowWindow(
+                window,
+                if maximized {
+                    winuser::SW_MAXIMIZE
                 } else {
-                    key(0)
-                };
+                    winuser::SW_RESTORE
+                },
+            );
+        }
+    }
 
-                // Windows keys
-                let remap = match input_key.vkCode as u8 as char {
-                    TILDE => key(winuser::VK_ESCAPE),
-                    UK_TILDE => key(TILDE),
-                    ALT_GR => {
-                        self.winkey_on = key_pressed;
-                        key(winuser::VK_LWIN)
-                    }
-                    LEFTALT => {
-                        self.leftalt_on = key_pressed;
-                        remap
-                    }
-                    LEFTCTRL => {
-                        self.leftctrl_on = key_pressed;
-                        remap
-                    }
-                    BACKSPACE => {
-                        if key_pressed && self.leftalt_on && self.leftctrl_on {
-                            unsafe {
-                                let top_window = winuser::GetForegroundWindow();
-                                let mut pid: DWORD = 0;
-                                winuser::GetWindowThreadProcessId(top_window, &mut pid);
+    fn monitors(&self) -> Vec<Monitor> {
+        get_monitors()
+    }
 
-                                let h = kernel32::OpenProcess(
-                                    winapi::um::winnt::PROCESS_ALL_ACCESS,
-                                    0,
-                                    pid,
-                                );
-                                if h != ptr::null_mut() {
-                                    kernel32::TerminateProcess(h, 0);
-                                }
-                            }
-                        }
-                        remap
-                    }
-                    RIGHTCTRL => key(winuser::VK_APPS),
-                    'U' => {
-                        if self.winkey_on && key_pressed {
-                            // We will not register a key-up due to the lock screen
-                            self.winkey_on = false;
-                            unsafe {
-                                winuser::LockWorkStation();
-                            }
-                            RemapTarget::Block
-                        } else {
-                            remap
-                        }
-                    }
-                    '4' => {
-                        if self.winkey_on && key_pressed {
-                            down_only(alt_key(winuser::VK_F4))
-                        } else {
-                            remap
-                        }
-                    }
-                    'M' => {
-                        if self.winkey_on && key_pressed {
-                            key('D')
-                        } else {
-                            remap
-                        }
-                    }
-                    _ => remap,
-                };
+    fn window_monitor(&self, window: HWND) -> Option<Monitor> {
+        get_monitor(unsafe {
+            winuser::MonitorFromWindow(window, winuser::MONITOR_DEFAULTTONEAREST)
+        })
+    }
+}
 
-                let remap = if self.mod1_on {
-                    // Caps-lock layer
+fn get_root_window_at(pos: (i32, i32)) -> HWND {
+    unsafe {
+        let w = winuser::WindowFromPoint(POINT { x: pos.0, y: pos.1 });
+        winuser::GetAncestor(w, 2 /* GA_ROOT */)
+    }
+}
 
-                    let mapped_key = match input_key.vkCode as u8 as char {
+fn get_window_process_id(hwnd: HWND) -> DWORD {
+    let mut pid: DWORD = 0;
+    unsafe {
+        winuser::GetWindowThreadProcessId(hwnd, &mut pid);
+    }
+    pid
+}
+
+// Executable file name of the process owning the window, e.g. "explorer.exe"
+fn get_window_process_name(hwnd: HWND) -> Option<String> {
+    get_process_name(get_window_process_id(hwnd))
+}
+
+fn get_process_name(pid: DWORD) -> Option<String> {
+    if 0 == pid {
+        return None;
+    }
+
+    unsafe {
+        let h = processthreadsapi::OpenProcess(winnt::PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if h == ptr::null_mut() {
+            return None;
+        }
+
+        let mut path = [0u16; 1024];
+        let mut len = path.len() as DWORD;
+        let res = winbase::QueryFullProcessImageNameW(h, 0, path.as_mut_ptr(), &mut len);
+        handleapi::CloseHandle(h);
+
+        if 0 == res {
+            return None;
+        }
+
+        let path = String::from_utf16_lossy(&path[..len as usize]);
+        path.rsplit('\\').next().map(|name| name.to_owned())
+    }
+}
+
+// Process names by window and process ID, as window handles get reused
+type ProcessNameCache = HashMap<(usize, DWORD), Option<String>>;
+
+// Opening the process takes too long to do for every click inside the hook, while
+// GetWindowThreadProcessId is quick
+fn get_cached_process_name(cache: &mut ProcessNameCache, hwnd: HWND) -> Option<String> {
+    let pid = get_window_process_id(hwnd);
+
+    // Closed windows never get taken out otherwise
+    if cache.len() >= 64 {
+        cache.clear();
+    }
+
+    cache
+        .entry((hwnd as usize, pid))
+        .or_insert_with(|| get_process_name(pid))
+        .clone()
+}
+
+fn get_window_class_name(hwnd: HWND) -> String {
+    unsafe {
+        let mut name = [0u16; 256];
+        let len = winuser::GetClassNameW(hwnd, name.as_mut_ptr(), name.len() as i32);
+        String::from_utf16_lossy(&name[..cmp::max(len, 0) as usize])
+    }
+}
+
+fn is_key_down(vk: i32) -> bool {
+    0 != unsafe { winuser::GetAsyncKeyState(vk) } as u16 & 0x8000
+}
+
+// The character `vk` types in the foreground window's keyboard layout
+fn get_typed_char(vk: u8, shifted: bool) -> Option<char> {
+    unsafe {
+        let mut key_state = [0u8; 256];
+        if shifted {
+            key_state[winuser::VK_SHIFT as usize] = 0x80;
+        }
+
+        let thread =
+            winuser::GetWindowThreadProcessId(winuser::GetForegroundWindow(), ptr::null_mut());
+        let layout = winuser::GetKeyboardLayout(thread);
+        let scancode = winuser::MapVirtualKeyA(vk as u32, winuser::MAPVK_VK_TO_VSC);
+
+        let mut chars = [0u16; 4];
+        let len = winuser::ToUnicodeEx(
+            vk as u32,
+            scancode,
+            key_state.as_ptr(),
+            chars.as_mut_ptr(),
+            chars.len() as i32,
+            4, /* don't touch the keyboard state, so dead keys keep working */
+            layout,
+        );
+
+        if 1 == len {
+            std::char::from_u32(chars[0] as u32).filter(|c| !c.is_control())
+        } else {
+            None
+        }
+    }
+}
+
+fn get_clipboard_text() -> Option<String> {
+    unsafe {
+        if 0 == winuser::OpenClipboard(ptr::null_mut()) {
+            return None;
+        }
+
+        let mut text = None;
+        let data = winuser::GetClipboardData(winuser::CF_UNICODETEXT);
+        if data != ptr::null_mut() {
+            let chars = winbase::GlobalLock(data) as *const u16;
+            if chars != ptr::null() {
+                let len = (0..).take_while(|&i| *chars.offset(i) != 0).count();
+                text = Some(String::from_utf16_lossy(std::slice::from_raw_parts(
+                    chars, len,
+                )));
+                winbase::GlobalUnlock(data);
+            }
+        }
+
+        winuser::CloseClipboard();
+        text
+    }
+}
+
+// Not in winapi 0.3.3
+const GMEM_MOVEABLE: UINT = 0x0002;
+
+fn set_clipboard_text(text: &str) -> bool {
+    let text = to_wide(text);
+
+    unsafe {
+        let data = winbase::GlobalAlloc(GMEM_MOVEABLE, text.len() * mem::size_of::<u16>());
+        if data == ptr::null_mut() {
+            return false;
+        }
+
+        let chars = winbase::GlobalLock(data) as *mut u16;
+        if chars == ptr::null_mut() {
+            winbase::GlobalFree(data);
+            return false;
+        }
+        ptr::copy_nonoverlapping(text.as_ptr(), chars, text.len());
+        winbase::GlobalUnlock(data);
+
+        if 0 == winuser::OpenClipboard(ptr::null_mut()) {
+            winbase::GlobalFree(data);
+            return false;
+        }
+
+        winuser::EmptyClipboard();
+        // The clipboard owns the memory from here on, unless this fails
+        let res = winuser::SetClipboardData(winuser::CF_UNICODETEXT, data);
+        if res == ptr::null_mut() {
+            winbase::GlobalFree(data);
+        }
+
+        winuser::CloseClipboard();
+        res != ptr::null_mut()
+    }
+}
+
+// Password managers and the like mark what they copy with this format,
+// so that clipboard history tools leave it alone
+fn is_clipboard_private() -> bool {
+    unsafe {
+        let format = winuser::RegisterClipboardFormatA(
+            "ExcludeClipboardContentFromMonitorProcessing\0".as_ptr() as *const i8,
+        );
+        0 != format && 0 != winuser::IsClipboardFormatAvailable(format)
+    }
+}
+
+struct SystemClipboard;
+
+impl ClipboardBackend for SystemClipboard {
+    fn get_text(&mut self) -> Option<String> {
+        get_clipboard_text()
+    }
+
+    fn set_text(&mut self, text: &str) -> bool {
+        set_clipboard_text(text)
+    }
+}
+
+// Where files we keep across runs go: %APPDATA%\h3keys3
+fn get_data_path(file_name: &str) -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("h3keys3").join(file_name))
+}
+
+// Returns the path written to
+fn write_data_file(file_name: &str, contents: &str) -> Option<PathBuf> {
+    let path = get_data_path(file_name)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).ok();
+    }
+    fs::write(&path, contents).ok().map(|_| path)
+}
+
+// Current local date and time, as "2018-03-14 15:09:26.535"
+fn get_log_time() -> String {
+    unsafe {
+        let mut now: minwinbase::SYSTEMTIME = mem::zeroed();
+        sysinfoapi::GetLocalTime(&mut now);
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+            now.wYear, now.wMonth, now.wDay, now.wHour, now.wMinute, now.wSecond, now.wMilliseconds
+        )
+    }
+}
+
+// Index into LEVELS, shared by all threads so the admin layer changes it for all
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(LOG_CONFIG.level as usize);
+
+fn get_log_level() -> Level {
+    LEVELS[LOG_LEVEL.load(Ordering::Relaxed) % LEVELS.len()]
+}
+
+// From the admin layer or `h3keys3 log-level`
+fn set_log_level(level: Level) {
+    LOG_LEVEL.store(level as usize, Ordering::Relaxed);
+    log(Level::Info, Category::Engine, || {
+        format!("Log level: {}", logging::level_name(level))
+    });
+}
+
+// Lines for the logging thread; None until it's started
+static mut LOG_SENDER: Option<Mutex<mpsc::Sender<String>>> = None;
+
+// Shifts h3keys3.log to h3keys3.1.log and so on, dropping the oldest
+fn rotate_log_files() {
+    for age in (0..LOG_CONFIG.max_old_files).rev() {
+        let from = get_data_path(&logging::file_name(age));
+        let to = get_data_path(&logging::file_name(age + 1));
+        if let (Some(from), Some(to)) = (from, to) {
+            fs::rename(from, to).ok();
+        }
+    }
+}
+
+// Opens the log for appending, rotating it first if `line_len` more bytes wouldn't fit
+fn open_log_file(line_len: u64) -> Option<(fs::File, u64)> {
+    let path = get_data_path(&logging::file_name(0))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).ok();
+    }
+
+    let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+    if size > 0 && size + line_len > LOG_CONFIG.max_file_bytes {
+        rotate_log_files();
+    }
+
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .ok()?;
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    Some((file, size))
+}
+
+// All lines get written by one thread, so the hooks never wait on the disk, and the log only
+// ever has the one handle, which can't write on after the file got rotated away
+fn start_logging() {
+    let (sender, receiver) = mpsc::channel::<String>();
+    unsafe {
+        LOG_SENDER = Some(Mutex::new(sender));
+    }
+
+    thread::spawn(move || {
+        let mut file: Option<(fs::File, u64)> = None;
+
+        for line in receiver.iter() {
+            let line_len = line.len() as u64;
+
+            let full = file.as_ref().map_or(true, |&(_, size)| {
+                size + line_len > LOG_CONFIG.max_file_bytes
+            });
+            if full {
+                // Closed first, so it can be renamed
+                drop(file.take());
+                file = open_log_file(line_len);
+            }
+
+            if let Some((ref mut f, ref mut size)) = file {
+                if f.write_all(line.as_bytes()).is_ok() {
+                    *size += line_len;
+                }
+            }
+        }
+    });
+}
+
+// `message` only gets called if the level is logged
+fn log<F: FnOnce() -> String>(level: Level, category: Category, message: F) {
+    if Level::Off == level || level > get_log_level() {
+        return;
+    }
+
+    let line = logging::format_line(&get_log_time(), level, category, &message());
+    if let Some(sender) = unsafe { LOG_SENDER.as_ref() } {
+        sender.lock().unwrap().send(line).ok();
+    }
+}
+
+fn get_clipboard_history_path() -> Option<PathBuf> {
+    get_data_path("clipboard_history.txt")
+}
+
+fn load_clipboard_history() -> ClipboardHistory {
+    let mut history = ClipboardHistory::new();
+    if let Some(data) = get_clipboard_history_path().and_then(|path| fs::read_to_string(path).ok())
+    {
+        history.load(&data);
+    }
+    history
+}
+
+// Writes the file with the history unlocked, like the typing stats
+fn save_clipboard_history(history: &Mutex<ClipboardHistory>) {
+    let data = history.lock().unwrap().save(&CLIPBOARD_HISTORY_CONFIG);
+    write_data_file("clipboard_history.txt", &data);
+}
+
+fn load_debounce_stats() -> Debounce {
+    let mut debounce = Debounce::new();
+    if let Some(data) = get_data_path("debounce_stats.txt").and_then(|p| fs::read_to_string(p).ok())
+    {
+        debounce.load(&data);
+    }
+    debounce
+}
+
+fn save_debounce_stats(debounce: &Mutex<Debounce>) {
+    if DEBOUNCE_CONFIG.enabled {
+        let data = debounce.lock().unwrap().save();
+        write_data_file("debounce_stats.txt", &data);
+    }
+}
+
+fn load_typing_stats() -> TypingStats {
+    let mut stats = TypingStats::new();
+    if let Some(data) = get_data_path("typing_stats.txt").and_then(|p| fs::read_to_string(p).ok()) {
+        stats.load(&data);
+    }
+    stats
+}
+
+// Writes the file with the stats unlocked, so the hook doesn't wait on it
+fn save_typing_stats(stats: &Mutex<TypingStats>) {
+    if TYPING_STATS_CONFIG.enabled {
+        let data = stats.lock().unwrap().save();
+        write_data_file("typing_stats.txt", &data);
+    }
+}
+
+// Writes JSON, CSV and heatmap exports next to the stats, and returns their directory
+fn export_typing_stats(stats: &Mutex<TypingStats>) -> Option<PathBuf> {
+    save_typing_stats(stats);
+    let (json, csv, svg) = {
+        let stats = stats.lock().unwrap();
+        (stats.to_json(), stats.to_csv(), stats.heatmap_svg())
+    };
+    write_data_file("typing_stats.json", &json)?;
+    write_data_file("typing_stats.csv", &csv)?;
+    let path = write_data_file("typing_stats_heatmap.svg", &svg)?;
+    path.parent().map(|dir| dir.to_path_buf())
+}
+
+// Current local date and time, as "2018-03-14" and "15:09"
+fn get_local_date_time() -> (String, String) {
+    unsafe {
+        let mut now: minwinbase::SYSTEMTIME = mem::zeroed();
+        sysinfoapi::GetLocalTime(&mut now);
+        (
+            format!("{:04}-{:02}-{:02}", now.wYear, now.wMonth, now.wDay),
+            format!("{:02}:{:02}", now.wHour, now.wMinute),
+        )
+    }
+}
+
+fn get_window_title(hwnd: HWND) -> String {
+    unsafe {
+        let len = winuser::GetWindowTextLengthW(hwnd);
+        let mut title = vec![0u16; cmp::max(len, 0) as usize + 1];
+        let len = winuser::GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as i32);
+        String::from_utf16_lossy(&title[..cmp::max(len, 0) as usize])
+    }
+}
+
+// Covers its whole monitor and has no caption, like games and video players
+fn is_window_fullscreen(hwnd: HWND) -> bool {
+    unsafe {
+        let style = winuser::GetWindowLongW(hwnd, winuser::GWL_STYLE) as DWORD;
+        if style & winuser::WS_CAPTION == winuser::WS_CAPTION {
+            return false;
+        }
+
+        let monitor = winuser::MonitorFromWindow(hwnd, winuser::MONITOR_DEFAULTTONEAREST);
+        match get_monitor(monitor) {
+            Some(monitor) => {
+                let rect = get_visible_window_rect(hwnd);
+                let bounds = &monitor.bounds;
+                rect.left <= bounds.left
+                    && rect.top <= bounds.top
+                    && rect.right >= bounds.right
+                    && rect.bottom >= bounds.bottom
+            }
+            None => false,
+        }
+    }
+}
+
+// Not in winapi 0.3.3
+#[link(name = "advapi32")]
+extern "system" {
+    fn GetTokenInformation(
+        TokenHandle: winnt::HANDLE,
+        TokenInformationClass: winnt::TOKEN_INFORMATION_CLASS,
+        TokenInformation: LPVOID,
+        TokenInformationLength: DWORD,
+        ReturnLength: PDWORD,
+    ) -> BOOL;
+}
+
+fn is_process_elevated(process: winnt::HANDLE) -> Option<bool> {
+    unsafe {
+        let mut token: winnt::HANDLE = ptr::null_mut();
+        if 0 == processthreadsapi::OpenProcessToken(process, winnt::TOKEN_QUERY, &mut token) {
+            return None;
+        }
+
+        let mut elevation: winnt::TOKEN_ELEVATION = mem::zeroed();
+        let mut len: DWORD = 0;
+        let res = GetTokenInformation(
+            token,
+            winnt::TokenElevation,
+            &mut elevation as *mut _ as LPVOID,
+            mem::size_of::<winnt::TOKEN_ELEVATION>() as DWORD,
+            &mut len,
+        );
+        handleapi::CloseHandle(token);
+
+        if 0 == res {
+            None
+        } else {
+            Some(0 != elevation.TokenIsElevated)
+        }
+    }
+}
+
+// Elevated while we aren't, so SetWindowPos would silently fail on it
+fn is_window_elevated(hwnd: HWND) -> bool {
+    unsafe {
+        if is_process_elevated(processthreadsapi::GetCurrentProcess()) == Some(true) {
+            return false;
+        }
+
+        let mut pid: DWORD = 0;
+        winuser::GetWindowThreadProcessId(hwnd, &mut pid);
+        if 0 == pid {
+            return false;
+        }
+
+        // Processes we can't even query are out of reach too
+        let h = processthreadsapi::OpenProcess(winnt::PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if h == ptr::null_mut() {
+            return true;
+        }
+
+        let elevated = is_process_elevated(h).unwrap_or(true);
+        handleapi::CloseHandle(h);
+        elevated
+    }
+}
+
+// Asks the system about the window as the rules need to know, and at most once
+struct DesktopWindowInfo {
+    hwnd: HWND,
+    process_name: RefCell<Option<String>>,
+    fullscreen: Cell<Option<bool>>,
+    elevated: Cell<Option<bool>>,
+}
+
+impl DesktopWindowInfo {
+    fn new(hwnd: HWND) -> DesktopWindowInfo {
+        DesktopWindowInfo {
+            hwnd,
+            process_name: RefCell::new(None),
+            fullscreen: Cell::new(None),
+            elevated: Cell::new(None),
+        }
+    }
+}
+
+fn get_cached<T: Copy, F: FnOnce() -> T>(cell: &Cell<Option<T>>, get: F) -> T {
+    let value = cell.get().unwrap_or_else(get);
+    cell.set(Some(value));
+    value
+}
+
+impl WindowInfo for DesktopWindowInfo {
+    fn class_name(&self) -> String {
+        get_window_class_name(self.hwnd)
+    }
+
+    fn process_name(&self) -> String {
+        let mut process_name = self.process_name.borrow_mut();
+        if process_name.is_none() {
+            *process_name = Some(get_window_process_name(self.hwnd).unwrap_or_default());
+        }
+        process_name.clone().unwrap_or_default()
+    }
+
+    fn title(&self) -> String {
+        get_window_title(self.hwnd)
+    }
+
+    fn fullscreen(&self) -> bool {
+        get_cached(&self.fullscreen, || is_window_fullscreen(self.hwnd))
+    }
+
+    fn elevated(&self) -> bool {
+        get_cached(&self.elevated, || is_window_elevated(self.hwnd))
+    }
+}
+
+fn is_window_allowed(hwnd: HWND) -> bool {
+    window_rules::is_window_allowed(WINDOW_RULES, &DesktopWindowInfo::new(hwnd))
+}
+
+fn install_hook(id: i32, hook: winuser::HOOKPROC) -> HHOOK {
+    unsafe { winuser::SetWindowsHookExA(id, hook, GetModuleHandleA(ptr::null()) as HINSTANCE, 0) }
+}
+
+// Tick count of the last input the system saw, hooked or not
+fn get_last_input_time() -> u32 {
+    let mut info = winuser::LASTINPUTINFO {
+        cbSize: mem::size_of::<winuser::LASTINPUTINFO>() as UINT,
+        dwTime: 0,
+    };
+    unsafe {
+        winuser::GetLastInputInfo(&mut info);
+    }
+    info.dwTime
+}
+
+// An unassigned key tap, and a mouse move by nothing. Returns false if they couldn't be sent.
+fn send_hook_probes() -> bool {
+    unsafe {
+        let mut inputs: [winuser::INPUT; 3] = mem::zeroed();
+
+        for (input, &down) in inputs.iter_mut().zip([true, false].iter()) {
+            input.type_ = winuser::INPUT_KEYBOARD;
+            *input.u.ki_mut() = winuser::KEYBDINPUT {
+                wVk: UNASSIGNED_VK as u16,
+                wScan: 0,
+                dwFlags: if down { 0 } else { winuser::KEYEVENTF_KEYUP },
+                time: 0,
+                dwExtraInfo: HOOK_PROBE_MAGIC,
+            };
+        }
+
+        inputs[2].type_ = winuser::INPUT_MOUSE;
+        *inputs[2].u.mi_mut() = winuser::MOUSEINPUT {
+            dx: 0,
+            dy: 0,
+            mouseData: 0,
+            dwFlags: winuser::MOUSEEVENTF_MOVE,
+            time: 0,
+            dwExtraInfo: HOOK_PROBE_MAGIC,
+        };
+
+        let sent = winuser::SendInput(
+            inputs.len() as UINT,
+            inputs.as_mut_ptr(),
+            mem::size_of::<winuser::INPUT>() as i32,
+        );
+        sent as usize == inputs.len()
+    }
+}
+
+// Keystroke from WM_INPUT: the keyboard which sent it, the virtual key, and whether it was
+// pressed. Injected input has no keyboard, and gets None.
+fn get_raw_input_key(input: winuser::HRAWINPUT) -> Option<(winnt::HANDLE, u8, bool)> {
+    unsafe {
+        let mut raw: winuser::RAWINPUT = mem::zeroed();
+        let mut size = mem::size_of::<winuser::RAWINPUT>() as UINT;
+        let res = winuser::GetRawInputData(
+            input,
+            winuser::RID_INPUT,
+            &mut raw as *mut _ as LPVOID,
+            &mut size,
+            mem::size_of::<winuser::RAWINPUTHEADER>() as UINT,
+        );
+
+        if UINT::max_value() == res
+            || winuser::RIM_TYPEKEYBOARD != raw.header.dwType
+            || raw.header.hDevice == ptr::null_mut()
+        {
+            return None;
+        }
+
+        let keyboard = raw.data.keyboard();
+        let pressed = 0 == keyboard.Flags as DWORD & winuser::RI_KEY_BREAK;
+        Some((raw.header.hDevice, keyboard.VKey as u8, pressed))
+    }
+}
+
+fn get_raw_input_device_info(device: winnt::HANDLE) -> DeviceInfo {
+    unsafe {
+        let mut len: UINT = 0;
+        winuser::GetRawInputDeviceInfoW(
+            device,
+            winuser::RIDI_DEVICENAME,
+            ptr::null_mut(),
+            &mut len,
+        );
+
+        let mut path = vec![0u16; len as usize];
+        let res = winuser::GetRawInputDeviceInfoW(
+            device,
+            winuser::RIDI_DEVICENAME,
+            path.as_mut_ptr() as LPVOID,
+            &mut len,
+        );
+
+        let path = if UINT::max_value() == res {
+            String::new()
+        } else {
+            let end = path.iter().position(|&c| 0 == c).unwrap_or(path.len());
+            String::from_utf16_lossy(&path[..end])
+        };
+
+        DeviceInfo { path }
+    }
+}
+
+// Caps-layer tap-dance keys, and how many taps each one tells apart
+const CAPS_TAP_DANCES: &[(char, u32)] = &[(SEMICOLON, 2)];
+
+fn caps_tap_dance_action(key: char, dance: TapDance) -> RemapTarget {
+    match (key, dance.taps, dance.held) {
+        // Enter, Shift+Enter on a double tap, and Ctrl+Enter when held
+        (SEMICOLON, _, true) => ctrl_key(winuser::VK_RETURN),
+        (SEMICOLON, 2, false) => shift_key(winuser::VK_RETURN),
+        (SEMICOLON, _, false) => key(winuser::VK_RETURN),
+        _ => RemapTarget::Block,
+    }
+}
+
+// Presses and releases whatever `target` maps to
+fn send_remap_tap(target: RemapTarget) {
+    match target {
+        RemapTarget::BlindKey(key) => {
+            InputHookState::send_key(key as u8, true);
+            InputHookState::send_key(key as u8, false);
+        }
+        RemapTarget::KeySeq(kseq) => {
+            for key_action in kseq.iter() {
+                match key_action {
+                    &KeyAction::Down(key) => InputHookState::send_key(key as u8, true),
+                    &KeyAction::Up(key) => InputHookState::send_key(key as u8, false),
+                }
+            }
+        }
+        RemapTarget::Block => (),
+    }
+}
+
+// For the log, keys redacted as configured
+fn describe_remap(remap: &RemapTarget) -> String {
+    match *remap {
+        RemapTarget::BlindKey(0) => "passed through".to_owned(),
+        RemapTarget::BlindKey(k) => logging::key_name(&LOG_CONFIG, k as u8),
+        RemapTarget::KeySeq(ref kseq) => {
+            let actions: Vec<String> = kseq
+                .iter()
+                .map(|action| match *action {
+                    KeyAction::Down(k) => {
+                        format!("{} down", logging::key_name(&LOG_CONFIG, k as u8))
+                    }
+                    KeyAction::Up(k) => format!("{} up", logging::key_name(&LOG_CONFIG, k as u8)),
+                })
+                .collect();
+            actions.join(", ")
+        }
+        RemapTarget::Block => "blocked".to_owned(),
+    }
+}
+
+// The key `remap` types, and whether it's shifted
+fn typed_key(vk: u8, remap: &RemapTarget) -> Option<(u8, bool)> {
+    let shift_on = is_key_down(winuser::VK_SHIFT);
+
+    match *remap {
+        RemapTarget::BlindKey(0) => Some((vk, shift_on)),
+        RemapTarget::BlindKey(k) => Some((k as u8, shift_on)),
+        RemapTarget::KeySeq(ref kseq) => match kseq[..] {
+            [KeyAction::Down(modifier), KeyAction::Down(k), KeyAction::Up(_), KeyAction::Up(_)]
+                if winuser::VK_SHIFT == modifier =>
+            {
+                Some((k as u8, true))
+            }
+            _ => None,
+        },
+        RemapTarget::Block => None,
+    }
+}
+
+// Presses a key with exactly the modifiers `stroke` asks for, whatever is held down
+fn send_nav_stroke(stroke: NavStroke) {
+    let flipped: Vec<(u8, bool)> = [
+        (winuser::VK_SHIFT, stroke.shift),
+        (winuser::VK_CONTROL, stroke.ctrl),
+    ]
+    .iter()
+    .filter(|&&(k, on)| is_key_down(k) != on)
+    .map(|&(k, on)| (k as u8, on))
+    .collect();
+
+    for &(k, on) in flipped.iter() {
+        InputHookState::send_key(k, on);
+    }
+
+    InputHookState::send_key(stroke.vk, true);
+    InputHookState::send_key(stroke.vk, false);
+
+    for &(k, on) in flipped.iter().rev() {
+        InputHookState::send_key(k, !on);
+    }
+}
+
+// Not in winapi 0.3.3
+RIDL!(#[uuid(0x5cdf2c82, 0x841e, 0x4546, 0x97, 0x22, 0x0c, 0xf7, 0x40, 0x78, 0x22, 0x9a)]
+interface IAudioEndpointVolume(IAudioEndpointVolumeVtbl): IUnknown(IUnknownVtbl) {
+    fn RegisterControlChangeNotify(pNotify: *mut IUnknown,) -> HRESULT,
+    fn UnregisterControlChangeNotify(pNotify: *mut IUnknown,) -> HRESULT,
+    fn GetChannelCount(pnChannelCount: *mut UINT,) -> HRESULT,
+    fn SetMasterVolumeLevel(fLevelDB: f32, pguidEventContext: LPCGUID,) -> HRESULT,
+    fn SetMasterVolumeLevelScalar(fLevel: f32, pguidEventContext: LPCGUID,) -> HRESULT,
+    fn GetMasterVolumeLevel(pfLevelDB: *mut f32,) -> HRESULT,
+    fn GetMasterVolumeLevelScalar(pfLevel: *mut f32,) -> HRESULT,
+    fn SetChannelVolumeLevel(nChannel: UINT, fLevelDB: f32, pguidEventContext: LPCGUID,) -> HRESULT,
+    fn SetChannelVolumeLevelScalar(
+        nChannel: UINT,
+        fLevel: f32,
+        pguidEventContext: LPCGUID,
+    ) -> HRESULT,
+    fn GetChannelVolumeLevel(nChannel: UINT, pfLevelDB: *mut f32,) -> HRESULT,
+    fn GetChannelVolumeLevelScalar(nChannel: UINT, pfLevel: *mut f32,) -> HRESULT,
+    fn SetMute(bMute: BOOL, pguidEventContext: LPCGUID,) -> HRESULT,
+    fn GetMute(pbMute: *mut BOOL,) -> HRESULT,
+});
+
+// Volume of the default output device in percent, and whether it's muted.
+// Needs COM initialized on the calling thread.
+fn get_volume() -> Option<(u32, bool)> {
+    unsafe {
+        let mut enumerator: *mut mmdeviceapi::IMMDeviceEnumerator = ptr::null_mut();
+        let hr = combaseapi::CoCreateInstance(
+            &mmdeviceapi::CLSID_MMDeviceEnumerator,
+            ptr::null_mut(),
+            combaseapi::CLSCTX_ALL,
+            &mmdeviceapi::IMMDeviceEnumerator::uuidof(),
+            &mut enumerator as *mut _ as *mut LPVOID,
+        );
+        if hr < 0 {
+            return None;
+        }
+
+        let mut device: *mut mmdeviceapi::IMMDevice = ptr::null_mut();
+        let hr = (*enumerator).GetDefaultAudioEndpoint(
+            mmdeviceapi::eRender,
+            mmdeviceapi::eConsole,
+            &mut device,
+        );
+        (*enumerator).Release();
+        if hr < 0 {
+            return None;
+        }
+
+        let mut volume: *mut IAudioEndpointVolume = ptr::null_mut();
+        let hr = (*device).Activate(
+            &IAudioEndpointVolume::uuidof(),
+            combaseapi::CLSCTX_ALL,
+            ptr::null_mut(),
+            &mut volume as *mut _ as *mut LPVOID,
+        );
+        (*device).Release();
+        if hr < 0 {
+            return None;
+        }
+
+        let mut level = 0f32;
+        let mut muted: BOOL = 0;
+        let res = if (*volume).GetMasterVolumeLevelScalar(&mut level) >= 0
+            && (*volume).GetMute(&mut muted) >= 0
+        {
+            Some(((level * 100f32).round() as u32, 0 != muted))
+        } else {
+            None
+        };
+        (*volume).Release();
+
+        res
+    }
+}
+
+// The volume only changes once the media key gets handled, so it's read a little later
+fn show_volume_level() {
+    thread::spawn(|| {
+        let rt = RuntimeContext::init();
+        thread::sleep(time::Duration::from_millis(50));
+        if let Some((percent, muted)) = get_volume() {
+            toast_notification(&media_keys::describe_level(
+                &MEDIA_CONFIG,
+                "Volume",
+                percent,
+                muted,
+            ));
+        }
+        rt.uninit();
+    });
+}
+
+// Not in winapi 0.3.3
+#[repr(C)]
+struct PhysicalMonitor {
+    handle: winnt::HANDLE,
+    _description: [u16; 128],
+}
+
+#[link(name = "dxva2")]
+extern "system" {
+    fn GetNumberOfPhysicalMonitorsFromHMONITOR(
+        hMonitor: HMONITOR,
+        pdwNumberOfPhysicalMonitors: LPDWORD,
+    ) -> BOOL;
+    fn GetPhysicalMonitorsFromHMONITOR(
+        hMonitor: HMONITOR,
+        dwPhysicalMonitorArraySize: DWORD,
+        pPhysicalMonitorArray: *mut PhysicalMonitor,
+    ) -> BOOL;
+    fn DestroyPhysicalMonitors(
+        dwPhysicalMonitorArraySize: DWORD,
+        pPhysicalMonitorArray: *mut PhysicalMonitor,
+    ) -> BOOL;
+    fn GetMonitorBrightness(
+        hMonitor: winnt::HANDLE,
+        pdwMinimumBrightness: LPDWORD,
+        pdwCurrentBrightness: LPDWORD,
+        pdwMaximumBrightness: LPDWORD,
+    ) -> BOOL;
+    fn SetMonitorBrightness(hMonitor: winnt::HANDLE, dwNewBrightness: DWORD) -> BOOL;
+}
+
+// Steps the brightness of the displays behind `monitor`, and returns the new level in percent,
+// or None if none of them supports DDC/CI
+fn step_monitor_brightness(monitor: HMONITOR, up: bool) -> Option<u32> {
+    unsafe {
+        let mut count: DWORD = 0;
+        if 0 == GetNumberOfPhysicalMonitorsFromHMONITOR(monitor, &mut count) || 0 == count {
+            return None;
+        }
+
+        let mut physical: Vec<PhysicalMonitor> = (0..count).map(|_| mem::zeroed()).collect();
+        if 0 == GetPhysicalMonitorsFromHMONITOR(monitor, count, physical.as_mut_ptr()) {
+            return None;
+        }
+
+        let mut res = None;
+        for display in physical.iter() {
+            let (mut min, mut current, mut max) = (0, 0, 0);
+            if 0 == GetMonitorBrightness(display.handle, &mut min, &mut current, &mut max) {
+                continue;
+            }
+
+            let brightness = media_keys::step_brightness(&MEDIA_CONFIG, min, current, max, up);
+            if 0 != SetMonitorBrightness(display.handle, brightness) {
+                res = Some(media_keys::level_percent(min, brightness, max));
+            }
+        }
+
+        DestroyPhysicalMonitors(count, physical.as_mut_ptr());
+        res
+    }
+}
+
+// Changes the brightness of the monitor with the foreground window on it.
+// DDC/CI takes its time, so this runs off the hook thread.
+fn change_brightness(up: bool) {
+    let monitor = unsafe {
+        winuser::MonitorFromWindow(
+            winuser::GetForegroundWindow(),
+            winuser::MONITOR_DEFAULTTOPRIMARY,
+        )
+    } as usize;
+
+    thread::spawn(move || {
+        let rt = RuntimeContext::init();
+        match step_monitor_brightness(monitor as HMONITOR, up) {
+            Some(percent) if MEDIA_CONFIG.show_level => toast_notification(
+                &media_keys::describe_level(&MEDIA_CONFIG, "Brightness", percent, false),
+            ),
+            Some(_) => (),
+            None => toast_notification("This monitor's brightness can't be changed"),
+        }
+        rt.uninit();
+    });
+}
+
+fn is_caps_lock_on() -> bool {
+    0 != unsafe { winuser::GetKeyState(winuser::VK_CAPITAL) } & 1
+}
+
+fn is_num_lock_on() -> bool {
+    0 != unsafe { winuser::GetKeyState(winuser::VK_NUMLOCK) } & 1
+}
+
+fn toggle_num_lock() {
+    InputHookState::send_key(winuser::VK_NUMLOCK as u8, true);
+    InputHookState::send_key(winuser::VK_NUMLOCK as u8, false);
+}
+
+// Numpad block, laid out as on laptops with a built-in one: 7 8 9 / U I O / J K L / M
+// are the digits, with the operators on the column to their right
+fn numpad_block_key(vk: char) -> Option<RemapTarget> {
+    let res = match vk {
+        '7' => key(winuser::VK_NUMPAD7),
+        '8' => key(winuser::VK_NUMPAD8),
+        '9' => key(winuser::VK_NUMPAD9),
+        '0' => key(winuser::VK_DIVIDE),
+        'U' => key(winuser::VK_NUMPAD4),
+        'I' => key(winuser::VK_NUMPAD5),
+        'O' => key(winuser::VK_NUMPAD6),
+        'P' => key(winuser::VK_MULTIPLY),
+        'J' => key(winuser::VK_NUMPAD1),
+        'K' => key(winuser::VK_NUMPAD2),
+        'L' => key(winuser::VK_NUMPAD3),
+        SEMICOLON => key(winuser::VK_SUBTRACT),
+        'M' => key(winuser::VK_NUMPAD0),
+        PERIOD => key(winuser::VK_DECIMAL),
+        FWD_SLASH => key(winuser::VK_ADD),
+        _ => return None,
+    };
+
+    Some(res)
+}
+
+// Caps+T numpad layer: the numpad block, plus Space as another 0 and keys for calculators
+fn numpad_key(vk: char) -> Option<RemapTarget> {
+    let res = match vk {
+        ' ' => key(winuser::VK_NUMPAD0),
+        // = and Enter, Backspace, and Escape to clear
+        PLUS | ENTER => key(winuser::VK_RETURN),
+        'H' => key(winuser::VK_BACK),
+        'Y' => key(winuser::VK_ESCAPE),
+        _ => return numpad_block_key(vk),
+    };
+
+    Some(res)
+}
+
+// The Caps key itself never gets through, so this goes around it
+fn toggle_caps_lock() {
+    InputHookState::send_key(winuser::VK_CAPITAL as u8, true);
+    InputHookState::send_key(winuser::VK_CAPITAL as u8, false);
+}
+
+// Feeds a character about to be typed into the text expander. Returns true if that completed an
+// abbreviation, which then got replaced, so the character mustn't be typed.
+fn expand_text(expander: &Mutex<TextExpander>, c: char) -> bool {
+    let snippet = match expander.lock().unwrap().typed(SNIPPETS, c) {
+        Some(snippet) => snippet,
+        None => return false,
+    };
+
+    let (date, time) = get_local_date_time();
+    let clipboard = if snippet.expansion.contains(CLIPBOARD_PLACEHOLDER) {
+        get_clipboard_text().unwrap_or_default()
+    } else {
+        String::new()
+    };
+
+    let expansion = text_expander::expand(
+        snippet,
+        &Placeholders {
+            date,
+            time,
+            clipboard,
+        },
+    );
+
+    for _ in 0..expansion.backspaces {
+        InputHookState::send_key(winuser::VK_BACK as u8, true);
+        InputHookState::send_key(winuser::VK_BACK as u8, false);
+    }
+
+    InputHookState::send_text(&expansion.text);
+
+    for _ in 0..expansion.cursor_back {
+        InputHookState::send_key(winuser::VK_LEFT as u8, true);
+        InputHookState::send_key(winuser::VK_LEFT as u8, false);
+    }
+
+    true
+}
+
+// Held back keys only reach the text expander now, as what they end up typing
+fn send_auto_shift_tap(tap: AutoShiftTap, expander: &Mutex<TextExpander>) {
+    match get_typed_char(tap.vk, tap.shifted) {
+        Some(c) if expand_text(expander, c) => return,
+        Some(_) => (),
+        None => expander.lock().unwrap().reset(),
+    }
+
+    if tap.shifted {
+        InputHookState::send_key(winuser::VK_SHIFT as u8, true);
+    }
+
+    InputHookState::send_key(tap.vk, true);
+    InputHookState::send_key(tap.vk, false);
+
+    if tap.shifted {
+        InputHookState::send_key(winuser::VK_SHIFT as u8, false);
+    }
+}
+
+fn send_mouse_button(button: MouseButton, down: bool) {
+    let flags = match (button, down) {
+        (MouseButton::Left, true) => winuser::MOUSEEVENTF_LEFTDOWN,
+        (MouseButton::Left, false) => winuser::MOUSEEVENTF_LEFTUP,
+        (MouseButton::Right, true) => winuser::MOUSEEVENTF_RIGHTDOWN,
+        (MouseButton::Right, false) => winuser::MOUSEEVENTF_RIGHTUP,
+        (MouseButton::Middle, true) => winuser::MOUSEEVENTF_MIDDLEDOWN,
+        (MouseButton::Middle, false) => winuser::MOUSEEVENTF_MIDDLEUP,
+    };
+
+    send_mouse_input(flags, (0, 0), 0);
+}
+
+fn send_mouse_motion(motion: MouseKeysMotion) {
+    if motion.pointer != (0, 0) {
+        send_mouse_input(winuser::MOUSEEVENTF_MOVE, motion.pointer, 0);
+    }
+
+    if motion.wheel.0 != 0 {
+        send_mouse_input(winuser::MOUSEEVENTF_HWHEEL, (0, 0), motion.wheel.0);
+    }
+
+    if motion.wheel.1 != 0 {
+        send_mouse_input(winuser::MOUSEEVENTF_WHEEL, (0, 0), motion.wheel.1);
+    }
+}
+
+// LowLevelHooksTimeout, in milliseconds. Newer Windows caps it at a second; when it's not set,
+// assume the older 300 ms default, to warn early rather than late.
+fn get_hook_timeout_ms() -> u32 {
+    let sub_key = to_wide("Control Panel\\Desktop");
+    let value = to_wide("LowLevelHooksTimeout");
+    let mut timeout: DWORD = 0;
+    let mut size = mem::size_of::<DWORD>() as DWORD;
+
+    let res = unsafe {
+        winreg::RegGetValueW(
+            winreg::HKEY_CURRENT_USER,
+            sub_key.as_ptr(),
+            value.as_ptr(),
+            winreg::RRF_RT_REG_DWORD,
+            ptr::null_mut(),
+            &mut timeout as *mut DWORD as *mut _,
+            &mut size,
+        )
+    };
+
+    if 0 == res && timeout > 0 {
+        cmp::min(timeout, 1000)
+    } else {
+        300
+    }
+}
+
+// Shared by all threads, so SendInput calls from the timer thread get counted too; None until
+// run() sets it up
+static mut LATENCY: Option<Mutex<Latency>> = None;
+
+// `what` describes the event, for slow ones
+fn record_latency<F: FnOnce() -> String>(probe: Probe, start: time::Instant, what: F) {
+    if !LATENCY_CONFIG.enabled {
+        return;
+    }
+
+    let elapsed = start.elapsed();
+    let us = cmp::min(
+        elapsed.as_secs() * 1_000_000 + elapsed.subsec_micros() as u64,
+        u32::max_value() as u64,
+    ) as u32;
+
+    let latency = match unsafe { LATENCY.as_ref() } {
+        Some(latency) => latency,
+        None => return,
+    };
+    let warning = latency
+        .lock()
+        .unwrap()
+        .record(&LATENCY_CONFIG, probe, us, || {
+            let (date, time) = get_local_date_time();
+            format!("{} {} {}", date, time, what())
+        });
+
+    if let Some(warning) = warning {
+        log(Level::Warn, Category::Hooks, || warning.clone());
+
+        post_toast_notification(warning);
+    }
+}
+
+fn describe_latency() -> String {
+    unsafe { LATENCY.as_ref() }.map_or(String::new(), |latency| latency.lock().unwrap().describe())
+}
+
+// Returns the path of the full report
+fn export_latency_report() -> Option<PathBuf> {
+    let report = unsafe { LATENCY.as_ref() }?.lock().unwrap().report();
+    write_data_file("latency.txt", &report)
+}
+
+// The summary, and where the full report went; for the admin layer and `h3keys3 latency`
+fn latency_report() -> String {
+    if !LATENCY_CONFIG.enabled {
+        return "Latency instrumentation is off".to_owned();
+    }
+
+    let summary = describe_latency();
+    match export_latency_report() {
+        Some(path) => format!("{}\n{}", summary, path.display()),
+        None => summary,
+    }
+}
+
+fn send_input<F: FnOnce() -> String>(mut input: winuser::INPUT, what: F) {
+    let start = time::Instant::now();
+    unsafe {
+        winuser::SendInput(1, &mut input, mem::size_of::<winuser::INPUT>() as i32);
+    }
+    record_latency(Probe::SendInput, start, what);
+}
+
+// `data` is the wheel delta for wheel events
+fn send_mouse_input(flags: DWORD, (x, y): (i32, i32), data: i32) {
+    unsafe {
+        let mut input = winuser::INPUT {
+            type_: winuser::INPUT_MOUSE,
+            u: mem::uninitialized(),
+        };
+
+        *input.u.mi_mut() = winuser::MOUSEINPUT {
+            dx: x,
+            dy: y,
+            mouseData: data as DWORD,
+            dwFlags: flags,
+            time: 0,
+            dwExtraInfo: H3KEYS_MAGIC,
+        };
+
+        send_input(input, || format!("mouse 0x{:04X}", flags));
+    }
+}
+
+struct InputHookState {
+    colemak_on: bool,
+
+    mod1_on: bool,
+    mod2_on: bool,
+    ctrlmod_on: bool,
+    winkey_on: bool,
+    admin_on: bool,
+
+    leftalt_on: bool,
+    leftctrl_on: bool,
+
+    // Everything passes through untouched, except for the resume chord
+    suspended: bool,
+    suspend_chord: SuspendChord,
+
+    window_move_hwnd: HWND,
+    mouse_move_from: (i32, i32),
+    // Point of the window under the cursor, relative to its size
+    window_move_grab: (f32, f32),
+
+    window_resize_hwnd: HWND,
+    mouse_resize_from: (i32, i32),
+    window_resize_from: Rect,
+    window_resize_edges: ResizeEdges,
+    // DPI of the monitor under the cursor
+    window_resize_dpi: u32,
+
+    // What the window being moved or resized can stick to, collected when it's grabbed
+    snap_monitors: Vec<Monitor>,
+    snap_windows: Arc<Mutex<SnapWindows>>,
+
+    scroll_emu_state: Arc<Mutex<ScrollEmuState>>,
+    // Of the windows scrolled in, for their multipliers
+    scroll_process_names: ProcessNameCache,
+
+    mouse_keys_on: bool,
+    mouse_keys_state: Arc<Mutex<MouseKeysState>>,
+
+    window_layer_on: bool,
+    window_nudge_on: bool,
+
+    window_switcher: Option<WindowSwitcher<HWND>>,
+    window_switcher_shift_on: bool,
+    // Keys typed into the switcher before its toast got refreshed share one refresh
+    window_switcher_refresh_posted: bool,
+
+    media_layer_on: bool,
+
+    // Caps+T held, and Caps+R toggled
+    numpad_layer_on: bool,
+    numpad_locked: bool,
+    // NumLock got turned on for the numpad layer, and needs turning back off after
+    num_lock_forced: bool,
+
+    clipboard_history: Arc<Mutex<ClipboardHistory>>,
+
+    auto_shift_state: Arc<Mutex<AutoShiftState>>,
+
+    caps_word: CapsWord,
+    // Caps went down at this time, and nothing else has been pressed since
+    caps_tap_from: Option<u32>,
+
+    tap_dance_state: Arc<Mutex<TapDanceState>>,
+
+    vim_nav: VimNav,
+
+    // Also fed the keys auto-shift types, by the timer thread among others
+    text_expander: Arc<Mutex<TextExpander>>,
+    // Window the characters in the expander's buffer were typed into
+    text_expander_hwnd: HWND,
+
+    mod1_keys_down: HashSet<i32>,
+
+    debounce: Arc<Mutex<Debounce>>,
+
+    // Saved every so often by the timer thread
+    typing_stats: Arc<Mutex<TypingStats>>,
+
+    // Keyboard each keystroke came from, as far as Raw Input tells
+    keyboard_tracker: KeyboardTracker,
+    // Whether the device rules remap each keyboard seen so far
+    keyboards_remapped: HashMap<usize, bool>,
+    // Pressed on keyboards which don't get remapped, so their releases go through as well
+    pass_through_keys: HashSet<u8>,
+
+    // Null when not installed
+    key_hook_handle: HHOOK,
+    mouse_hook_handle: HHOOK,
+    hook_health: HookHealth,
+}
+
+impl InputHookState {
+    fn new() -> InputHookState {
+        InputHookState {
+            colemak_on: true,
+
+            mod1_on: false,
+            mod2_on: false,
+            ctrlmod_on: false,
+            winkey_on: false,
+            admin_on: false, // global option control mode, colemak enable/disable, etc.
+
+            leftalt_on: false,
+            leftctrl_on: false,
+
+            suspended: false,
+            suspend_chord: SuspendChord::new(),
+
+            window_move_hwnd: ptr::null_mut(),
+            mouse_move_from: (0, 0),
+            window_move_grab: (0f32, 0f32),
+
+            window_resize_hwnd: ptr::null_mut(),
+            mouse_resize_from: (0, 0),
+            window_resize_from: Rect::new(0, 0, 0, 0),
+            window_resize_edges: ResizeEdges::default(),
+            window_resize_dpi: 96,
+
+            snap_monitors: Vec::new(),
+            snap_windows: Arc::new(Mutex::new(SnapWindows {
+                grabbed: 0,
+                rects: Vec::new(),
+            })),
+
+            scroll_emu_state: Arc::new(Mutex::new(ScrollEmuState::new())),
+            scroll_process_names: HashMap::new(),
+
+            mouse_keys_on: false,
+            mouse_keys_state: Arc::new(Mutex::new(MouseKeysState::new())),
+
+            window_layer_on: false,
+            window_nudge_on: false,
+
+            window_switcher: None,
+            window_switcher_shift_on: false,
+            window_switcher_refresh_posted: false,
+
+            media_layer_on: false,
+
+            numpad_layer_on: false,
+            numpad_locked: false,
+            num_lock_forced: false,
+
+            clipboard_history: Arc::new(Mutex::new(load_clipboard_history())),
+
+            auto_shift_state: Arc::new(Mutex::new(AutoShiftState::new())),
+
+            caps_word: CapsWord::new(),
+            caps_tap_from: None,
+
+            tap_dance_state: Arc::new(Mutex::new(TapDanceState::new())),
+
+            vim_nav: VimNav::new(),
+
+            text_expander: Arc::new(Mutex::new(TextExpander::new())),
+            text_expander_hwnd: ptr::null_mut(),
+
+            mod1_keys_down: HashSet::new(),
+
+            debounce: Arc::new(Mutex::new(load_debounce_stats())),
+
+            typing_stats: Arc::new(Mutex::new(load_typing_stats())),
+
+            keyboard_tracker: KeyboardTracker::new(),
+            keyboards_remapped: HashMap::new(),
+            pass_through_keys: HashSet::new(),
+
+            key_hook_handle: ptr::null_mut(),
+            mouse_hook_handle: ptr::null_mut(),
+            hook_health: HookHealth::new(unsafe { sysinfoapi::GetTickCount() }),
+        }
+    }
+
+    fn extended_key_flag(key: i32) -> DWORD {
+        match key {
+            winuser::VK_UP | winuser::VK_DOWN | winuser::VK_LEFT | winuser::VK_RIGHT => {
+                winuser::KEYEVENTF_EXTENDEDKEY
+            }
+            // Otherwise they'd be taken for / on the main keyboard, and Pause
+            winuser::VK_DIVIDE | winuser::VK_NUMLOCK => winuser::KEYEVENTF_EXTENDEDKEY,
+            // Browser, volume, media and app launch keys
+            winuser::VK_BROWSER_BACK..=winuser::VK_LAUNCH_APP2 => winuser::KEYEVENTF_EXTENDEDKEY,
+            _ => 0,
+        }
+    }
+
+    fn send_key(key: u8, down: bool) {
+        unsafe {
+            let mut input = winuser::INPUT {
+                type_: winuser::INPUT_KEYBOARD,
+                u: mem::uninitialized(),
+            };
+
+            let ext_flag = Self::extended_key_flag(key as _);
+            let scancode = winuser::MapVirtualKeyA(key as u32, winuser::MAPVK_VK_TO_VSC) as u16;
+
+            *input.u.ki_mut() = winuser::KEYBDINPUT {
+                wVk: key as u16,
+                wScan: scancode,
+                dwFlags: ext_flag | if down { 0 } else { winuser::KEYEVENTF_KEYUP },
+                time: 0,
+                dwExtraInfo: H3KEYS_MAGIC,
+            };
+
+            send_input(input, || {
+                format!("key 0x{:02X} {}", key, if down { "down" } else { "up" })
+            });
+        }
+
+        //unsafe { winuser::keybd_event(key, 0, if down {0} else {winuser::KEYEVENTF_KEYUP}, H3KEYS_MAGIC); }
+    }
+
+    // Types out text regardless of the keyboard layout, with "\n" as Enter
+    fn send_text(text: &str) {
+        for c in text.chars() {
+            if '\n' == c {
+                Self::send_key(winuser::VK_RETURN as u8, true);
+                Self::send_key(winuser::VK_RETURN as u8, false);
+                continue;
+            }
+
+            let mut units = [0u16; 2];
+            for &unit in c.encode_utf16(&mut units).iter() {
+                for &down in &[true, false] {
+                    unsafe {
+                        let mut input = winuser::INPUT {
+                            type_: winuser::INPUT_KEYBOARD,
+                            u: mem::uninitialized(),
+                        };
+
+                        *input.u.ki_mut() = winuser::KEYBDINPUT {
+                            wVk: 0,
+                            wScan: unit,
+                            dwFlags: winuser::KEYEVENTF_UNICODE
+                                | if down { 0 } else { winuser::KEYEVENTF_KEYUP },
+                            time: 0,
+                            dwExtraInfo: H3KEYS_MAGIC,
+                        };
+
+                        send_input(input, || format!("text 0x{:04X}", unit));
+                    }
+                }
+            }
+        }
+    }
+
+    // Releases the keys held down through the Caps layer, Ctrl from Caps+F included
+    fn release_mod1_keys(&mut self) {
+        for &key in self.mod1_keys_down.iter() {
+            Self::send_key(key as u8, false);
+        }
+        self.mod1_keys_down.clear();
+        self.ctrlmod_on = false;
+    }
+
+    // Make sure all remapped keys get released, and any mouse action in progress ends
+    fn release_layers(&mut self) {
+        self.release_mod1_keys();
+        self.window_move_hwnd = ptr::null_mut();
+        self.window_resize_hwnd = ptr::null_mut();
+        self.admin_on = false;
+        self.release_mouse_keys();
+        self.window_layer_on = false;
+        self.window_nudge_on = false;
+        self.media_layer_on = false;
+        self.numpad_layer_on = false;
+        self.update_num_lock();
+        self.close_window_switcher(false);
+        self.clipboard_history.lock().unwrap().cancel();
+
+        let now = unsafe { sysinfoapi::GetTickCount() };
+        let dance = self
+            .tap_dance_state
+            .lock()
+            .unwrap()
+            .finish(&TAP_DANCE_CONFIG, now);
+        if let Some((key, dance)) = dance {
+            send_remap_tap(caps_tap_dance_action(key, dance));
+        }
+
+        // A drag in progress stops right away, but inertia is left to run out
+        let scroll = &mut self.scroll_emu_state.lock().unwrap();
+        if scroll.scroll_emu_on {
+            scroll.stop();
+        }
+    }
+
+    // Caps+E layer: keyboard window placement. The right hand keys map onto the screen:
+    // U I O / J K L / M , . are the quarters, halves and center, and 7 8 9 the thirds.
+    // Holding F turns I J K L into nudges.
+    fn window_layer_remap(&mut self, vk: char, key_pressed: bool) -> RemapTarget {
+        match vk {
+            'E' => {
+                if !key_pressed {
+                    self.window_layer_on = false;
+                    self.window_nudge_on = false;
+                }
+                return RemapTarget::Block;
+            }
+            'F' => {
+                self.window_nudge_on = key_pressed;
+                return RemapTarget::Block;
+            }
+            LEFTALT | ALT | CTRL => return key(0), // pass-through
+            _ => (),
+        }
+
+        if !key_pressed {
+            return RemapTarget::Block;
+        }
+
+        let command = if self.window_nudge_on {
+            match vk {
+                'J' => Some(WindowCommand::Nudge(-NUDGE_STEP, 0)),
+                'L' => Some(WindowCommand::Nudge(NUDGE_STEP, 0)),
+                'I' => Some(WindowCommand::Nudge(0, -NUDGE_STEP)),
+                'K' => Some(WindowCommand::Nudge(0, NUDGE_STEP)),
+                _ => None,
+            }
+        } else {
+            match vk {
+                'U' => Some(WindowCommand::Tile(Tile::TopLeft)),
+                'I' => Some(WindowCommand::Tile(Tile::TopHalf)),
+                'O' => Some(WindowCommand::Tile(Tile::TopRight)),
+                'J' => Some(WindowCommand::Tile(Tile::LeftHalf)),
+                'K' => Some(WindowCommand::Center),
+                'L' => Some(WindowCommand::Tile(Tile::RightHalf)),
+                'M' => Some(WindowCommand::Tile(Tile::BottomLeft)),
+                COMMA => Some(WindowCommand::Tile(Tile::BottomHalf)),
+                PERIOD => Some(WindowCommand::Tile(Tile::BottomRight)),
+                '7' => Some(WindowCommand::Tile(Tile::LeftThird)),
+                '8' => Some(WindowCommand::Tile(Tile::CenterThird)),
+                '9' => Some(WindowCommand::Tile(Tile::RightThird)),
+                SEMICOLON => Some(WindowCommand::ToggleMaximize),
+                'H' => Some(WindowCommand::CycleLayout),
+                'N' => Some(WindowCommand::NextMonitor),
+                _ => None,
+            }
+        };
+
+        if let Some(command) = command {
+            log(Level::Info, Category::Window, || format!("{:?}", command));
+            window_manager::run_command(&mut DesktopWindowManager, command);
+        }
+
+        RemapTarget::Block
+    }
+
+    // BlindKey targets of the Caps layer are remembered, so they can be released along with Caps
+    fn track_mod1_key(&mut self, target: &RemapTarget, key_pressed: bool) {
+        if let RemapTarget::BlindKey(k) = *target {
+            if key_pressed {
+                self.mod1_keys_down.insert(k);
+            } else {
+                self.mod1_keys_down.remove(&k);
+            }
+        }
+    }
+
+    // The numpad keys only type digits with NumLock on
+    fn update_num_lock(&mut self) {
+        let numpad_on = self.numpad_layer_on || self.numpad_locked;
+
+        if numpad_on && !is_num_lock_on() {
+            toggle_num_lock();
+            self.num_lock_forced = true;
+        } else if !numpad_on && self.num_lock_forced {
+            if is_num_lock_on() {
+                toggle_num_lock();
+            }
+            self.num_lock_forced = false;
+        }
+    }
+
+    // Caps+T numpad layer, while held. Anything not on it is swallowed.
+    fn numpad_layer_remap(&mut self, vk: char, key_pressed: bool) -> RemapTarget {
+        match vk {
+            'T' => {
+                if !key_pressed {
+                    self.numpad_layer_on = false;
+                    self.update_num_lock();
+                }
+                RemapTarget::Block
+            }
+            LEFTSHIFT | RIGHTSHIFT | LEFTALT | ALT | CTRL => key(0), // pass-through
+            _ => numpad_key(vk).unwrap_or(RemapTarget::Block),
+        }
+    }
+
+    // Caps+A media layer, see media_keys::media_key
+    fn media_layer_remap(&mut self, vk: char, key_pressed: bool) -> RemapTarget {
+        match vk {
+            'A' => {
+                if !key_pressed {
+                    self.media_layer_on = false;
+                }
+                RemapTarget::Block
+            }
+            LEFTSHIFT | RIGHTSHIFT | LEFTALT | ALT | CTRL => key(0), // pass-through
+            _ => match media_keys::media_key(vk as u8) {
+                Some(MediaKey::Key(vk)) => key(i32::from(vk)),
+                Some(MediaKey::Volume(vk)) => {
+                    if key_pressed && MEDIA_CONFIG.show_level {
+                        show_volume_level();
+                    }
+                    key(i32::from(vk))
+                }
+                Some(MediaKey::Brightness(up)) => {
+                    if key_pressed {
+                        change_brightness(up);
+                    }
+                    RemapTarget::Block
+                }
+                None => RemapTarget::Block,
+            },
+        }
+    }
+
+    fn open_window_switcher(&mut self) {
+        self.window_switcher = Some(WindowSwitcher::new(get_switcher_windows()));
+        self.refresh_window_switcher();
+    }
+
+    // Showing a toast takes long enough to risk the hook timeout, so it's left to the window
+    fn refresh_window_switcher(&mut self) {
+        if self.window_switcher_refresh_posted {
+            return;
+        }
+
+        self.window_switcher_refresh_posted = post_message(WM_REFRESH_SWITCHER, 0);
+    }
+
+    // WM_REFRESH_SWITCHER
+    fn show_window_switcher(&mut self) {
+        self.window_switcher_refresh_posted = false;
+
+        // Closed in the meantime
+        if let Some(ref switcher) = self.window_switcher {
+            toast_notification(&switcher.describe(&SWITCHER_CONFIG));
+        }
+    }
+
+    fn close_window_switcher(&mut self, activate: bool) {
+        self.window_switcher_shift_on = false;
+
+        if let Some(switcher) = self.window_switcher.take() {
+            hide_toast_notification();
+
+            if activate {
+                if let Some(w) = switcher.selection() {
+                    activate_window(w);
+                }
+            }
+        }
+    }
+
+    // Caps+Tab window switcher. Letters and digits narrow down the list, Tab and Shift+Tab
+    // move the selection, Backspace edits the filter, Enter switches right away and Escape cancels.
+    // Releasing Caps switches to the selection.
+    fn window_switcher_remap(&mut self, vk: char, key_pressed: bool) -> RemapTarget {
+        match vk {
+            LEFTSHIFT | RIGHTSHIFT => {
+                self.window_switcher_shift_on = key_pressed;
+                return RemapTarget::Block;
+            }
+            LEFTALT | ALT | CTRL => return key(0), // pass-through
+            _ => (),
+        }
+
+        if !key_pressed {
+            return RemapTarget::Block;
+        }
+
+        match vk {
+            ESCAPE => {
+                self.close_window_switcher(false);
+                return RemapTarget::Block;
+            }
+            ENTER => {
+                self.close_window_switcher(true);
+                return RemapTarget::Block;
+            }
+            _ => (),
+        }
+
+        // Filter with the letters as typed, not as they sit on the keyboard
+        let typed = match remap_colemak(vk as u8) {
+            remapped if self.colemak_on && remapped != 0 => remapped as u8 as char,
+            _ => vk,
+        };
+
+        let shift_on = self.window_switcher_shift_on;
+        if let Some(ref mut switcher) = self.window_switcher {
+            match vk {
+                TAB if shift_on => switcher.select_prev(),
+                TAB => switcher.select_next(),
+                BACKSPACE => switcher.pop_char(),
+                _ => match typed {
+                    'A'..='Z' | '0'..='9' | ' ' => switcher.push_char(typed.to_ascii_lowercase()),
+                    _ => return RemapTarget::Block,
+                },
+            }
+        }
+
+        self.refresh_window_switcher();
+        RemapTarget::Block
+    }
+
+    // Feeds what a key press is about to type into the text expander. Returns true if that
+    // completed an abbreviation, in which case it's been replaced, and the key must be swallowed.
+    fn text_expander_key(&mut self, vk: u8, remap: &RemapTarget) -> bool {
+        let vk = match *remap {
+            RemapTarget::BlindKey(0) => vk,
+            RemapTarget::BlindKey(k) => k as u8,
+            RemapTarget::KeySeq(_) | RemapTarget::Block => {
+                self.text_expander.lock().unwrap().reset();
+                return false;
+            }
+        };
+
+        match vk as i32 {
+            winuser::VK_BACK => {
+                self.text_expander.lock().unwrap().backspace();
+                return false;
+            }
+            winuser::VK_SHIFT | winuser::VK_LSHIFT | winuser::VK_RSHIFT => return false,
+            _ => (),
+        }
+
+        // Layer keys, shortcuts and navigation can all move the caret
+        let shortcut_on = is_key_down(winuser::VK_CONTROL) || is_key_down(winuser::VK_MENU);
+        if self.mod1_on || self.mod2_on || self.winkey_on || shortcut_on {
+            self.text_expander.lock().unwrap().reset();
+            return false;
+        }
+
+        let hwnd = unsafe { winuser::GetForegroundWindow() };
+        if hwnd != self.text_expander_hwnd {
+            self.text_expander.lock().unwrap().reset();
+            self.text_expander_hwnd = hwnd;
+        }
+
+        match get_typed_char(vk, is_key_down(winuser::VK_SHIFT)) {
+            Some(c) => expand_text(&self.text_expander, c),
+            None => {
+                self.text_expander.lock().unwrap().reset();
+                false
+            }
+        }
+    }
+
+    // WM_INPUT; `time` is the message's
+    fn raw_keyboard_input(&mut self, input: winuser::HRAWINPUT, time: u32) {
+        if let Some((keyboard, vk, pressed)) = get_raw_input_key(input) {
+            self.keyboard_tracker
+                .raw_key(keyboard as usize, vk, pressed, time);
+        }
+    }
+
+    // Raw Input only reports a keystroke after the low-level hook has seen it, so the hook goes
+    // by the keyboard which sent the latest one it could match up. Releases follow whatever their
+    // presses did.
+    fn is_key_passed_through(&mut self, vk: u8, key_pressed: bool, time: u32) -> bool {
+        let keyboard = self.keyboard_tracker.hook_key(vk, key_pressed, time);
+        if !key_pressed {
+            return self.pass_through_keys.remove(&vk);
+        }
+
+        let remapped = match keyboard {
+            None => true,
+            Some(keyboard) => *self.keyboards_remapped.entry(keyboard).or_insert_with(|| {
+                let info = get_raw_input_device_info(keyboard as winnt::HANDLE);
+                let remapped = device_rules::is_device_remapped(DEVICE_RULES, &info);
+                log(Level::Info, Category::Hooks, || {
+                    format!("Keyboard {}, remapped: {}", info.path, remapped)
+                });
+                remapped
+            }),
+        };
+
+        if remapped {
+            self.pass_through_keys.remove(&vk);
+        } else {
+            self.pass_through_keys.insert(vk);
+        }
+
+        !remapped
+    }
+
+    // WM_CLIPBOARDUPDATE
+    fn clipboard_updated(&mut self) {
+        if is_clipboard_private() {
+            return;
+        }
+
+        let text = match SystemClipboard.get_text() {
+            Some(text) => text,
+            None => return,
+        };
+
+        let owner = unsafe { winuser::GetClipboardOwner() };
+        let source_app = if owner == ptr::null_mut() {
+            None
+        } else {
+            get_window_process_name(owner)
+        };
+
+        // Saved by the timer thread once copying stops for a bit
+        self.clipboard_history.lock().unwrap().record(
+            &CLIPBOARD_HISTORY_CONFIG,
+            &text,
+            source_app.as_ref().map(|app| &app[..]),
+        );
+    }
+
+    // Puts the entry picked with Caps+B on the clipboard, and pastes it
+    fn paste_clipboard_selection(&mut self) {
+        let pasted = self
+            .clipboard_history
+            .lock()
+            .unwrap()
+            .paste_selection(&mut SystemClipboard);
+        if pasted {
+            post_hide_toast_notification();
+            Self::send_key(winuser::VK_CONTROL as u8, true);
+            Self::send_key(b'V', true);
+            Self::send_key(b'V', false);
+            Self::send_key(winuser::VK_CONTROL as u8, false);
+        }
+    }
+
+    // Caps+B cycles through the clipboard history. While cycling, B goes further back, N forward,
+    // P pins or unpins, Enter pastes right away and Escape cancels. Releasing Caps pastes.
+    fn clipboard_history_remap(&mut self, vk: char, key_pressed: bool) -> RemapTarget {
+        match vk {
+            LEFTALT | ALT | CTRL => return key(0), // pass-through
+            _ => (),
+        }
+
+        if !key_pressed {
+            return RemapTarget::Block;
+        }
+
+        if ENTER == vk {
+            self.paste_clipboard_selection();
+            return RemapTarget::Block;
+        }
+
+        let mut history = self.clipboard_history.lock().unwrap();
+        match vk {
+            'B' => history.select_older(),
+            'N' => history.select_newer(),
+            'P' => {
+                history.toggle_pin();
+            }
+            ESCAPE => {
+                history.cancel();
+                post_hide_toast_notification();
+                return RemapTarget::Block;
+            }
+            _ => return RemapTarget::Block,
+        }
+
+        post_toast_notification(history.describe(&CLIPBOARD_HISTORY_CONFIG));
+        RemapTarget::Block
+    }
+
+    // Returns true if auto-shift took the key event; it's either held back,
+    // or the release of a held back key which got typed just now
+    fn auto_shift_key(
+        &mut self,
+        vk: u8,
+        remap: &RemapTarget,
+        key_pressed: bool,
+        time: u32,
+    ) -> bool {
+        let auto_shift = &mut self.auto_shift_state.lock().unwrap();
+
+        if !key_pressed {
+            return match auto_shift.release(vk, time) {
+                Some(tap) => {
+                    if let Some(tap) = tap {
+                        send_auto_shift_tap(tap, &self.text_expander);
+                    }
+                    true
+                }
+                None => false,
+            };
+        }
+
+        let layer = if self.mod1_on {
+            return false;
+        } else if self.mod2_on {
+            AutoShiftLayer::Pipe
+        } else {
+            AutoShiftLayer::Base
+        };
+
+        let output = match *remap {
+            RemapTarget::BlindKey(0) => vk,
+            RemapTarget::BlindKey(k) => k as u8,
+            RemapTarget::KeySeq(_) | RemapTarget::Block => return false,
+        };
+
+        // Real modifiers turn it off, so shortcuts work as usual
+        let modifiers_on = self.winkey_on
+            || [winuser::VK_SHIFT, winuser::VK_CONTROL, winuser::VK_MENU]
+                .iter()
+                .any(|&k| is_key_down(k));
+
+        let timeout = if modifiers_on {
+            None
+        } else {
+            auto_shift::timeout_for(&AUTO_SHIFT_CONFIG, layer, output)
+        };
+
+        auto_shift.press(vk, output, timeout, time)
+    }
+
+    fn caps_tap_dance_key(&mut self, vk: char, max_taps: u32, key_pressed: bool, time: u32) {
+        let tap_dance = &mut self.tap_dance_state.lock().unwrap();
+
+        if key_pressed {
+            tap_dance.press(vk, max_taps, time);
+        } else if let Some(dance) = tap_dance.release(&TAP_DANCE_CONFIG, vk, time) {
+            send_remap_tap(caps_tap_dance_action(vk, dance));
+        }
+    }
+
+    // Returns true if caps word typed the key itself, shifted
+    fn caps_word_key(&mut self, vk: u8, remap: &RemapTarget) -> bool {
+        if !self.caps_word.is_on() {
+            return false;
+        }
+
+        let (vk, shifted) = match typed_key(vk, remap) {
+            Some(typed) => typed,
+            None => {
+                self.caps_word.set(false);
+                return false;
+            }
+        };
+
+        // Boundaries go by what's typed, so they follow Colemak and the keyboard layout
+        match self.caps_word.key(vk, get_typed_char(vk, shifted)) {
+            CapsWordAction::Shift if !shifted => {
+                // Only the press needs Shift; the release goes through as usual
+                Self::send_key(winuser::VK_SHIFT as u8, true);
+                Self::send_key(vk, true);
+                Self::send_key(winuser::VK_SHIFT as u8, false);
+                true
+            }
+            CapsWordAction::Underscore => {
+                Self::send_text("_");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Caps+G navigation mode: vim-style motions, counts, visual mode and operators, typed as
+    // the usual editing keys. Keys without a binding are swallowed, unless part of a shortcut.
+    fn vim_nav_remap(&mut self, vk: char, key_pressed: bool, remap: RemapTarget) -> RemapTarget {
+        match vk {
+            LEFTSHIFT | RIGHTSHIFT | LEFTCTRL | RIGHTCTRL | LEFTALT | ALT | CTRL => return remap,
+            _ if !key_pressed => return remap,
+            _ => (),
+        }
+
+        if is_key_down(winuser::VK_MENU) {
+            return remap;
+        }
+
+        // Motions go by what's typed, so they follow Colemak and the keyboard layout
+        let typed = match typed_key(vk as u8, &remap) {
+            Some((k, _)) if winuser::VK_ESCAPE as u8 == k => Some(vim_nav::ESCAPE),
+            Some((k, shifted)) => get_typed_char(k, shifted),
+            None => None,
+        };
+
+        let ctrl_on = is_key_down(winuser::VK_CONTROL);
+        let mode = self.vim_nav.mode();
+        match typed.and_then(|c| self.vim_nav.key(c, ctrl_on)) {
+            Some(strokes) => {
+                for stroke in strokes {
+                    send_nav_stroke(stroke);
+                }
+            }
+            None if ctrl_on => return remap,
+            None => (),
+        }
+
+        if self.vim_nav.mode() != mode {
+            post_toast_notification(self.vim_nav.describe().to_owned());
+        }
+
+        RemapTarget::Block
+    }
+
+    fn release_mouse_keys(&mut self) {
+        self.mouse_keys_on = false;
+
+        let buttons = self.mouse_keys_state.lock().unwrap().release_all();
+        for button in buttons {
+            send_mouse_button(button, false);
+        }
+    }
+
+    // Caps+W layer: pointer motion, mouse buttons and wheel
+    fn mouse_keys_remap(&mut self, vk: char, key_pressed: bool) -> RemapTarget {
+        if 'W' == vk {
+            if !key_pressed {
+                self.release_mouse_keys();
+            }
+            return RemapTarget::Block;
+        }
+
+        let mouse_keys = &mut self.mouse_keys_state.lock().unwrap();
+
+        let button = match vk {
+            'U' => Some(MouseButton::Left),
+            'O' => Some(MouseButton::Right),
+            'M' => Some(MouseButton::Middle),
+            _ => None,
+        };
+
+        if let Some(button) = button {
+            // Holding the key down holds the button, which is how drags are done
+            if key_pressed {
+                if mouse_keys.press_button(button) {
+                    send_mouse_button(button, true);
+                }
+            } else if mouse_keys.release_button(button) {
+                send_mouse_button(button, false);
+            }
+
+            return RemapTarget::Block;
+        }
+
+        let held = &mut mouse_keys.held;
+        match vk {
+            'J' => held.left = key_pressed,
+            'L' => held.right = key_pressed,
+            'I' => held.up = key_pressed,
+            'K' => held.down = key_pressed,
+            'Y' => held.scroll_up = key_pressed,
+            'N' => held.scroll_down = key_pressed,
+            'H' => held.scroll_left = key_pressed,
+            SEMICOLON => held.scroll_right = key_pressed,
+            'D' => held.precision = key_pressed,
+            // Modifiers pass through, so that ctrl-click and friends work
+            LEFTALT | ALT | CTRL => return key(0),
+            _ => (),
+        }
+
+        RemapTarget::Block
+    }
+
+    // Replaces the given hooks, or installs them for the first time. Returns false if any failed.
+    fn install_hooks(&mut self, keyboard: bool, mouse: bool) -> bool {
+        unsafe {
+            if keyboard {
+                if !self.key_hook_handle.is_null() {
+                    winuser::UnhookWindowsHookEx(self.key_hook_handle);
+                }
+                self.key_hook_handle = install_hook(winuser::WH_KEYBOARD_LL, Some(global_key_hook));
+            }
+
+            if mouse {
+                if !self.mouse_hook_handle.is_null() {
+                    winuser::UnhookWindowsHookEx(self.mouse_hook_handle);
+                }
+                self.mouse_hook_handle =
+                    install_hook(winuser::WH_MOUSE_LL, Some(global_mouse_hook));
+            }
+        }
+
+        !self.key_hook_handle.is_null() && !self.mouse_hook_handle.is_null()
+    }
+
+    // Runs on a timer
+    fn check_hook_health(&mut self) {
+        let now = unsafe { sysinfoapi::GetTickCount() };
+
+        // Failed to install before
+        if self.key_hook_handle.is_null() || self.mouse_hook_handle.is_null() {
+            let (keyboard, mouse) = (
+                self.key_hook_handle.is_null(),
+                self.mouse_hook_handle.is_null(),
+            );
+            if self.install_hooks(keyboard, mouse) {
+                self.hook_health.reinstalled(now);
+                log(Level::Info, Category::Hooks, || {
+                    "Input hooks installed".to_owned()
+                });
+                toast_notification("Input hooks installed");
+            }
+            return;
+        }
+
+        match self
+            .hook_health
+            .check(&HOOK_HEALTH_CONFIG, get_last_input_time())
+        {
+            HookCheck::Fine => (),
+            HookCheck::SendProbes => {
+                // Input to elevated windows doesn't reach our hooks, the probes included
+                let foreground = unsafe { winuser::GetForegroundWindow() };
+                if is_window_elevated(foreground) || !send_hook_probes() {
+                    self.hook_health.probes_failed(now);
+                } else {
+                    log(Level::Debug, Category::Hooks, || {
+                        "Hooks missed some input, probing them".to_owned()
+                    });
+                }
+            }
+            HookCheck::Reinstall { keyboard, mouse } => {
+                // Whatever was held down when the hook went missing got released unseen
+                self.release_layers();
+                self.mod1_on = false;
+                self.mod2_on = false;
+
+                let installed = self.install_hooks(keyboard, mouse);
+                self.hook_health.reinstalled(now);
+                log(
+                    if installed { Level::Warn } else { Level::Error },
+                    Category::Hooks,
+                    || {
+                        format!(
+                            "Hook lost (keyboard: {}, mouse: {}), {}",
+                            keyboard,
+                            mouse,
+                            if installed {
+                                "reinstalled"
+                            } else {
+                                "could not reinstall"
+                            }
+                        )
+                    },
+                );
+                toast_notification(if installed {
+                    "Input hook was lost, and is back"
+                } else {
+                    "Input hook was lost, and could not be reinstalled"
+                });
+            }
+        }
+    }
+
+    fn set_suspended(&mut self, suspended: bool) {
+        if suspended {
+            self.release_layers();
+            self.scroll_emu_state.lock().unwrap().stop();
+            self.mod1_on = false;
+            self.mod2_on = false;
+
+            if self.winkey_on {
+                Self::send_key(winuser::VK_LWIN as u8, false);
+                self.winkey_on = false;
+            }
+        }
+
+        self.suspended = suspended;
+        self.text_expander.lock().unwrap().reset();
+        self.caps_word.set(false);
+        self.vim_nav.set_on(false);
+        self.numpad_locked = false;
+        self.update_num_lock();
+        if let Some(tap) = self.auto_shift_state.lock().unwrap().flush() {
+            send_auto_shift_tap(tap, &self.text_expander);
+        }
+        log(Level::Info, Category::Engine, || {
+            (if suspended { "Suspended" } else { "Resumed" }).to_owned()
+        });
+        toast_notification(if suspended { "Suspended" } else { "Resumed" });
+    }
+
+    // A command from another instance; returns the reply
+    fn ipc_command(&mut self, command: IpcCommand) -> String {
+        match command {
+            IpcCommand::Status => (),
+            IpcCommand::Latency => return latency_report(),
+            IpcCommand::LogLevel(level) => {
+                if let Some(level) = level {
+                    set_log_level(level);
+                }
+                return logging::level_name(get_log_level()).to_owned();
+            }
+            IpcCommand::Suspend | IpcCommand::Resume => {
+                let suspended = IpcCommand::Suspend == command;
+                if suspended != self.suspended {
+                    self.set_suspended(suspended);
+                }
+            }
+        }
+
+        (if self.suspended {
+            "suspended"
+        } else {
+            "running"
+        })
+        .to_owned()
+    }
+
+    fn key_hook(&mut self, code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        if winuser::HC_ACTION == code {
+            let input_key = unsafe { *(lparam as winuser::PKBDLLHOOKSTRUCT) };
+            if input_key.dwExtraInfo == HOOK_PROBE_MAGIC {
+                self.hook_health
+                    .key_probe_seen(unsafe { sysinfoapi::GetTickCount() });
+                return 1;
+            }
+            if input_key.dwExtraInfo == H3KEYS_MAGIC {
+                return unsafe { winuser::CallNextHookEx(ptr::null_mut(), code, wparam, lparam) };
+            }
+
+            let key_pressed =
+                winuser::WM_KEYDOWN == wparam as u32 || winuser::WM_SYSKEYDOWN == wparam as u32;
+            let key_released =
+                winuser::WM_KEYUP == wparam as u32 || winuser::WM_SYSKEYUP == wparam as u32;
+
+            let down_only = |rt: RemapTarget| {
+                if key_pressed {
+                    rt
+                } else {
+                    key(0)
+                }
+            };
+
+            if key_pressed {
+                //println!("key: 0x{:x}", input_key.vkCode);
+            }
+
+            if key_pressed || key_released {
+                // Tracked even when suspended, as they're part of the kill and suspend chords
+                match input_key.vkCode as u8 as char {
+                    LEFTALT => self.leftalt_on = key_pressed,
+                    LEFTCTRL => self.leftctrl_on = key_pressed,
+                    _ => (),
+                }
+
+                // Ctrl+Alt+Pause toggles suspension
+                match self.suspend_chord.key(
+                    &SUSPEND_CONFIG,
+                    input_key.vkCode as u8,
+                    key_pressed,
+                    self.leftctrl_on,
+                    self.leftalt_on,
+                ) {
+                    ChordKey::Toggle => {
+                        let suspended = !self.suspended;
+                        self.set_suspended(suspended);
+                        return 1;
+                    }
+                    ChordKey::Swallow => return 1,
+                    ChordKey::Other => (),
+                }
+
+                // Keeps track of keyboards while suspended as well
+                let passed_through =
+                    self.is_key_passed_through(input_key.vkCode as u8, key_pressed, input_key.time);
+                if self.suspended || passed_through {
+                    return unsafe {
+                        winuser::CallNextHookEx(ptr::null_mut(), code, wparam, lparam)
+                    };
+                }
+
+                // Chatter from worn switches never makes it to the remapping
+                if self.debounce.lock().unwrap().key(
+                    &DEBOUNCE_CONFIG,
+                    input_key.vkCode as u8,
+                    key_pressed,
+                    input_key.time,
+                ) {
+                    return 1;
+                }
+
+                let stats_layer = if self.mod1_on {
+                    StatsLayer::Caps
+                } else if self.mod2_on {
+                    StatsLayer::Pipe
+                } else {
+                    StatsLayer::Base
+                };
+                let stats_layout = if self.colemak_on {
+                    StatsLayout::Colemak
+                } else {
+                    StatsLayout::Qwerty
+                };
+                self.typing_stats.lock().unwrap().key(
+                    &TYPING_STATS_CONFIG,
+                    input_key.vkCode as u8,
+                    key_pressed,
+                    stats_layer,
+                    stats_layout,
+                    input_key.time,
+                );
+
+                // Another key going down ends any tap-dance in progress, so its action comes first
+                if key_pressed {
+                    if winuser::VK_CAPITAL != input_key.vkCode as i32 {
+                        self.caps_tap_from = None;
+                    }
+
+                    let dance = self.tap_dance_state.lock().unwrap().interrupt(
+                        &TAP_DANCE_CONFIG,
+                        input_key.vkCode as u8 as char,
+                        input_key.time,
+                    );
+                    if let Some((key, dance)) = dance {
+                        send_remap_tap(caps_tap_dance_action(key, dance));
+                    }
+                }
+
+                // Enable caps-lock layer
+                if winuser::VK_CAPITAL == input_key.vkCode as i32 {
+                    self.typing_stats.lock().unwrap().layer_key(
+                        &TYPING_STATS_CONFIG,
+                        StatsLayer::Caps,
+                        key_pressed,
+                        input_key.time,
+                    );
+
+                    // Auto-repeat doesn't restart the tap
+                    if key_pressed && !self.mod1_on {
+                        self.caps_tap_from = Some(input_key.time);
+                    }
+
+                    // If disabling, make sure all remapped keys get released
+                    if key_released {
+                        self.close_window_switcher(true);
+                        self.paste_clipboard_selection();
+                        self.release_layers();
+
+                        // Tapping Caps on its own can toggle caps word
+                        let tapped = self.caps_tap_from.take().map_or(false, |from| {
+                            input_key.time.wrapping_sub(from) < CAPS_LOCK_CONFIG.tap_ms
+                        });
+                        if tapped && CAPS_LOCK_CONFIG.caps_word_on_tap {
+                            let on = !self.caps_word.is_on();
+                            self.caps_word.set(on);
+                            let toast = if on { "Caps word on" } else { "Caps word off" };
+                            post_toast_notification(toast.to_owned());
+                        }
+                    }
+
+                    self.mod1_on = key_pressed;
+                    return 1;
+                }
+
+                // Enable pipe/backslash layer
+                if winuser::VK_OEM_102 == input_key.vkCode as i32 {
+                    self.typing_stats.lock().unwrap().layer_key(
+                        &TYPING_STATS_CONFIG,
+                        StatsLayer::Pipe,
+                        key_pressed,
+                        input_key.time,
+                    );
+
+                    self.mod2_on = key_pressed;
+                    return 1;
+                }
+
+                // Colemak
+                let remap = if self.colemak_on {
+                    key(remap_colemak(input_key.vkCode as u8))
+                } else {
+                    key(0)
+                };
+
+                // Windows keys
+                let remap = match input_key.vkCode as u8 as char {
+                    TILDE => key(winuser::VK_ESCAPE),
+                    UK_TILDE => key(TILDE),
+                    ALT_GR => {
+                        self.winkey_on = key_pressed;
+                        key(winuser::VK_LWIN)
+                    }
+                    BACKSPACE => {
+                        if key_pressed && self.leftalt_on && self.leftctrl_on {
+                            unsafe {
+                                let top_window = winuser::GetForegroundWindow();
+                                let mut pid: DWORD = 0;
+                                winuser::GetWindowThreadProcessId(top_window, &mut pid);
+
+                                let h = kernel32::OpenProcess(winnt::PROCESS_ALL_ACCESS, 0, pid);
+                                if h != ptr::null_mut() {
+                                    kernel32::TerminateProcess(h, 0);
+                                }
+                            }
+                        }
+                        remap
+                    }
+                    RIGHTCTRL => key(winuser::VK_APPS),
+                    'U' => {
+                        if self.winkey_on && key_pressed {
+                            // We will not register a key-up due to the lock screen
+                            self.winkey_on = false;
+                            unsafe {
+                                winuser::LockWorkStation();
+                            }
+                            RemapTarget::Block
+                        } else {
+                            remap
+                        }
+                    }
+                    '4' => {
+                        if self.winkey_on && key_pressed {
+                            down_only(alt_key(winuser::VK_F4))
+                        } else {
+                            remap
+                        }
+                    }
+                    'M' => {
+                        if self.winkey_on && key_pressed {
+                            key('D')
+                        } else {
+                            remap
+                        }
+                    }
+                    vk => match launcher::target_for_key(LAUNCH_TARGETS, vk) {
+                        Some(target) if self.winkey_on => {
+                            if key_pressed {
+                                post_run_or_raise(target);
+                            }
+                            RemapTarget::Block
+                        }
+                        _ => remap,
+                    },
+                };
+
+                let remap = if self.mod1_on && self.window_switcher.is_some() {
+                    self.window_switcher_remap(input_key.vkCode as u8 as char, key_pressed)
+                } else if self.mod1_on && self.clipboard_history.lock().unwrap().is_cycling() {
+                    self.clipboard_history_remap(input_key.vkCode as u8 as char, key_pressed)
+                } else if self.mod1_on && self.mouse_keys_on {
+                    self.mouse_keys_remap(input_key.vkCode as u8 as char, key_pressed)
+                } else if self.mod1_on && self.window_layer_on {
+                    self.window_layer_remap(input_key.vkCode as u8 as char, key_pressed)
+                } else if self.mod1_on && self.media_layer_on {
+                    let vk = input_key.vkCode as u8 as char;
+                    let mapped_key = self.media_layer_remap(vk, key_pressed);
+                    self.track_mod1_key(&mapped_key, key_pressed);
+                    mapped_key
+                } else if self.mod1_on && self.numpad_layer_on {
+                    let vk = input_key.vkCode as u8 as char;
+                    let mapped_key = self.numpad_layer_remap(vk, key_pressed);
+                    self.track_mod1_key(&mapped_key, key_pressed);
+                    mapped_key
+                } else if self.mod1_on {
+                    // Caps-lock layer
+
+                    let tap_dance = CAPS_TAP_DANCES
+                        .iter()
+                        .find(|&&(k, _)| k == input_key.vkCode as u8 as char);
+
+                    let mapped_key = match input_key.vkCode as u8 as char {
+                        LEFTSHIFT | RIGHTSHIFT if CAPS_LOCK_CONFIG.shift_toggles_caps_lock => {
+                            if key_pressed {
+                                let on = !is_caps_lock_on();
+                                toggle_caps_lock();
+                                let toast = if on { "Caps Lock on" } else { "Caps Lock off" };
+                                post_toast_notification(toast.to_owned());
+                            }
+                            RemapTarget::Block
+                        }
+                        vk if tap_dance.is_some() => {
+                            let max_taps = tap_dance.map_or(1, |&(_, max_taps)| max_taps);
+                            self.caps_tap_dance_key(vk, max_taps, key_pressed, input_key.time);
+                            RemapTarget::Block
+                        }
                         ESCAPE => {
                             self.admin_on = key_pressed;
                             RemapTarget::Block
@@ -403,6 +3044,10 @@ impl InputHookState {
                         ' ' => {
                             if self.admin_on {
                                 if key_released {
+                                    save_typing_stats(&self.typing_stats);
+                                    save_clipboard_history(&self.clipboard_history);
+                                    save_debounce_stats(&self.debounce);
+                                    log(Level::Info, Category::Engine, || "Exiting".to_owned());
                                     toast_notification("Program terminated");
                                     std::process::exit(0);
                                 } else {
@@ -412,7 +3057,73 @@ impl InputHookState {
                                 key(winuser::VK_SPACE)
                             }
                         }
+                        'D' if self.admin_on => {
+                            if key_pressed {
+                                post_toast_notification(self.debounce.lock().unwrap().describe());
+                            }
+                            RemapTarget::Block
+                        }
                         'D' => key(winuser::VK_SHIFT),
+                        'W' => {
+                            self.mouse_keys_on = key_pressed;
+                            RemapTarget::Block
+                        }
+                        'E' => {
+                            // Their releases would go to the window layer, and get lost
+                            if key_pressed {
+                                self.release_mod1_keys();
+                            }
+                            self.window_layer_on = key_pressed;
+                            RemapTarget::Block
+                        }
+                        'A' => {
+                            self.media_layer_on = key_pressed;
+                            RemapTarget::Block
+                        }
+                        'T' => {
+                            if key_pressed {
+                                self.numpad_layer_on = true;
+                                self.update_num_lock();
+                            }
+                            RemapTarget::Block
+                        }
+                        'R' => {
+                            if key_pressed {
+                                self.numpad_locked = !self.numpad_locked;
+                                self.update_num_lock();
+                                let toast = if self.numpad_locked {
+                                    "Numpad on"
+                                } else {
+                                    "Numpad off"
+                                };
+                                post_toast_notification(toast.to_owned());
+                            }
+                            RemapTarget::Block
+                        }
+                        'G' => {
+                            if key_pressed {
+                                let on = VimMode::Off == self.vim_nav.mode();
+                                self.vim_nav.set_on(on);
+                                post_toast_notification(self.vim_nav.describe().to_owned());
+                            }
+                            RemapTarget::Block
+                        }
+                        TAB => {
+                            if key_pressed {
+                                self.open_window_switcher();
+                            }
+                            RemapTarget::Block
+                        }
+                        'B' => {
+                            if key_pressed {
+                                let mut history = self.clipboard_history.lock().unwrap();
+                                history.select_older();
+                                post_toast_notification(
+                                    history.describe(&CLIPBOARD_HISTORY_CONFIG),
+                                );
+                            }
+                            RemapTarget::Block
+                        }
                         'F' => {
                             self.ctrlmod_on = key_pressed;
                             key(winuser::VK_CONTROL)
@@ -440,6 +3151,9 @@ impl InputHookState {
                             if self.admin_on {
                                 if key_pressed {
                                     self.colemak_on = !self.colemak_on;
+                                    log(Level::Info, Category::Engine, || {
+                                        format!("Colemak: {}", self.colemak_on)
+                                    });
                                     toast_notification(if self.colemak_on {
                                         "Colemak"
                                     } else {
@@ -454,8 +3168,42 @@ impl InputHookState {
                         }
                         'X' => down_only(ctrl_key('X')),
                         'V' => down_only(ctrl_key('V')),
+                        'P' if self.admin_on => {
+                            if key_pressed {
+                                self.set_suspended(true);
+                            }
+                            RemapTarget::Block
+                        }
+                        'S' if self.admin_on => {
+                            if key_pressed {
+                                match export_typing_stats(&self.typing_stats) {
+                                    Some(dir) => toast_notification(&format!(
+                                        "Typing stats exported to {}",
+                                        dir.display()
+                                    )),
+                                    None => toast_notification("Could not export typing stats"),
+                                }
+                            }
+                            RemapTarget::Block
+                        }
+                        'L' if self.admin_on => {
+                            if key_pressed {
+                                toast_notification(&latency_report());
+                            }
+                            RemapTarget::Block
+                        }
+                        'V' if self.admin_on => {
+                            if key_pressed {
+                                let level = logging::next_level(get_log_level());
+                                set_log_level(level);
+                                toast_notification(&format!(
+                                    "Log level: {}",
+                                    logging::level_name(level)
+                                ));
+                            }
+                            RemapTarget::Block
+                        }
                         'S' => down_only(ctrl_key('S')),
-                        SEMICOLON => key(winuser::VK_RETURN),
                         'P' => key(winuser::VK_DELETE),
                         COMMA => down_only(shift_key('7')),
                         PERIOD => down_only(shift_key(winuser::VK_OEM_5)),
@@ -488,14 +3236,7 @@ impl InputHookState {
                         _ => RemapTarget::Block,
                     };
 
-                    if let RemapTarget::BlindKey(k) = mapped_key {
-                        if key_pressed {
-                            self.mod1_keys_down.insert(k);
-                        } else {
-                            self.mod1_keys_down.remove(&k);
-                        }
-                    }
-
+                    self.track_mod1_key(&mapped_key, key_pressed);
                     mapped_key
                 } else if self.mod2_on {
                     // Pipe/backslash layer
@@ -537,10 +3278,52 @@ impl InputHookState {
                         ])),
                         _ => RemapTarget::Block,
                     }
+                } else if self.numpad_locked && !self.winkey_on {
+                    // Locked on with Caps+R, keys outside the numpad block type as usual, so text
+                    // can still be typed around the numbers
+                    numpad_block_key(input_key.vkCode as u8 as char).unwrap_or(remap)
+                } else if VimMode::Off != self.vim_nav.mode() && !self.winkey_on {
+                    self.vim_nav_remap(input_key.vkCode as u8 as char, key_pressed, remap)
                 } else {
                     remap
                 };
 
+                if key_pressed {
+                    let tap = self
+                        .auto_shift_state
+                        .lock()
+                        .unwrap()
+                        .interrupt(input_key.vkCode as u8, input_key.time);
+                    if let Some(tap) = tap {
+                        send_auto_shift_tap(tap, &self.text_expander);
+                    }
+                }
+
+                if key_pressed && self.caps_word_key(input_key.vkCode as u8, &remap) {
+                    // Caps word types the key itself, which the expander doesn't follow
+                    self.text_expander.lock().unwrap().reset();
+                    return 1;
+                }
+
+                // Keys held back by auto-shift reach the expander once they're typed
+                if self.auto_shift_key(input_key.vkCode as u8, &remap, key_pressed, input_key.time)
+                {
+                    return 1;
+                }
+
+                if key_pressed && self.text_expander_key(input_key.vkCode as u8, &remap) {
+                    return 1;
+                }
+
+                log(Level::Debug, Category::Engine, || {
+                    format!(
+                        "{} {}: {}",
+                        logging::key_name(&LOG_CONFIG, input_key.vkCode as u8),
+                        if key_pressed { "down" } else { "up" },
+                        describe_remap(&remap)
+                    )
+                });
+
                 if remap != key(0) {
                     match remap {
                         RemapTarget::BlindKey(key) => {
@@ -565,45 +3348,121 @@ impl InputHookState {
         return unsafe { winuser::CallNextHookEx(ptr::null_mut(), code, wparam, lparam) };
     }
 
+    fn collect_snap_targets(&mut self, grabbed: HWND) {
+        self.snap_monitors = get_monitors();
+
+        // Going through the windows, with a DWM query for each, takes too long for the hook.
+        // Until they're in, the window only snaps to the monitors.
+        let grabbed = grabbed as usize;
+        *self.snap_windows.lock().unwrap() = SnapWindows {
+            grabbed,
+            rects: Vec::new(),
+        };
+
+        let snap_windows = self.snap_windows.clone();
+        thread::spawn(move || {
+            let rects = get_visible_windows()
+                .into_iter()
+                .filter(|&(hwnd, _)| hwnd as usize != grabbed)
+                .map(|(_, rect)| rect)
+                .collect();
+
+            // Unless another window got grabbed in the meantime
+            let snap_windows = &mut snap_windows.lock().unwrap();
+            if snap_windows.grabbed == grabbed {
+                snap_windows.rects = rects;
+            }
+        });
+    }
+
     fn mouse_hook(&mut self, code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-        if winuser::HC_ACTION == code && self.mod1_on {
+        if winuser::HC_ACTION == code {
+            let mouse_data = unsafe { *(lparam as winuser::PMSLLHOOKSTRUCT) };
+            if mouse_data.dwExtraInfo == HOOK_PROBE_MAGIC {
+                self.hook_health
+                    .mouse_probe_seen(unsafe { sysinfoapi::GetTickCount() });
+                return 1;
+            }
+
+            // Clicking most likely moved the caret
+            match wparam as u32 {
+                winuser::WM_LBUTTONDOWN | winuser::WM_RBUTTONDOWN | winuser::WM_MBUTTONDOWN => {
+                    self.text_expander.lock().unwrap().reset();
+                }
+                _ => (),
+            }
+        }
+
+        if winuser::HC_ACTION == code && self.mod1_on && !self.suspended {
             let mouse_data = unsafe { *(lparam as winuser::PMSLLHOOKSTRUCT) };
+            if mouse_data.dwExtraInfo == H3KEYS_MAGIC {
+                return unsafe { winuser::CallNextHookEx(ptr::null_mut(), code, wparam, lparam) };
+            }
+
+            // Caps+click isn't a Caps tap
+            self.caps_tap_from = None;
 
             // Window move
             if winuser::WM_LBUTTONDOWN == wparam as u32 {
                 self.mouse_move_from = (mouse_data.pt.x, mouse_data.pt.y);
                 self.window_move_hwnd = get_window_under_cursor(self.mouse_move_from);
                 if self.window_move_hwnd != ptr::null_mut() {
-                    let rect = get_window_rect(self.window_move_hwnd);
-                    self.window_move_from = (rect.left, rect.top);
+                    let hwnd = self.window_move_hwnd;
+                    self.window_move_grab = window_geometry::grab_point(
+                        &get_visible_window_rect(hwnd),
+                        self.mouse_move_from,
+                    );
+                    self.collect_snap_targets(hwnd);
                 }
 
                 return 1;
             }
 
             if winuser::WM_LBUTTONUP == wparam as u32 {
+                // Dropping with the cursor at a monitor edge tiles the window
+                if self.window_move_hwnd != ptr::null_mut() {
+                    let pt = (mouse_data.pt.x, mouse_data.pt.y);
+                    let tile = window_geometry::monitor_at(&self.snap_monitors, pt).and_then(|m| {
+                        window_geometry::tile_at(&SNAP_CONFIG, pt, m)
+                            .map(|tile| window_geometry::tile_rect(tile, &m.work_area))
+                    });
+
+                    if let Some(rect) = tile {
+                        set_window_rect(self.window_move_hwnd, &rect);
+                    }
+                }
+
                 self.window_move_hwnd = ptr::null_mut();
                 return 1;
             }
 
             if winuser::WM_MOUSEMOVE == wparam as u32 && self.window_move_hwnd != ptr::null_mut() {
-                let x = self.window_move_from.0 + mouse_data.pt.x - self.mouse_move_from.0;
-                let y = self.window_move_from.1 + mouse_data.pt.y - self.mouse_move_from.1;
+                let pt = (mouse_data.pt.x, mouse_data.pt.y);
 
-                unsafe {
-                    winuser::SetWindowPos(
-                        self.window_move_hwnd,
-                        ptr::null_mut(),
-                        x,
-                        y,
-                        0,
-                        0,
-                        winuser::SWP_NOACTIVATE
-                            | winuser::SWP_NOOWNERZORDER
-                            | winuser::SWP_NOSIZE
-                            | winuser::SWP_NOZORDER,
-                    );
-                }
+                // Re-read the size, as it changes when crossing onto a monitor with a different DPI
+                let current = get_visible_window_rect(self.window_move_hwnd);
+                let rect = window_geometry::drag_rect(
+                    pt,
+                    self.window_move_grab,
+                    (current.width(), current.height()),
+                );
+                let rect = window_geometry::snap_move(
+                    &SNAP_CONFIG,
+                    &rect,
+                    &self.snap_monitors,
+                    &self.snap_windows.lock().unwrap().rects,
+                );
+                let rect = match window_geometry::monitor_at(&self.snap_monitors, pt) {
+                    Some(monitor) => window_geometry::clamp_title_bar(
+                        &SNAP_CONFIG,
+                        &rect,
+                        &self.snap_monitors,
+                        monitor,
+                    ),
+                    None => rect,
+                };
+
+                set_window_pos(self.window_move_hwnd, (rect.left, rect.top));
             }
 
             // Window resize
@@ -611,8 +3470,16 @@ impl InputHookState {
                 self.mouse_resize_from = (mouse_data.pt.x, mouse_data.pt.y);
                 self.window_resize_hwnd = get_window_under_cursor(self.mouse_resize_from);
                 if self.window_resize_hwnd != ptr::null_mut() {
-                    let rect = get_window_rect(self.window_resize_hwnd);
-                    self.window_resize_from = (rect.right - rect.left, rect.bottom - rect.top);
+                    let hwnd = self.window_resize_hwnd;
+                    self.window_resize_from = get_visible_window_rect(hwnd);
+                    self.window_resize_edges = window_geometry::nearest_edges(
+                        &self.window_resize_from,
+                        self.mouse_resize_from,
+                    );
+                    self.collect_snap_targets(hwnd);
+                    self.window_resize_dpi =
+                        window_geometry::monitor_at(&self.snap_monitors, self.mouse_resize_from)
+                            .map_or(96, |m| m.dpi);
                 }
 
                 return 1;
@@ -625,32 +3492,44 @@ impl InputHookState {
 
             if winuser::WM_MOUSEMOVE == wparam as u32 && self.window_resize_hwnd != ptr::null_mut()
             {
-                let x = self.window_resize_from.0 + mouse_data.pt.x - self.mouse_resize_from.0;
-                let y = self.window_resize_from.1 + mouse_data.pt.y - self.mouse_resize_from.1;
+                let pt = (mouse_data.pt.x, mouse_data.pt.y);
 
-                unsafe {
-                    winuser::SetWindowPos(
-                        self.window_resize_hwnd,
-                        ptr::null_mut(),
-                        0,
-                        0,
-                        x,
-                        y,
-                        winuser::SWP_NOACTIVATE
-                            | winuser::SWP_NOOWNERZORDER
-                            | winuser::SWP_NOMOVE
-                            | winuser::SWP_NOZORDER,
-                    );
+                // Crossing onto a monitor with a different DPI rescales the window,
+                // so carry on from wherever that left it
+                if let Some(monitor) = window_geometry::monitor_at(&self.snap_monitors, pt) {
+                    if monitor.dpi != self.window_resize_dpi {
+                        self.window_resize_dpi = monitor.dpi;
+                        self.window_resize_from = get_visible_window_rect(self.window_resize_hwnd);
+                        self.mouse_resize_from = pt;
+                    }
                 }
+
+                let rect = window_geometry::resize_rect(
+                    &SNAP_CONFIG,
+                    &self.window_resize_from,
+                    self.window_resize_edges,
+                    (
+                        pt.0 - self.mouse_resize_from.0,
+                        pt.1 - self.mouse_resize_from.1,
+                    ),
+                );
+                let rect = window_geometry::snap_resize(
+                    &SNAP_CONFIG,
+                    &rect,
+                    self.window_resize_edges,
+                    &self.snap_monitors,
+                    &self.snap_windows.lock().unwrap().rects,
+                );
+
+                set_window_rect(self.window_resize_hwnd, &rect);
             }
 
             // Scroll emulation
-            if 1 == self
-                .scroll_emu_state
-                .lock()
-                .unwrap()
-                .mouse_hook(wparam, mouse_data)
-            {
+            if 1 == self.scroll_emu_state.lock().unwrap().mouse_hook(
+                wparam,
+                mouse_data,
+                &mut self.scroll_process_names,
+            ) {
                 return 1;
             }
         }
@@ -660,44 +3539,31 @@ impl InputHookState {
 }
 
 impl ScrollEmuState {
-    fn new() -> ScrollEmuState {
-        ScrollEmuState {
-            scroll_emu_on: false,
-            scroll_emu_from: (0, 0),
-            scroll_emu_acc: (0f32, 0f32),
-        }
-    }
+    fn mouse_hook(
+        &mut self,
+        wparam: WPARAM,
+        mouse_data: winuser::MSLLHOOKSTRUCT,
+        process_names: &mut ProcessNameCache,
+    ) -> LRESULT {
+        let pt = (mouse_data.pt.x, mouse_data.pt.y);
 
-    fn mouse_hook(&mut self, wparam: WPARAM, mouse_data: winuser::MSLLHOOKSTRUCT) -> LRESULT {
         if winuser::WM_MBUTTONDOWN == wparam as u32 {
-            self.scroll_emu_from = (mouse_data.pt.x, mouse_data.pt.y);
-            self.scroll_emu_acc = (0f32, 0f32);
-            self.scroll_emu_on = true;
+            let process_name = get_cached_process_name(process_names, get_root_window_at(pt));
+            self.begin(
+                &SCROLL_EMU_CONFIG,
+                pt,
+                process_name.as_ref().map(|name| name.as_str()),
+            );
             return 1;
         }
 
         if winuser::WM_MBUTTONUP == wparam as u32 {
-            self.scroll_emu_on = false;
+            self.end(&SCROLL_EMU_CONFIG);
             return 1;
         }
 
         if winuser::WM_MOUSEMOVE == wparam as u32 && self.scroll_emu_on {
-            let hscroll = (mouse_data.pt.x - self.scroll_emu_from.0) as f32;
-            let vscroll = (self.scroll_emu_from.1 - mouse_data.pt.y) as f32;
-
-            // Dead zone
-            //let hscroll = if hscroll * hscroll > 1f32 { hscroll } else { 0f32 };
-            //let vscroll = if vscroll * vscroll > 1f32 { vscroll } else { 0f32 };
-
-            // Curve
-            let hscroll = hscroll.signum() * hscroll.abs().powf(1.5f32);
-            let vscroll = vscroll.signum() * vscroll.abs().powf(1.5f32);
-
-            // Blend
-            let t = 0.3f32;
-            self.scroll_emu_acc.0 = self.scroll_emu_acc.0 * (1.0f32 - t) + hscroll * t;
-            self.scroll_emu_acc.1 = self.scroll_emu_acc.1 * (1.0f32 - t) + vscroll * t;
-
+            self.mouse_moved(&SCROLL_EMU_CONFIG, pt);
             return 1;
         }
 
@@ -705,42 +3571,17 @@ impl ScrollEmuState {
     }
 
     fn emulate_scroll(&mut self) -> Box<dyn Fn()> {
-        if self.scroll_emu_on {
-            let decay = 0.92f32;
-            self.scroll_emu_acc = (self.scroll_emu_acc.0 * decay, self.scroll_emu_acc.1 * decay);
-        }
-
         let scroll_from = self.scroll_emu_from;
-        let scroll_acc = if self.scroll_emu_on {
-            self.scroll_emu_acc
-        } else {
-            (0f32, 0f32)
-        };
+        let wheel = self.tick(&SCROLL_EMU_CONFIG);
 
         // Defer winapi usage so that we can bring it outside of the mutex in the calling code
         Box::new(move || {
-            if scroll_acc.0 as i32 != 0 {
-                unsafe {
-                    winuser::mouse_event(
-                        winuser::MOUSEEVENTF_HWHEEL,
-                        scroll_from.0 as u32,
-                        scroll_from.1 as u32,
-                        scroll_acc.0 as i32 as u32,
-                        H3KEYS_MAGIC,
-                    );
-                }
+            if wheel.0 != 0 {
+                send_mouse_input(winuser::MOUSEEVENTF_HWHEEL, scroll_from, wheel.0);
             }
 
-            if scroll_acc.1 as i32 != 0 {
-                unsafe {
-                    winuser::mouse_event(
-                        winuser::MOUSEEVENTF_WHEEL,
-                        scroll_from.0 as u32,
-                        scroll_from.1 as u32,
-                        scroll_acc.1 as i32 as u32,
-                        H3KEYS_MAGIC,
-                    );
-                }
+            if wheel.1 != 0 {
+                send_mouse_input(winuser::MOUSEEVENTF_WHEEL, scroll_from, wheel.1);
             }
         })
     }
@@ -750,7 +3591,17 @@ static mut HOOK_STATE: Option<InputHookState> = None;
 
 unsafe extern "system" fn global_key_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     if let Some(hook_state) = HOOK_STATE.as_mut() {
-        hook_state.key_hook(code, wparam, lparam)
+        hook_state.hook_health.event(sysinfoapi::GetTickCount());
+        let start = time::Instant::now();
+        let res = hook_state.key_hook(code, wparam, lparam);
+        record_latency(Probe::KeyHook, start, || {
+            if winuser::HC_ACTION != code {
+                return format!("code {}", code);
+            }
+            let input_key = *(lparam as winuser::PKBDLLHOOKSTRUCT);
+            format!("key 0x{:02X}, message 0x{:04X}", input_key.vkCode, wparam)
+        });
+        res
     } else {
         0
     }
@@ -758,7 +3609,13 @@ unsafe extern "system" fn global_key_hook(code: i32, wparam: WPARAM, lparam: LPA
 
 unsafe extern "system" fn global_mouse_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     if let Some(hook_state) = HOOK_STATE.as_mut() {
-        hook_state.mouse_hook(code, wparam, lparam)
+        hook_state.hook_health.event(sysinfoapi::GetTickCount());
+        let start = time::Instant::now();
+        let res = hook_state.mouse_hook(code, wparam, lparam);
+        record_latency(Probe::MouseHook, start, || {
+            format!("message 0x{:04X}", wparam)
+        });
+        res
     } else {
         0
     }
@@ -773,10 +3630,173 @@ pub unsafe extern "system" fn win_proc(
     if msg == winuser::WM_DESTROY {
         winuser::PostQuitMessage(0);
     }
+    if msg == winuser::WM_INPUT {
+        if let Some(hook_state) = HOOK_STATE.as_mut() {
+            let time = winuser::GetMessageTime() as u32;
+            hook_state.raw_keyboard_input(l_param as winuser::HRAWINPUT, time);
+        }
+    }
+    if msg == winuser::WM_TIMER && w_param == HOOK_HEALTH_TIMER {
+        if let Some(hook_state) = HOOK_STATE.as_mut() {
+            hook_state.check_hook_health();
+        }
+        return 0;
+    }
+    if msg == WM_SHOW_TOAST {
+        let content = Box::from_raw(l_param as *mut String);
+        toast_notification(&content);
+        return 0;
+    }
+    if msg == WM_HIDE_TOAST {
+        hide_toast_notification();
+        return 0;
+    }
+    if msg == WM_RUN_OR_RAISE {
+        run_or_raise(&*(l_param as *const LaunchTarget));
+        return 0;
+    }
+    if msg == WM_REFRESH_SWITCHER {
+        if let Some(hook_state) = HOOK_STATE.as_mut() {
+            hook_state.show_window_switcher();
+        }
+        return 0;
+    }
+    if msg == winuser::WM_CLIPBOARDUPDATE {
+        if let Some(hook_state) = HOOK_STATE.as_mut() {
+            hook_state.clipboard_updated();
+        }
+        return 0;
+    }
+    if msg == winuser::WM_COPYDATA {
+        if let Some(text) = read_copy_data(l_param) {
+            let command = ipc::parse(&text);
+            let reply = match (command, HOOK_STATE.as_mut()) {
+                (Some(command), Some(hook_state)) => hook_state.ipc_command(command),
+                _ => ipc::usage(),
+            };
+            send_copy_data(w_param as HWND, h_wnd, &reply);
+            return if command.is_some() { 1 } else { 2 };
+        }
+    }
     return winuser::DefWindowProcW(h_wnd, msg, w_param, l_param);
 }
 
+// Sends text to another instance's window, from ours; returns what its window procedure did
+fn send_copy_data(to: HWND, from: HWND, text: &str) -> LRESULT {
+    let mut data = winuser::COPYDATASTRUCT {
+        dwData: IPC_COPY_DATA,
+        cbData: text.len() as DWORD,
+        lpData: text.as_ptr() as LPVOID,
+    };
+
+    let mut res = 0;
+    unsafe {
+        winuser::SendMessageTimeoutW(
+            to,
+            winuser::WM_COPYDATA,
+            from as WPARAM,
+            &mut data as *mut _ as LPARAM,
+            winuser::SMTO_ABORTIFHUNG,
+            5000,
+            &mut res,
+        );
+    }
+    res as LRESULT
+}
+
+fn read_copy_data(l_param: LPARAM) -> Option<String> {
+    unsafe {
+        let data = &*(l_param as winuser::PCOPYDATASTRUCT);
+        if IPC_COPY_DATA != data.dwData {
+            return None;
+        }
+
+        let bytes = slice::from_raw_parts(data.lpData as *const u8, data.cbData as usize);
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+// What the running instance replied with
+static mut IPC_REPLY: Option<String> = None;
+
+unsafe extern "system" fn ipc_client_proc(
+    h_wnd: HWND,
+    msg: UINT,
+    w_param: WPARAM,
+    l_param: LPARAM,
+) -> LRESULT {
+    if msg == winuser::WM_COPYDATA {
+        if let Some(text) = read_copy_data(l_param) {
+            IPC_REPLY = Some(text);
+            return 1;
+        }
+    }
+    winuser::DefWindowProcW(h_wnd, msg, w_param, l_param)
+}
+
+// Sends a command to the running instance and prints its reply; returns the exit code
+fn run_ipc_command(command: &str) -> i32 {
+    unsafe {
+        // Being a windows subsystem app, there's no console to print to unless borrowed
+        wincon::AttachConsole(wincon::ATTACH_PARENT_PROCESS);
+
+        let server = winuser::FindWindowA(WINDOW_CLASS.as_ptr() as *const i8, ptr::null());
+        if server == ptr::null_mut() {
+            eprintln!("h3keys3 isn't running");
+            return 1;
+        }
+
+        // Message-only window for the reply to come to
+        let class_name = "h3keys3_ipc\0";
+        let wnd_class = winuser::WNDCLASSA {
+            style: 0,
+            lpfnWndProc: Some(ipc_client_proc),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: 0 as HINSTANCE,
+            hIcon: 0 as HICON,
+            hCursor: 0 as HCURSOR,
+            hbrBackground: 0 as HBRUSH,
+            lpszMenuName: 0 as LPCSTR,
+            lpszClassName: class_name.as_ptr() as *const i8,
+        };
+        winuser::RegisterClassA(&wnd_class);
+        let client = winuser::CreateWindowExA(
+            0,
+            class_name.as_ptr() as *const i8,
+            class_name.as_ptr() as *const i8,
+            0,
+            0,
+            0,
+            0,
+            0,
+            winuser::HWND_MESSAGE,
+            0 as HMENU,
+            0 as HINSTANCE,
+            ptr::null_mut(),
+        );
+
+        let res = send_copy_data(server, client, command);
+        match IPC_REPLY.take() {
+            Some(reply) => println!("{}", reply),
+            None => eprintln!("h3keys3 didn't reply"),
+        }
+
+        if 1 == res {
+            0
+        } else {
+            1
+        }
+    }
+}
+
 fn main() {
+    // Arguments are a command for the instance already running
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(run_ipc_command(&args.join(" ")));
+    }
+
     let rt = RuntimeContext::init();
     run();
     rt.uninit();
@@ -792,27 +3812,56 @@ thread_local! {
     static PREVIOUS_TOAST: RefCell<Option<ComPtr<ToastNotification>>> = RefCell::new(None);
 }
 
-fn toast_notification(content: &str) {
+fn hide_toast_notification() {
     TOAST_NOTIFIER.with(|toast_notifier| {
         let toast_notifier = &*toast_notifier.borrow();
 
         PREVIOUS_TOAST.with(|prev_toast| {
             let prev_toast = &mut *prev_toast.borrow_mut();
 
-            // If there's any previous toast, hide it right away.
-            let should_hide_previous = if let &mut Some(ref toast) = prev_toast {
+            if let Some(ref toast) = *prev_toast {
                 unsafe {
                     toast_notifier.hide(toast).ok();
                 }
-                true
-            } else {
-                false
-            };
-
-            if should_hide_previous {
-                *prev_toast = None;
             }
 
+            *prev_toast = None;
+        });
+    });
+}
+
+// Showing or hiding a toast takes long enough to risk the hook timeout, so the hooks leave it
+// to the window, which takes ownership of the text
+fn post_toast_notification(content: String) {
+    let content = Box::into_raw(Box::new(content));
+    if !post_message(WM_SHOW_TOAST, content as LPARAM) {
+        drop(unsafe { Box::from_raw(content) });
+    }
+}
+
+fn post_hide_toast_notification() {
+    post_message(WM_HIDE_TOAST, 0);
+}
+
+fn toast_notification(content: &str) {
+    // Toasts can show clipboard contents
+    log(Level::Info, Category::Notifications, || {
+        if LOG_CONFIG.redact_keys {
+            "Toast".to_owned()
+        } else {
+            format!("Toast: {}", content)
+        }
+    });
+
+    // If there's any previous toast, hide it right away.
+    hide_toast_notification();
+
+    TOAST_NOTIFIER.with(|toast_notifier| {
+        let toast_notifier = &*toast_notifier.borrow();
+
+        PREVIOUS_TOAST.with(|prev_toast| {
+            let prev_toast = &mut *prev_toast.borrow_mut();
+
             unsafe {
                 // Get a toast XML template
                 let toast_xml =
@@ -862,7 +3911,23 @@ fn toast_notification(content: &str) {
 
 fn run() {
     unsafe {
+        // Low-level hooks report physical cursor positions; without per-monitor DPI awareness,
+        // window rects would be scaled to some other coordinate system on high-DPI monitors.
+        shellscalingapi::SetProcessDpiAwareness(shellscalingapi::PROCESS_PER_MONITOR_DPI_AWARE);
+
+        start_logging();
+        LATENCY = Some(Mutex::new(Latency::new(get_hook_timeout_ms())));
+        log(Level::Info, Category::Engine, || {
+            format!("Started h3keys3 {}", env!("CARGO_PKG_VERSION"))
+        });
+
         HOOK_STATE = Some(InputHookState::new());
+
+        // Caps Lock left on from before would be stuck on, with the Caps key taken over
+        if CAPS_LOCK_CONFIG.reset_on_startup && is_caps_lock_on() {
+            toggle_caps_lock();
+        }
+
         kernel32::SetThreadPriority(
             kernel32::GetCurrentThread(),
             1, /* THREAD_PRIORITY_ABOVE_NORMAL */
@@ -871,31 +3936,70 @@ fn run() {
 
     {
         let scroll_state = unsafe { HOOK_STATE.as_mut().unwrap().scroll_emu_state.clone() };
+        let mouse_keys_state = unsafe { HOOK_STATE.as_mut().unwrap().mouse_keys_state.clone() };
+        let auto_shift_state = unsafe { HOOK_STATE.as_mut().unwrap().auto_shift_state.clone() };
+        let tap_dance_state = unsafe { HOOK_STATE.as_mut().unwrap().tap_dance_state.clone() };
+        let typing_stats = unsafe { HOOK_STATE.as_mut().unwrap().typing_stats.clone() };
+        let text_expander = unsafe { HOOK_STATE.as_mut().unwrap().text_expander.clone() };
+        let clipboard_history = unsafe { HOOK_STATE.as_mut().unwrap().clipboard_history.clone() };
+        let debounce = unsafe { HOOK_STATE.as_mut().unwrap().debounce.clone() };
 
-        thread::spawn(move || loop {
-            let run_scroll_actions = scroll_state.lock().unwrap().emulate_scroll();
-            run_scroll_actions();
-            thread::sleep(time::Duration::from_millis(10));
-        });
-    }
+        thread::spawn(move || {
+            let mut last_tick = time::Instant::now();
 
-    unsafe {
-        winuser::SetWindowsHookExA(
-            winuser::WH_KEYBOARD_LL,
-            Some(global_key_hook),
-            GetModuleHandleA(ptr::null()) as HINSTANCE,
-            0,
-        );
+            loop {
+                let run_scroll_actions = scroll_state.lock().unwrap().emulate_scroll();
+                run_scroll_actions();
 
-        winuser::SetWindowsHookExA(
-            winuser::WH_MOUSE_LL,
-            Some(global_mouse_hook),
-            GetModuleHandleA(ptr::null()) as HINSTANCE,
-            0,
-        );
+                let now = time::Instant::now();
+                let dt = now.duration_since(last_tick);
+                let dt = dt.as_secs() as f32 + dt.subsec_nanos() as f32 * 1e-9;
+                last_tick = now;
+
+                let motion = mouse_keys_state
+                    .lock()
+                    .unwrap()
+                    .step(&MOUSE_KEYS_CONFIG, dt);
+                send_mouse_motion(motion);
+
+                let now = unsafe { sysinfoapi::GetTickCount() };
+                let tap = auto_shift_state.lock().unwrap().tick(now);
+                if let Some(tap) = tap {
+                    send_auto_shift_tap(tap, &text_expander);
+                }
+
+                let dance = tap_dance_state.lock().unwrap().tick(&TAP_DANCE_CONFIG, now);
+                if let Some((key, dance)) = dance {
+                    send_remap_tap(caps_tap_dance_action(key, dance));
+                }
+
+                let needs_saving = typing_stats
+                    .lock()
+                    .unwrap()
+                    .needs_saving(&TYPING_STATS_CONFIG);
+                if needs_saving {
+                    save_typing_stats(&typing_stats);
+                }
+
+                let needs_saving = clipboard_history
+                    .lock()
+                    .unwrap()
+                    .needs_saving(&CLIPBOARD_HISTORY_CONFIG, now);
+                if needs_saving {
+                    save_clipboard_history(&clipboard_history);
+                }
+
+                let needs_saving = debounce.lock().unwrap().needs_saving();
+                if needs_saving {
+                    save_debounce_stats(&debounce);
+                }
+
+                thread::sleep(time::Duration::from_millis(10));
+            }
+        });
     }
 
-    let class_name = "h3keys3";
+    let class_name = WINDOW_CLASS;
     let wnd_class = winuser::WNDCLASSA {
         style: 0,
         lpfnWndProc: Some(win_proc),
@@ -930,6 +4034,48 @@ fn run() {
         )
     };
 
+    unsafe {
+        MESSAGE_HWND.store(hwnd as usize, Ordering::Relaxed);
+        winuser::AddClipboardFormatListener(hwnd);
+        winuser::SetTimer(
+            hwnd,
+            HOOK_HEALTH_TIMER,
+            HOOK_HEALTH_CONFIG.check_interval_ms,
+            None,
+        );
+    }
+
+    // Once the window exists, as the hooks post messages to it.
+    // Retried by the hook health checks if it fails.
+    if unsafe { HOOK_STATE.as_mut().unwrap().install_hooks(true, true) } {
+        log(Level::Info, Category::Hooks, || {
+            "Input hooks installed".to_owned()
+        });
+    } else {
+        log(Level::Error, Category::Hooks, || {
+            format!("Could not install the input hooks, error {}", unsafe {
+                kernel32::GetLastError()
+            })
+        });
+        toast_notification("Could not install the input hooks");
+    }
+
+    // Raw Input tells keyboards apart, for the device rules
+    let keyboards = winuser::RAWINPUTDEVICE {
+        usUsagePage: 0x01, // HID_USAGE_PAGE_GENERIC
+        usUsage: 0x06,     // HID_USAGE_GENERIC_KEYBOARD
+        dwFlags: winuser::RIDEV_INPUTSINK,
+        hwndTarget: hwnd,
+    };
+
+    unsafe {
+        winuser::RegisterRawInputDevices(
+            &keyboards,
+            1,
+            mem::size_of::<winuser::RAWINPUTDEVICE>() as UINT,
+        );
+    }
+
     let mut msg = winuser::MSG {
         hwnd: 0 as HWND,
         message: 0 as UINT,
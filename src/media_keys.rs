@@ -0,0 +1,161 @@
+// Media layer on Caps+A: playback, volume, screen brightness and browser keys.
+//
+// Playback, volume and browser controls go out as the media keys themselves, so whatever
+// handles those on the keyboard handles them here too. Brightness has no key; it gets set
+// over DDC/CI, which only external monitors tend to support.
+
+use std::cmp;
+
+pub struct MediaConfig {
+    // Show the volume or brightness in a toast after changing it
+    pub show_level: bool,
+    // Share of the monitor's brightness range one press changes it by, in percent
+    pub brightness_step: u32,
+    // Characters in the level bar
+    pub bar_len: u32,
+}
+
+pub const MEDIA_CONFIG: MediaConfig = MediaConfig {
+    show_level: true,
+    brightness_step: 10,
+    bar_len: 20,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MediaKey {
+    // Sent as this virtual key
+    Key(u8),
+    // Sent as this virtual key, after which the volume may be shown
+    Volume(u8),
+    // Brightness up if true, down otherwise
+    Brightness(bool),
+}
+
+// VK_OEM_1 and VK_OEM_PERIOD
+const SEMICOLON: u8 = 0xBA;
+const PERIOD: u8 = 0xBE;
+
+// J K L are previous, play/pause and next, ; stop, and U I O volume down, mute and up.
+// H and N are browser back and forward, Y refresh, and M and . brightness down and up.
+pub fn media_key(vk: u8) -> Option<MediaKey> {
+    let res = match vk {
+        b'J' => MediaKey::Key(0xB1),      // VK_MEDIA_PREV_TRACK
+        b'K' => MediaKey::Key(0xB3),      // VK_MEDIA_PLAY_PAUSE
+        b'L' => MediaKey::Key(0xB0),      // VK_MEDIA_NEXT_TRACK
+        SEMICOLON => MediaKey::Key(0xB2), // VK_MEDIA_STOP
+        b'U' => MediaKey::Volume(0xAE),   // VK_VOLUME_DOWN
+        b'I' => MediaKey::Volume(0xAD),   // VK_VOLUME_MUTE
+        b'O' => MediaKey::Volume(0xAF),   // VK_VOLUME_UP
+        b'H' => MediaKey::Key(0xA6),      // VK_BROWSER_BACK
+        b'N' => MediaKey::Key(0xA7),      // VK_BROWSER_FORWARD
+        b'Y' => MediaKey::Key(0xA8),      // VK_BROWSER_REFRESH
+        b'M' => MediaKey::Brightness(false),
+        PERIOD => MediaKey::Brightness(true),
+        _ => return None,
+    };
+
+    Some(res)
+}
+
+// What's shown after changing a level, e.g. "Volume 45%" with a bar underneath
+pub fn describe_level(cfg: &MediaConfig, name: &str, percent: u32, muted: bool) -> String {
+    let filled = (cmp::min(percent, 100) * cfg.bar_len + 50) / 100;
+    let bar: String = (0..cfg.bar_len)
+        .map(|i| if i < filled { '\u{2588}' } else { '\u{2591}' })
+        .collect();
+
+    format!(
+        "{} {}%{}\n{}",
+        name,
+        percent,
+        if muted { " (muted)" } else { "" },
+        bar
+    )
+}
+
+// Where `current` sits between `min` and `max`, in percent
+pub fn level_percent(min: u32, current: u32, max: u32) -> u32 {
+    if max <= min {
+        return 100;
+    }
+
+    cmp::min(current, max).saturating_sub(min) * 100 / (max - min)
+}
+
+// Brightness after one press, kept within the monitor's range
+pub fn step_brightness(cfg: &MediaConfig, min: u32, current: u32, max: u32, up: bool) -> u32 {
+    let step = cmp::max(max.saturating_sub(min) * cfg.brightness_step / 100, 1);
+
+    if up {
+        cmp::min(current.saturating_add(step), max)
+    } else {
+        cmp::max(current.saturating_sub(step), min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::MediaKey::*;
+    use super::*;
+
+    #[test]
+    fn maps_the_layer() {
+        let table = [
+            (b'J', Some(Key(0xB1))),
+            (b'K', Some(Key(0xB3))),
+            (b'L', Some(Key(0xB0))),
+            (SEMICOLON, Some(Key(0xB2))),
+            (b'U', Some(Volume(0xAE))),
+            (b'I', Some(Volume(0xAD))),
+            (b'O', Some(Volume(0xAF))),
+            (b'H', Some(Key(0xA6))),
+            (b'N', Some(Key(0xA7))),
+            (b'Y', Some(Key(0xA8))),
+            (b'M', Some(Brightness(false))),
+            (PERIOD, Some(Brightness(true))),
+            // Everything else on the layer is blocked
+            (b'A', None),
+            (b'P', None),
+            (b'1', None),
+        ];
+        for &(vk, expected) in table.iter() {
+            assert_eq!(media_key(vk), expected, "vk {:#x}", vk);
+        }
+    }
+
+    #[test]
+    fn describes_levels() {
+        let bar = |filled: usize| "\u{2588}".repeat(filled) + &"\u{2591}".repeat(20 - filled);
+        assert_eq!(
+            describe_level(&MEDIA_CONFIG, "Volume", 45, false),
+            format!("Volume 45%\n{}", bar(9))
+        );
+        assert_eq!(
+            describe_level(&MEDIA_CONFIG, "Volume", 0, true),
+            format!("Volume 0% (muted)\n{}", bar(0))
+        );
+        assert_eq!(
+            describe_level(&MEDIA_CONFIG, "Brightness", 100, false),
+            format!("Brightness 100%\n{}", bar(20))
+        );
+    }
+
+    #[test]
+    fn level_percent_within_range() {
+        assert_eq!(level_percent(0, 50, 100), 50);
+        assert_eq!(level_percent(20, 20, 70), 0);
+        assert_eq!(level_percent(20, 45, 70), 50);
+        assert_eq!(level_percent(0, 150, 100), 100);
+        assert_eq!(level_percent(50, 50, 50), 100);
+    }
+
+    #[test]
+    fn brightness_steps_stay_in_range() {
+        assert_eq!(step_brightness(&MEDIA_CONFIG, 0, 50, 100, true), 60);
+        assert_eq!(step_brightness(&MEDIA_CONFIG, 0, 50, 100, false), 40);
+        assert_eq!(step_brightness(&MEDIA_CONFIG, 0, 95, 100, true), 100);
+        assert_eq!(step_brightness(&MEDIA_CONFIG, 10, 15, 100, false), 10);
+        // Narrow ranges still move by one
+        assert_eq!(step_brightness(&MEDIA_CONFIG, 0, 3, 5, true), 4);
+    }
+}
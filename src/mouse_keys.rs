@@ -0,0 +1,299 @@
+// Keyboard-driven mouse pointer, active while Caps+W is held.
+//
+// The motion model only depends on which keys are held and for how long,
+// so the input hook just flips flags, and the timer thread turns them into motion.
+
+use std::mem;
+
+pub struct MouseKeysConfig {
+    // Pointer speed in pixels per second, right after pressing a direction key
+    pub base_speed: f32,
+    // Pointer speed in pixels per second, after `accel_time` seconds
+    pub max_speed: f32,
+    pub accel_time: f32,
+    // Shape of the acceleration ramp; 1 is linear, higher values stay slow for longer
+    pub accel_curve: f32,
+    // Speed multiplier when precision mode is held
+    pub precision_factor: f32,
+    // Wheel units per second, where 120 is one notch
+    pub scroll_speed: f32,
+}
+
+pub const MOUSE_KEYS_CONFIG: MouseKeysConfig = MouseKeysConfig {
+    base_speed: 150.0,
+    max_speed: 1800.0,
+    accel_time: 0.8,
+    accel_curve: 2.0,
+    precision_factor: 0.2,
+    scroll_speed: 1200.0,
+};
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct HeldKeys {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+
+    pub scroll_left: bool,
+    pub scroll_right: bool,
+    pub scroll_up: bool,
+    pub scroll_down: bool,
+
+    pub precision: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+fn axis(negative: bool, positive: bool) -> f32 {
+    match (negative, positive) {
+        (true, false) => -1.0,
+        (false, true) => 1.0,
+        _ => 0.0,
+    }
+}
+
+fn normalized(x: f32, y: f32) -> (f32, f32) {
+    let len = (x * x + y * y).sqrt();
+    if len > 0.0 {
+        (x / len, y / len)
+    } else {
+        (0.0, 0.0)
+    }
+}
+
+// Pointer velocity in pixels per second, with y pointing down the screen
+pub fn pointer_velocity(cfg: &MouseKeysConfig, held: &HeldKeys, held_for: f32) -> (f32, f32) {
+    let dir = normalized(axis(held.left, held.right), axis(held.up, held.down));
+
+    let ramp = if cfg.accel_time > 0.0 {
+        (held_for / cfg.accel_time).max(0.0).min(1.0)
+    } else {
+        1.0
+    };
+    let mut speed = cfg.base_speed + (cfg.max_speed - cfg.base_speed) * ramp.powf(cfg.accel_curve);
+
+    if held.precision {
+        speed *= cfg.precision_factor;
+    }
+
+    (dir.0 * speed, dir.1 * speed)
+}
+
+// Wheel velocity in wheel units per second, with positive values scrolling up and right,
+// matching the sign convention of MOUSEEVENTF_WHEEL and MOUSEEVENTF_HWHEEL
+pub fn scroll_velocity(cfg: &MouseKeysConfig, held: &HeldKeys) -> (f32, f32) {
+    let mut speed = cfg.scroll_speed;
+
+    if held.precision {
+        speed *= cfg.precision_factor;
+    }
+
+    (
+        axis(held.scroll_left, held.scroll_right) * speed,
+        axis(held.scroll_down, held.scroll_up) * speed,
+    )
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct MouseKeysMotion {
+    pub pointer: (i32, i32),
+    pub wheel: (i32, i32),
+}
+
+pub struct MouseKeysState {
+    pub held: HeldKeys,
+    pub buttons_down: Vec<MouseButton>,
+
+    move_time: f32,
+    // Sub-pixel and sub-unit motion carried over to the next step
+    move_acc: (f32, f32),
+    scroll_acc: (f32, f32),
+}
+
+impl MouseKeysState {
+    pub fn new() -> MouseKeysState {
+        MouseKeysState {
+            held: HeldKeys::default(),
+            buttons_down: Vec::new(),
+
+            move_time: 0.0,
+            move_acc: (0.0, 0.0),
+            scroll_acc: (0.0, 0.0),
+        }
+    }
+
+    // Returns true if the button was not already down; keyboard auto-repeat must not re-click
+    pub fn press_button(&mut self, button: MouseButton) -> bool {
+        if self.buttons_down.contains(&button) {
+            false
+        } else {
+            self.buttons_down.push(button);
+            true
+        }
+    }
+
+    // Returns true if the button was down
+    pub fn release_button(&mut self, button: MouseButton) -> bool {
+        let count = self.buttons_down.len();
+        self.buttons_down.retain(|&b| b != button);
+        self.buttons_down.len() != count
+    }
+
+    // Stops all motion, and returns the buttons which still need releasing
+    pub fn release_all(&mut self) -> Vec<MouseButton> {
+        self.held = HeldKeys::default();
+        self.move_time = 0.0;
+        self.move_acc = (0.0, 0.0);
+        self.scroll_acc = (0.0, 0.0);
+        mem::replace(&mut self.buttons_down, Vec::new())
+    }
+
+    // Advances the motion by `dt` seconds, returning whole pixels and wheel units to emit
+    pub fn step(&mut self, cfg: &MouseKeysConfig, dt: f32) -> MouseKeysMotion {
+        let held = self.held;
+
+        let velocity = pointer_velocity(cfg, &held, self.move_time);
+        if velocity == (0.0, 0.0) {
+            self.move_time = 0.0;
+            self.move_acc = (0.0, 0.0);
+        } else {
+            self.move_time += dt;
+        }
+
+        let wheel_velocity = scroll_velocity(cfg, &held);
+        if wheel_velocity == (0.0, 0.0) {
+            self.scroll_acc = (0.0, 0.0);
+        }
+
+        MouseKeysMotion {
+            pointer: accumulate(&mut self.move_acc, velocity, dt),
+            wheel: accumulate(&mut self.scroll_acc, wheel_velocity, dt),
+        }
+    }
+}
+
+fn accumulate(acc: &mut (f32, f32), velocity: (f32, f32), dt: f32) -> (i32, i32) {
+    acc.0 += velocity.0 * dt;
+    acc.1 += velocity.1 * dt;
+
+    let whole = (acc.0.trunc(), acc.1.trunc());
+    acc.0 -= whole.0;
+    acc.1 -= whole.1;
+
+    (whole.0 as i32, whole.1 as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: MouseKeysConfig = MouseKeysConfig {
+        base_speed: 100.0,
+        max_speed: 1100.0,
+        accel_time: 1.0,
+        accel_curve: 2.0,
+        precision_factor: 0.5,
+        scroll_speed: 240.0,
+    };
+
+    fn right() -> HeldKeys {
+        HeldKeys {
+            right: true,
+            ..HeldKeys::default()
+        }
+    }
+
+    #[test]
+    fn acceleration() {
+        assert_eq!(pointer_velocity(&CFG, &right(), 0.0), (100.0, 0.0));
+        // A quarter of the way there at half the time, with the quadratic curve
+        assert_eq!(pointer_velocity(&CFG, &right(), 0.5), (350.0, 0.0));
+        assert_eq!(pointer_velocity(&CFG, &right(), 1.0), (1100.0, 0.0));
+    }
+
+    #[test]
+    fn speed_cap() {
+        assert_eq!(pointer_velocity(&CFG, &right(), 10.0), (1100.0, 0.0));
+
+        let precise = HeldKeys {
+            precision: true,
+            ..right()
+        };
+        assert_eq!(pointer_velocity(&CFG, &precise, 10.0), (550.0, 0.0));
+    }
+
+    #[test]
+    fn diagonal_motion_is_no_faster() {
+        let held = HeldKeys {
+            right: true,
+            up: true,
+            ..HeldKeys::default()
+        };
+        let (x, y) = pointer_velocity(&CFG, &held, 0.0);
+        assert!((x + y).abs() < 1e-3);
+        assert!(((x * x + y * y).sqrt() - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn opposite_keys_cancel_out() {
+        let held = HeldKeys {
+            left: true,
+            right: true,
+            ..HeldKeys::default()
+        };
+        assert_eq!(pointer_velocity(&CFG, &held, 1.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn step_carries_sub_pixel_motion() {
+        let mut state = MouseKeysState::new();
+        state.held = right();
+
+        // 100 pixels per second, in steps of 1/8 of a pixel
+        let pixels: i32 = (0..16).map(|_| state.step(&CFG, 0.00125).pointer.0).sum();
+        assert_eq!(pixels, 2);
+    }
+
+    #[test]
+    fn step_accelerates_while_held() {
+        let mut state = MouseKeysState::new();
+        state.held = right();
+
+        let first = state.step(&CFG, 0.25).pointer.0;
+        state.step(&CFG, 0.25);
+        state.step(&CFG, 0.25);
+        let fourth = state.step(&CFG, 0.25).pointer.0;
+        assert!(fourth > first * 5);
+
+        // Letting go starts over at the base speed
+        state.held = HeldKeys::default();
+        state.step(&CFG, 0.25);
+        state.held = right();
+        assert_eq!(state.step(&CFG, 0.25).pointer, (25, 0));
+    }
+
+    #[test]
+    fn scroll() {
+        let mut state = MouseKeysState::new();
+        state.held = HeldKeys {
+            scroll_down: true,
+            ..HeldKeys::default()
+        };
+        assert_eq!(state.step(&CFG, 0.5).wheel, (0, -120));
+    }
+
+    #[test]
+    fn buttons_ignore_auto_repeat() {
+        let mut state = MouseKeysState::new();
+        assert!(state.press_button(MouseButton::Left));
+        assert!(!state.press_button(MouseButton::Left));
+        assert_eq!(state.release_all(), vec![MouseButton::Left]);
+        assert!(!state.release_button(MouseButton::Left));
+    }
+}
@@ -0,0 +1,429 @@
+// Caps+middle-drag scroll emulation.
+//
+// Mouse motion relative to where the middle button went down is shaped, blended
+// into a scroll rate, and emitted as wheel input by the timer thread every tick.
+// Everything in here is plain math; the winapi side lives next to the hooks.
+
+pub struct ScrollEmuConfig {
+    // Motion below this many pixels from the anchor is ignored
+    pub dead_zone: f32,
+    // Power applied to the distance from the anchor; higher values make far drags scroll faster
+    pub curve_exponent: f32,
+    // How much of each new mouse sample goes into the scroll rate
+    pub blend: f32,
+    // Per-tick decay of the scroll rate while the middle button is held
+    pub decay: f32,
+
+    // Keep scrolling after the middle button is released, slowing down by `inertia_decay` per tick
+    pub inertia: bool,
+    pub inertia_decay: f32,
+    // Coasting stops once the rate drops below this many wheel units per tick
+    pub inertia_min_rate: f32,
+
+    // Only scroll along the dominant direction of the drag,
+    // decided once the cursor travels `axis_lock_distance` pixels from the anchor
+    pub axis_lock: bool,
+    pub axis_lock_distance: f32,
+
+    // Content follows the cursor, as with touch screens
+    pub natural: bool,
+
+    // Rate multipliers for windows owned by the given executables, e.g. ("firefox.exe", 0.5)
+    pub app_multipliers: &'static [(&'static str, f32)],
+
+    // Emit wheel amounts smaller than WHEEL_DELTA, for smooth scrolling in apps which support it
+    pub high_res: bool,
+    // Executables which only understand whole WHEEL_DELTA notches, regardless of `high_res`
+    pub notched_apps: &'static [&'static str],
+}
+
+pub const SCROLL_EMU_CONFIG: ScrollEmuConfig = ScrollEmuConfig {
+    dead_zone: 0.0,
+    curve_exponent: 1.5,
+    blend: 0.3,
+    decay: 0.92,
+
+    inertia: false,
+    inertia_decay: 0.95,
+    inertia_min_rate: 1.0,
+
+    axis_lock: false,
+    axis_lock_distance: 8.0,
+
+    natural: false,
+
+    app_multipliers: &[],
+
+    high_res: true,
+    notched_apps: &[],
+};
+
+// One notch of a traditional mouse wheel
+pub const WHEEL_DELTA: f32 = 120.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScrollAxis {
+    Horizontal,
+    Vertical,
+}
+
+pub struct ScrollEmuState {
+    pub scroll_emu_on: bool,
+    pub scroll_emu_from: (i32, i32),
+    pub scroll_emu_acc: (f32, f32),
+
+    coasting: bool,
+    locked_axis: Option<ScrollAxis>,
+    multiplier: f32,
+
+    // Wheel amount per emitted step; 1 for high resolution output, WHEEL_DELTA for notches
+    output_step: f32,
+    // Fractional output carried over to the next tick
+    remainder: (f32, f32),
+}
+
+// Looks up the rate multiplier for the executable owning the window being scrolled
+fn app_multiplier(cfg: &ScrollEmuConfig, process_name: Option<&str>) -> f32 {
+    let process_name = match process_name {
+        Some(name) => name,
+        None => return 1.0,
+    };
+
+    cfg.app_multipliers
+        .iter()
+        .find(|&&(app, _)| app.eq_ignore_ascii_case(process_name))
+        .map(|&(_, multiplier)| multiplier)
+        .unwrap_or(1.0)
+}
+
+fn output_step(cfg: &ScrollEmuConfig, process_name: Option<&str>) -> f32 {
+    let notched = match process_name {
+        Some(name) => cfg
+            .notched_apps
+            .iter()
+            .any(|app| app.eq_ignore_ascii_case(name)),
+        None => false,
+    };
+
+    if cfg.high_res && !notched {
+        1.0
+    } else {
+        WHEEL_DELTA
+    }
+}
+
+// Turns a fractional wheel amount into a whole number of `step`-sized units,
+// carrying whatever doesn't fit over to the next call
+pub fn quantize(amount: f32, remainder: &mut f32, step: f32) -> i32 {
+    let total = amount + *remainder;
+    let steps = (total / step).trunc();
+    *remainder = total - steps * step;
+    (steps * step) as i32
+}
+
+fn shape(cfg: &ScrollEmuConfig, delta: f32) -> f32 {
+    if delta.abs() <= cfg.dead_zone {
+        0.0
+    } else {
+        delta.signum() * delta.abs().powf(cfg.curve_exponent)
+    }
+}
+
+impl ScrollEmuState {
+    pub fn new() -> ScrollEmuState {
+        ScrollEmuState {
+            scroll_emu_on: false,
+            scroll_emu_from: (0, 0),
+            scroll_emu_acc: (0f32, 0f32),
+
+            coasting: false,
+            locked_axis: None,
+            multiplier: 1.0,
+
+            output_step: 1.0,
+            remainder: (0f32, 0f32),
+        }
+    }
+
+    // Middle button down; `process_name` is the executable owning the window under the cursor
+    pub fn begin(&mut self, cfg: &ScrollEmuConfig, pt: (i32, i32), process_name: Option<&str>) {
+        self.scroll_emu_from = pt;
+        self.scroll_emu_acc = (0f32, 0f32);
+        self.scroll_emu_on = true;
+        self.coasting = false;
+        self.locked_axis = None;
+        self.multiplier = app_multiplier(cfg, process_name);
+        self.output_step = output_step(cfg, process_name);
+        self.remainder = (0f32, 0f32);
+    }
+
+    // Middle button up
+    pub fn end(&mut self, cfg: &ScrollEmuConfig) {
+        self.scroll_emu_on = false;
+        self.coasting = cfg.inertia;
+    }
+
+    // Stop right away, without coasting
+    pub fn stop(&mut self) {
+        self.scroll_emu_on = false;
+        self.coasting = false;
+    }
+
+    pub fn mouse_moved(&mut self, cfg: &ScrollEmuConfig, pt: (i32, i32)) {
+        if !self.scroll_emu_on {
+            return;
+        }
+
+        let hscroll = (pt.0 - self.scroll_emu_from.0) as f32;
+        let vscroll = (self.scroll_emu_from.1 - pt.1) as f32;
+
+        if cfg.axis_lock && self.locked_axis.is_none() {
+            if hscroll.abs().max(vscroll.abs()) >= cfg.axis_lock_distance {
+                self.locked_axis = Some(if hscroll.abs() > vscroll.abs() {
+                    ScrollAxis::Horizontal
+                } else {
+                    ScrollAxis::Vertical
+                });
+            }
+        }
+
+        let (hscroll, vscroll) = match self.locked_axis {
+            Some(ScrollAxis::Horizontal) => (hscroll, 0.0),
+            Some(ScrollAxis::Vertical) => (0.0, vscroll),
+            None => (hscroll, vscroll),
+        };
+
+        let direction = if cfg.natural { -1.0 } else { 1.0 };
+        let hscroll = shape(cfg, hscroll) * direction * self.multiplier;
+        let vscroll = shape(cfg, vscroll) * direction * self.multiplier;
+
+        let t = cfg.blend;
+        self.scroll_emu_acc.0 = self.scroll_emu_acc.0 * (1.0f32 - t) + hscroll * t;
+        self.scroll_emu_acc.1 = self.scroll_emu_acc.1 * (1.0f32 - t) + vscroll * t;
+    }
+
+    // Advances by one timer tick, and returns the wheel amounts to emit
+    pub fn tick(&mut self, cfg: &ScrollEmuConfig) -> (i32, i32) {
+        let decay = if self.scroll_emu_on {
+            cfg.decay
+        } else if self.coasting {
+            cfg.inertia_decay
+        } else {
+            return (0, 0);
+        };
+
+        self.scroll_emu_acc = (self.scroll_emu_acc.0 * decay, self.scroll_emu_acc.1 * decay);
+
+        if self.coasting
+            && self.scroll_emu_acc.0.abs() < cfg.inertia_min_rate
+            && self.scroll_emu_acc.1.abs() < cfg.inertia_min_rate
+        {
+            self.coasting = false;
+            self.remainder = (0f32, 0f32);
+            return (0, 0);
+        }
+
+        (
+            quantize(
+                self.scroll_emu_acc.0,
+                &mut self.remainder.0,
+                self.output_step,
+            ),
+            quantize(
+                self.scroll_emu_acc.1,
+                &mut self.remainder.1,
+                self.output_step,
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Samples go straight into the rate, which then stays put, so each tick emits the same
+    const CFG: ScrollEmuConfig = ScrollEmuConfig {
+        blend: 1.0,
+        decay: 1.0,
+        app_multipliers: &[("slow.exe", 0.25)],
+        notched_apps: &["notched.exe"],
+        ..SCROLL_EMU_CONFIG
+    };
+
+    fn drag(cfg: &ScrollEmuConfig, process_name: Option<&str>, to: (i32, i32)) -> ScrollEmuState {
+        let mut state = ScrollEmuState::new();
+        state.begin(cfg, (100, 100), process_name);
+        state.mouse_moved(cfg, to);
+        state
+    }
+
+    fn ticks(state: &mut ScrollEmuState, cfg: &ScrollEmuConfig, n: usize) -> Vec<(i32, i32)> {
+        (0..n).map(|_| state.tick(cfg)).collect()
+    }
+
+    #[test]
+    fn curve() {
+        // 4 and 9 pixels, to the power of 1.5; up scrolls up
+        let mut state = drag(&CFG, None, (109, 96));
+        assert_eq!(state.tick(&CFG), (27, 8));
+
+        let cfg = ScrollEmuConfig {
+            natural: true,
+            ..CFG
+        };
+        let mut state = drag(&cfg, None, (109, 96));
+        assert_eq!(state.tick(&cfg), (-27, -8));
+    }
+
+    #[test]
+    fn dead_zone() {
+        let cfg = ScrollEmuConfig {
+            dead_zone: 4.0,
+            ..CFG
+        };
+        let mut state = drag(&cfg, None, (104, 91));
+        assert_eq!(state.tick(&cfg), (0, 27));
+    }
+
+    #[test]
+    fn blend_and_decay() {
+        let cfg = ScrollEmuConfig {
+            blend: 0.5,
+            decay: 0.5,
+            ..CFG
+        };
+        let mut state = drag(&cfg, None, (100, 84));
+        // 16 pixels make a rate of 64, half of it blended in, then halved by each tick
+        assert_eq!(ticks(&mut state, &cfg, 3), vec![(0, 16), (0, 8), (0, 4)]);
+    }
+
+    #[test]
+    fn axis_lock() {
+        let cfg = ScrollEmuConfig {
+            curve_exponent: 1.0,
+            axis_lock: true,
+            ..CFG
+        };
+        let mut state = ScrollEmuState::new();
+        state.begin(&cfg, (100, 100), None);
+
+        // Not far enough to decide yet
+        state.mouse_moved(&cfg, (103, 98));
+        assert_eq!(state.tick(&cfg), (3, 2));
+
+        state.mouse_moved(&cfg, (110, 96));
+        assert_eq!(state.tick(&cfg), (10, 0));
+
+        // Stays horizontal, even once the drag turns vertical
+        state.mouse_moved(&cfg, (102, 80));
+        assert_eq!(state.tick(&cfg), (2, 0));
+    }
+
+    #[test]
+    fn remainder_carried_over() {
+        let cfg = ScrollEmuConfig {
+            curve_exponent: 1.0,
+            ..CFG
+        };
+        // A quarter of a unit per tick
+        let mut state = drag(&cfg, Some("SLOW.EXE"), (100, 99));
+        let emitted = ticks(&mut state, &cfg, 8);
+        assert_eq!(emitted[..4], [(0, 0), (0, 0), (0, 0), (0, 1)]);
+        assert_eq!(emitted.iter().map(|&(_, v)| v).sum::<i32>(), 2);
+    }
+
+    #[test]
+    fn quantize_adds_up_to_whole_notches() {
+        let mut remainder = 0.0;
+        let notches: Vec<i32> = (0..8)
+            .map(|_| quantize(30.0, &mut remainder, WHEEL_DELTA))
+            .collect();
+        assert_eq!(notches, vec![0, 0, 0, 120, 0, 0, 0, 120]);
+        assert_eq!(remainder, 0.0);
+
+        // Backwards too, keeping the rest for later
+        let mut remainder = 0.0;
+        let notches: Vec<i32> = (0..3)
+            .map(|_| quantize(-50.0, &mut remainder, WHEEL_DELTA))
+            .collect();
+        assert_eq!(notches, vec![0, 0, -120]);
+        assert_eq!(remainder, -30.0);
+    }
+
+    #[test]
+    fn quantize_high_res() {
+        let mut remainder = 0.0;
+        let units: Vec<i32> = [0.5, 0.5, 2.75, 0.25]
+            .iter()
+            .map(|&amount| quantize(amount, &mut remainder, 1.0))
+            .collect();
+        assert_eq!(units, vec![0, 1, 2, 1]);
+        assert_eq!(remainder, 0.0);
+    }
+
+    #[test]
+    fn output_steps() {
+        assert_eq!(output_step(&CFG, None), 1.0);
+        assert_eq!(output_step(&CFG, Some("firefox.exe")), 1.0);
+        assert_eq!(output_step(&CFG, Some("Notched.exe")), WHEEL_DELTA);
+
+        let cfg = ScrollEmuConfig {
+            high_res: false,
+            ..CFG
+        };
+        assert_eq!(output_step(&cfg, None), WHEEL_DELTA);
+    }
+
+    #[test]
+    fn notched_app_gets_whole_notches() {
+        let cfg = ScrollEmuConfig {
+            curve_exponent: 1.0,
+            ..CFG
+        };
+        // 30 units per tick
+        let mut state = drag(&cfg, Some("notched.exe"), (100, 70));
+        assert_eq!(
+            ticks(&mut state, &cfg, 8),
+            vec![
+                (0, 0),
+                (0, 0),
+                (0, 0),
+                (0, 120),
+                (0, 0),
+                (0, 0),
+                (0, 0),
+                (0, 120),
+            ]
+        );
+    }
+
+    #[test]
+    fn inertia() {
+        let cfg = ScrollEmuConfig {
+            inertia: true,
+            inertia_decay: 0.5,
+            ..CFG
+        };
+        let mut state = drag(&cfg, None, (100, 96));
+        assert_eq!(state.tick(&cfg), (0, 8));
+
+        state.end(&cfg);
+        assert_eq!(
+            ticks(&mut state, &cfg, 5),
+            vec![(0, 4), (0, 2), (0, 1), (0, 0), (0, 0)]
+        );
+    }
+
+    #[test]
+    fn stop_ends_inertia() {
+        let cfg = ScrollEmuConfig {
+            inertia: true,
+            ..CFG
+        };
+        let mut state = drag(&cfg, None, (100, 96));
+        state.end(&cfg);
+        state.stop();
+        assert_eq!(state.tick(&cfg), (0, 0));
+    }
+}
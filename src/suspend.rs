@@ -0,0 +1,136 @@
+// The chord toggling suspension, with Left Ctrl and Left Alt held.
+//
+// While suspended, everything but the chord passes through untouched. With Ctrl down, Windows
+// reports the Pause key as Break (VK_CANCEL), so both count. Keyboards without either can add
+// a key of their own, and `h3keys3 resume` works regardless.
+
+pub struct SuspendConfig {
+    // Virtual keys which toggle suspension when pressed with Left Ctrl and Left Alt
+    pub keys: &'static [u8],
+}
+
+pub const SUSPEND_CONFIG: SuspendConfig = SuspendConfig {
+    // VK_PAUSE and VK_CANCEL
+    keys: &[0x13, 0x03],
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChordKey {
+    // Not part of the chord
+    Other,
+    // Swallow the key without toggling: auto-repeat, or the release of the chord key
+    Swallow,
+    Toggle,
+}
+
+pub struct SuspendChord {
+    // The chord key went down with the modifiers held, and hasn't come up yet
+    key_down: bool,
+}
+
+impl SuspendChord {
+    pub fn new() -> SuspendChord {
+        SuspendChord { key_down: false }
+    }
+
+    pub fn key(
+        &mut self,
+        cfg: &SuspendConfig,
+        vk: u8,
+        key_pressed: bool,
+        ctrl_on: bool,
+        alt_on: bool,
+    ) -> ChordKey {
+        if !cfg.keys.contains(&vk) {
+            return ChordKey::Other;
+        }
+
+        if !key_pressed {
+            // The modifiers may well have come up first
+            return if self.key_down {
+                self.key_down = false;
+                ChordKey::Swallow
+            } else {
+                ChordKey::Other
+            };
+        }
+
+        if !(ctrl_on && alt_on) {
+            return ChordKey::Other;
+        }
+
+        if self.key_down {
+            ChordKey::Swallow
+        } else {
+            self.key_down = true;
+            ChordKey::Toggle
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use self::ChordKey::*;
+    use super::*;
+
+    const PAUSE: u8 = 0x13;
+    const BREAK: u8 = 0x03;
+
+    // (vk, pressed, Ctrl held, Alt held)
+    fn run(cfg: &SuspendConfig, events: &[(u8, bool, bool, bool)]) -> Vec<ChordKey> {
+        let mut chord = SuspendChord::new();
+        events
+            .iter()
+            .map(|&(vk, pressed, ctrl, alt)| chord.key(cfg, vk, pressed, ctrl, alt))
+            .collect()
+    }
+
+    #[test]
+    fn toggles_with_ctrl_alt() {
+        let events = [
+            (PAUSE, true, true, true),
+            (PAUSE, false, true, true),
+            // What the Pause key comes in as with Ctrl down
+            (BREAK, true, true, true),
+            (BREAK, false, true, true),
+        ];
+        assert_eq!(
+            run(&SUSPEND_CONFIG, &events),
+            vec![Toggle, Swallow, Toggle, Swallow]
+        );
+    }
+
+    #[test]
+    fn needs_both_modifiers() {
+        let events = [
+            (PAUSE, true, true, false),
+            (PAUSE, false, true, false),
+            (PAUSE, true, false, true),
+            (b'P', true, true, true),
+        ];
+        assert_eq!(run(&SUSPEND_CONFIG, &events), vec![Other; 4]);
+    }
+
+    #[test]
+    fn auto_repeat_toggles_once() {
+        let events = [
+            (PAUSE, true, true, true),
+            (PAUSE, true, true, true),
+            (PAUSE, true, true, true),
+            // Released after the modifiers, and still swallowed
+            (PAUSE, false, false, false),
+            (PAUSE, true, true, true),
+        ];
+        assert_eq!(
+            run(&SUSPEND_CONFIG, &events),
+            vec![Toggle, Swallow, Swallow, Swallow, Toggle]
+        );
+    }
+
+    #[test]
+    fn extra_keys() {
+        let cfg = SuspendConfig { keys: &[0x7B] };
+        let events = [(0x7B, true, true, true), (PAUSE, true, true, true)];
+        assert_eq!(run(&cfg, &events), vec![Toggle, Other]);
+    }
+}
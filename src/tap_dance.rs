@@ -0,0 +1,224 @@
+// Tap-dance: one key, different actions depending on how many times it's tapped,
+// and whether the last tap was held.
+//
+// Everything is decided from event timestamps, so the same sequence of presses, releases
+// and timer ticks always resolves the same way.
+
+pub struct TapDanceConfig {
+    // Longest gap between taps, and shortest press counting as a hold, in milliseconds
+    pub tapping_term_ms: u32,
+}
+
+pub const TAP_DANCE_CONFIG: TapDanceConfig = TapDanceConfig {
+    tapping_term_ms: 200,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TapDance {
+    pub taps: u32,
+    // The last tap was held down for the tapping term
+    pub held: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Dance {
+    key: char,
+    // Reaching this many taps resolves right away, without waiting for another one
+    max_taps: u32,
+    taps: u32,
+    down: bool,
+    // Time of the last press or release
+    since: u32,
+}
+
+impl Dance {
+    fn resolve(&self, cfg: &TapDanceConfig, now: u32) -> TapDance {
+        TapDance {
+            taps: self.taps,
+            held: self.down && now.wrapping_sub(self.since) >= cfg.tapping_term_ms,
+        }
+    }
+}
+
+pub struct TapDanceState {
+    dance: Option<Dance>,
+    // Resolved as held while still down; its auto-repeat and release are ignored
+    held_key: Option<char>,
+}
+
+impl TapDanceState {
+    pub fn new() -> TapDanceState {
+        TapDanceState {
+            dance: None,
+            held_key: None,
+        }
+    }
+
+    // Pressing another key ends the dance in progress, with what it got up to so far
+    pub fn interrupt(
+        &mut self,
+        cfg: &TapDanceConfig,
+        key: char,
+        now: u32,
+    ) -> Option<(char, TapDance)> {
+        match self.dance {
+            Some(dance) if dance.key != key => self.finish(cfg, now),
+            _ => None,
+        }
+    }
+
+    pub fn press(&mut self, key: char, max_taps: u32, now: u32) {
+        if self.held_key == Some(key) {
+            return;
+        }
+
+        match self.dance {
+            // Auto-repeat
+            Some(ref dance) if dance.key == key && dance.down => (),
+            Some(ref mut dance) if dance.key == key => {
+                dance.taps += 1;
+                dance.down = true;
+                dance.since = now;
+            }
+            _ => {
+                self.dance = Some(Dance {
+                    key,
+                    max_taps,
+                    taps: 1,
+                    down: true,
+                    since: now,
+                })
+            }
+        }
+    }
+
+    pub fn release(&mut self, cfg: &TapDanceConfig, key: char, now: u32) -> Option<TapDance> {
+        if self.held_key == Some(key) {
+            self.held_key = None;
+            return None;
+        }
+
+        let mut dance = match self.dance {
+            Some(dance) if dance.key == key && dance.down => dance,
+            _ => return None,
+        };
+
+        let resolved = dance.resolve(cfg, now);
+        if resolved.held || resolved.taps >= dance.max_taps {
+            self.dance = None;
+            return Some(resolved);
+        }
+
+        dance.down = false;
+        dance.since = now;
+        self.dance = Some(dance);
+        None
+    }
+
+    // Timer thread: resolves a dance once the key's been held, or left alone, for the tapping term
+    pub fn tick(&mut self, cfg: &TapDanceConfig, now: u32) -> Option<(char, TapDance)> {
+        let dance = self.dance?;
+        if now.wrapping_sub(dance.since) < cfg.tapping_term_ms {
+            return None;
+        }
+
+        let resolved = self.finish(cfg, now);
+        if dance.down {
+            self.held_key = Some(dance.key);
+        }
+        resolved
+    }
+
+    // Resolves whatever is in progress right away. A key resolved as held is forgotten too, as
+    // its release may never reach the tap-dance code, e.g. with Caps released before it.
+    pub fn finish(&mut self, cfg: &TapDanceConfig, now: u32) -> Option<(char, TapDance)> {
+        self.held_key = None;
+        self.dance
+            .take()
+            .map(|dance| (dance.key, dance.resolve(cfg, now)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: TapDanceConfig = TapDanceConfig {
+        tapping_term_ms: 200,
+    };
+
+    fn dance(taps: u32, held: bool) -> Option<(char, TapDance)> {
+        Some((';', TapDance { taps, held }))
+    }
+
+    #[test]
+    fn single_tap() {
+        let mut state = TapDanceState::new();
+        state.press(';', 2, 0);
+        assert_eq!(state.release(&CFG, ';', 50), None);
+        assert_eq!(state.tick(&CFG, 150), None);
+        assert_eq!(state.tick(&CFG, 250), dance(1, false));
+        assert_eq!(state.tick(&CFG, 500), None);
+    }
+
+    #[test]
+    fn double_tap_resolves_at_max_taps() {
+        let mut state = TapDanceState::new();
+        state.press(';', 2, 0);
+        assert_eq!(state.release(&CFG, ';', 50), None);
+        state.press(';', 2, 100);
+        assert_eq!(
+            state.release(&CFG, ';', 150),
+            Some(TapDance {
+                taps: 2,
+                held: false,
+            })
+        );
+        assert_eq!(state.tick(&CFG, 400), None);
+    }
+
+    #[test]
+    fn hold_ignores_repeat_and_release() {
+        let mut state = TapDanceState::new();
+        state.press(';', 2, 0);
+        assert_eq!(state.tick(&CFG, 100), None);
+        assert_eq!(state.tick(&CFG, 210), dance(1, true));
+
+        // Auto-repeat, then the release
+        state.press(';', 2, 250);
+        assert_eq!(state.tick(&CFG, 500), None);
+        assert_eq!(state.release(&CFG, ';', 600), None);
+
+        state.press(';', 2, 1000);
+        assert_eq!(state.release(&CFG, ';', 1050), None);
+        assert_eq!(state.tick(&CFG, 1300), dance(1, false));
+    }
+
+    #[test]
+    fn interrupted_by_another_key() {
+        let mut state = TapDanceState::new();
+        state.press(';', 3, 0);
+        assert_eq!(state.release(&CFG, ';', 50), None);
+        state.press(';', 3, 100);
+
+        // The key itself doesn't interrupt
+        assert_eq!(state.interrupt(&CFG, ';', 120), None);
+        assert_eq!(state.interrupt(&CFG, 'K', 130), dance(2, false));
+        assert_eq!(state.release(&CFG, ';', 160), None);
+        assert_eq!(state.tick(&CFG, 500), None);
+    }
+
+    #[test]
+    fn caps_released_before_held_key() {
+        let mut state = TapDanceState::new();
+        state.press(';', 2, 0);
+        assert_eq!(state.tick(&CFG, 250), dance(1, true));
+
+        // Caps goes up first, so the release of ; never reaches the tap-dance code
+        assert_eq!(state.finish(&CFG, 300), None);
+
+        state.press(';', 2, 1000);
+        assert_eq!(state.release(&CFG, ';', 1050), None);
+        assert_eq!(state.tick(&CFG, 1300), dance(1, false));
+    }
+}
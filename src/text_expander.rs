@@ -0,0 +1,244 @@
+// Text expansion: typing an abbreviation replaces it with a snippet.
+//
+// The expander sees the characters keys actually type, after any layout remapping, and keeps
+// the last few in a rolling buffer. Anything which might move the caret resets the buffer,
+// so an abbreviation only fires when it was typed in one go.
+
+pub struct Snippet {
+    // Case sensitive; fires as soon as its last character is typed
+    pub abbreviation: &'static str,
+    // May contain {date}, {time} and {clipboard},
+    // plus a single {cursor} marking where the caret ends up
+    pub expansion: &'static str,
+}
+
+// If several abbreviations end with the same character, the first one listed wins
+pub const SNIPPETS: &[Snippet] = &[
+    Snippet {
+        abbreviation: ";date",
+        expansion: "{date}",
+    },
+    Snippet {
+        abbreviation: ";time",
+        expansion: "{time}",
+    },
+    Snippet {
+        abbreviation: ";now",
+        expansion: "{date} {time}",
+    },
+    Snippet {
+        abbreviation: ";quote",
+        expansion: "\u{201c}{clipboard}\u{201d}{cursor}",
+    },
+    Snippet {
+        abbreviation: ";sig",
+        expansion: "Best regards,\n{cursor}",
+    },
+];
+
+pub const CLIPBOARD_PLACEHOLDER: &str = "{clipboard}";
+const CURSOR_PLACEHOLDER: &str = "{cursor}";
+
+pub struct Placeholders {
+    pub date: String,
+    pub time: String,
+    // Only needs filling in when the snippet uses it
+    pub clipboard: String,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Expansion {
+    // Characters of the abbreviation already typed out, which need erasing
+    pub backspaces: usize,
+    // Line breaks are always "\n"
+    pub text: String,
+    // Left presses taking the caret from the end of `text` to the {cursor} spot
+    pub cursor_back: usize,
+}
+
+fn fill_placeholders(text: &str, placeholders: &Placeholders) -> String {
+    text.replace("{date}", &placeholders.date)
+        .replace("{time}", &placeholders.time)
+        .replace(CLIPBOARD_PLACEHOLDER, &placeholders.clipboard)
+        .replace("\r\n", "\n")
+}
+
+// `snippet`'s abbreviation is expected to be typed out except for its last character,
+// which the caller swallows
+pub fn expand(snippet: &Snippet, placeholders: &Placeholders) -> Expansion {
+    // Split at the cursor first, so nothing pasted in from the clipboard can move it
+    let (before, after) = match snippet.expansion.find(CURSOR_PLACEHOLDER) {
+        Some(pos) => (
+            &snippet.expansion[..pos],
+            &snippet.expansion[pos + CURSOR_PLACEHOLDER.len()..],
+        ),
+        None => (snippet.expansion, ""),
+    };
+
+    let before = fill_placeholders(before, placeholders);
+    let after = fill_placeholders(after, placeholders);
+
+    Expansion {
+        backspaces: snippet.abbreviation.chars().count().saturating_sub(1),
+        cursor_back: after.chars().count(),
+        text: before + &after,
+    }
+}
+
+pub struct TextExpander {
+    buffer: String,
+}
+
+impl TextExpander {
+    pub fn new() -> TextExpander {
+        TextExpander {
+            buffer: String::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    pub fn backspace(&mut self) {
+        self.buffer.pop();
+    }
+
+    // Returns the snippet whose abbreviation `c` completes, if any
+    pub fn typed(&mut self, snippets: &'static [Snippet], c: char) -> Option<&'static Snippet> {
+        self.buffer.push(c);
+
+        let max_len = snippets
+            .iter()
+            .map(|snippet| snippet.abbreviation.chars().count())
+            .max()
+            .unwrap_or(0);
+        while self.buffer.chars().count() > max_len {
+            self.buffer.remove(0);
+        }
+
+        let snippet = snippets
+            .iter()
+            .find(|snippet| self.buffer.ends_with(snippet.abbreviation));
+
+        if snippet.is_some() {
+            self.buffer.clear();
+        }
+
+        snippet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SNIPPETS: &[Snippet] = &[
+        Snippet {
+            abbreviation: ";sig",
+            expansion: "Best regards,\n{cursor}",
+        },
+        Snippet {
+            abbreviation: "ig",
+            expansion: "shadowed by ;sig",
+        },
+        Snippet {
+            abbreviation: ";quote",
+            expansion: "\u{201c}{clipboard}\u{201d}{cursor}",
+        },
+        Snippet {
+            abbreviation: ";Now",
+            expansion: "{date} {time}",
+        },
+    ];
+
+    fn placeholders(clipboard: &str) -> Placeholders {
+        Placeholders {
+            date: "2018-03-14".to_owned(),
+            time: "15:09".to_owned(),
+            clipboard: clipboard.to_owned(),
+        }
+    }
+
+    // The abbreviation the last character completed, if any
+    fn type_text(expander: &mut TextExpander, text: &str) -> Option<&'static str> {
+        let mut fired = None;
+        for c in text.chars() {
+            fired = expander.typed(SNIPPETS, c).map(|s| s.abbreviation);
+        }
+        fired
+    }
+
+    #[test]
+    fn fires_on_last_character() {
+        let mut expander = TextExpander::new();
+        assert_eq!(type_text(&mut expander, "hello ;si"), None);
+        assert_eq!(type_text(&mut expander, "g"), Some(";sig"));
+        // The buffer starts over after firing
+        assert_eq!(type_text(&mut expander, "g"), None);
+        // Without the ;, the shorter one fires
+        assert_eq!(type_text(&mut expander, "big"), Some("ig"));
+    }
+
+    #[test]
+    fn case_sensitive() {
+        let mut expander = TextExpander::new();
+        assert_eq!(type_text(&mut expander, ";now"), None);
+        assert_eq!(type_text(&mut expander, ";Now"), Some(";Now"));
+    }
+
+    #[test]
+    fn long_typing_keeps_matching() {
+        let mut expander = TextExpander::new();
+        let text: String = ::std::iter::repeat('x').take(1000).collect();
+        assert_eq!(type_text(&mut expander, &text), None);
+        assert_eq!(type_text(&mut expander, ";quote"), Some(";quote"));
+    }
+
+    #[test]
+    fn reset_on_click() {
+        let mut expander = TextExpander::new();
+        type_text(&mut expander, ";qu");
+        // Clicking may have moved the caret somewhere else
+        expander.reset();
+        assert_eq!(type_text(&mut expander, "ote"), None);
+    }
+
+    #[test]
+    fn backspace_edits_the_buffer() {
+        let mut expander = TextExpander::new();
+        type_text(&mut expander, ";six");
+        expander.backspace();
+        expander.backspace();
+        assert_eq!(type_text(&mut expander, "ig"), Some(";sig"));
+
+        // Backspace with nothing typed does nothing
+        expander.backspace();
+        assert_eq!(type_text(&mut expander, ";Now"), Some(";Now"));
+    }
+
+    #[test]
+    fn expand_cursor_and_backspaces() {
+        assert_eq!(
+            expand(&SNIPPETS[0], &placeholders("")),
+            Expansion {
+                backspaces: 3,
+                text: "Best regards,\n".to_owned(),
+                cursor_back: 0,
+            }
+        );
+        assert_eq!(
+            expand(&SNIPPETS[3], &placeholders("")).text,
+            "2018-03-14 15:09"
+        );
+    }
+
+    #[test]
+    fn expand_clipboard() {
+        let expansion = expand(&SNIPPETS[2], &placeholders("line\r\n{cursor}"));
+        // Clipboard text can't move the cursor, and gets its line breaks normalized
+        assert_eq!(expansion.text, "\u{201c}line\n{cursor}\u{201d}");
+        assert_eq!(expansion.cursor_back, 0);
+        assert_eq!(expansion.backspaces, 5);
+    }
+}
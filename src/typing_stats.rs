@@ -0,0 +1,731 @@
+// Opt-in typing statistics: key and layer usage, bigrams, same-finger bigrams and layer key
+// hold times, with a keyboard heatmap.
+//
+// Everything is counted by physical key, so nothing typed can be read back out, and counts
+// from different sessions and machines simply add up. In privacy mode only per-key counts
+// and hold times are kept, and nothing about which keys follow which.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+pub struct TypingStatsConfig {
+    pub enabled: bool,
+    // Per-key counts and hold times only
+    pub privacy_mode: bool,
+    // Key presses further apart than this don't make a bigram, in milliseconds
+    pub bigram_gap_ms: u32,
+    // Presses between saves
+    pub save_every: u32,
+}
+
+pub const TYPING_STATS_CONFIG: TypingStatsConfig = TypingStatsConfig {
+    enabled: false,
+    privacy_mode: false,
+    bigram_gap_ms: 1000,
+    save_every: 200,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum StatsLayer {
+    Base,
+    Caps,
+    Pipe,
+}
+
+const LAYERS: &[(StatsLayer, &str)] = &[
+    (StatsLayer::Base, "base"),
+    (StatsLayer::Caps, "caps"),
+    (StatsLayer::Pipe, "pipe"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum StatsLayout {
+    Qwerty,
+    Colemak,
+}
+
+const LAYOUTS: &[(StatsLayout, &str)] = &[
+    (StatsLayout::Qwerty, "qwerty"),
+    (StatsLayout::Colemak, "colemak"),
+];
+
+fn name_of<T: PartialEq>(names: &[(T, &'static str)], item: T) -> &'static str {
+    names
+        .iter()
+        .find(|&&(ref i, _)| *i == item)
+        .map_or("", |&(_, name)| name)
+}
+
+fn parse_name<T: Copy>(names: &[(T, &'static str)], name: &str) -> Option<T> {
+    names.iter().find(|&&(_, n)| n == name).map(|&(i, _)| i)
+}
+
+// Physical keys of the main block, by row, with each row's offset in key widths
+const ROWS: &[(f32, &[u8])] = &[
+    (
+        0.0,
+        &[
+            0xC0, b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'0', 0xBD, 0xBB,
+        ],
+    ),
+    (
+        1.5,
+        &[
+            b'Q', b'W', b'E', b'R', b'T', b'Y', b'U', b'I', b'O', b'P', 0xDB, 0xDD, 0xDC,
+        ],
+    ),
+    (
+        1.75,
+        &[
+            b'A', b'S', b'D', b'F', b'G', b'H', b'J', b'K', b'L', 0xBA, 0xDE,
+        ],
+    ),
+    (
+        1.25,
+        &[
+            0xE2, b'Z', b'X', b'C', b'V', b'B', b'N', b'M', 0xBC, 0xBE, 0xBF,
+        ],
+    ),
+];
+
+const VK_CAPITAL: u8 = 0x14;
+const VK_SPACE: u8 = 0x20;
+
+pub fn key_name(vk: u8) -> String {
+    let name = match vk {
+        b'A'..=b'Z' | b'0'..=b'9' => return (vk as char).to_string(),
+        VK_CAPITAL => "Caps",
+        VK_SPACE => "Space",
+        0xC0 => "`",
+        0xBD => "-",
+        0xBB => "=",
+        0xDB => "[",
+        0xDD => "]",
+        0xDC => "\\",
+        0xBA => ";",
+        0xDE => "'",
+        0xBC => ",",
+        0xBE => ".",
+        0xBF => "/",
+        0xE2 => "ISO",
+        _ => return format!("0x{:02X}", vk),
+    };
+
+    name.to_owned()
+}
+
+// Touch typing fingers, 0-3 for the left pinky to index, 4 for the thumbs and 5-8 for the right
+// index to pinky. Which finger presses a key only depends on where it is, whatever the layout.
+fn finger(vk: u8) -> Option<u8> {
+    if VK_SPACE == vk {
+        return Some(4);
+    }
+
+    let (row, col) = ROWS
+        .iter()
+        .enumerate()
+        .filter_map(|(row, &(_, keys))| keys.iter().position(|&k| k == vk).map(|col| (row, col)))
+        .next()?;
+
+    // The number row sits half a key left of the letters, so 1 goes with the pinky and 6 with
+    // the right index finger
+    if 0 == row {
+        return Some(match col {
+            0 | 1 => 0,
+            2 => 1,
+            3 => 2,
+            4 | 5 => 3,
+            6 | 7 => 5,
+            8 => 6,
+            9 => 7,
+            _ => 8,
+        });
+    }
+
+    // The ISO key sits left of Z, shifting the bottom row's columns by one
+    let col = if 3 == row { cmp::max(col, 1) - 1 } else { col };
+
+    Some(match col {
+        0 => 0,
+        1 => 1,
+        2 => 2,
+        3 | 4 => 3,
+        5 | 6 => 5,
+        7 => 6,
+        8 => 7,
+        _ => 8,
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+struct Hold {
+    count: u64,
+    total_ms: u64,
+    max_ms: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+struct LayoutStats {
+    presses: u64,
+    bigrams: u64,
+    same_finger_bigrams: u64,
+}
+
+pub struct TypingStats {
+    keys: HashMap<(u8, StatsLayer), u64>,
+    bigrams: HashMap<(u8, u8), u64>,
+    layouts: HashMap<StatsLayout, LayoutStats>,
+    holds: HashMap<StatsLayer, Hold>,
+
+    // Not saved
+    keys_down: HashSet<u8>,
+    // Last base layer key press, for bigrams
+    last_press: Option<(u8, u32)>,
+    held_since: HashMap<StatsLayer, u32>,
+    unsaved: u32,
+}
+
+// JSON string contents
+fn json_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains(',') || text.contains('"') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+impl TypingStats {
+    pub fn new() -> TypingStats {
+        TypingStats {
+            keys: HashMap::new(),
+            bigrams: HashMap::new(),
+            layouts: HashMap::new(),
+            holds: HashMap::new(),
+            keys_down: HashSet::new(),
+            last_press: None,
+            held_since: HashMap::new(),
+            unsaved: 0,
+        }
+    }
+
+    // `vk` is the physical key, and `layer` the one it was pressed on
+    pub fn key(
+        &mut self,
+        cfg: &TypingStatsConfig,
+        vk: u8,
+        key_pressed: bool,
+        layer: StatsLayer,
+        layout: StatsLayout,
+        time: u32,
+    ) {
+        if !cfg.enabled {
+            return;
+        }
+
+        if !key_pressed {
+            self.keys_down.remove(&vk);
+            return;
+        }
+
+        // Auto-repeat
+        if !self.keys_down.insert(vk) {
+            return;
+        }
+
+        *self.keys.entry((vk, layer)).or_insert(0) += 1;
+        self.unsaved += 1;
+
+        if cfg.privacy_mode {
+            return;
+        }
+
+        let last_press = self.last_press.take();
+        if StatsLayer::Base != layer {
+            return;
+        }
+        self.last_press = Some((vk, time));
+
+        let layout_stats = self
+            .layouts
+            .entry(layout)
+            .or_insert_with(LayoutStats::default);
+        layout_stats.presses += 1;
+
+        let prev = match last_press {
+            Some((prev, at)) if time.wrapping_sub(at) <= cfg.bigram_gap_ms => prev,
+            _ => return,
+        };
+
+        *self.bigrams.entry((prev, vk)).or_insert(0) += 1;
+        layout_stats.bigrams += 1;
+
+        // Pressing the same key twice doesn't count
+        let same_finger = prev != vk && finger(prev).is_some() && finger(prev) == finger(vk);
+        if same_finger {
+            layout_stats.same_finger_bigrams += 1;
+        }
+    }
+
+    // Presses and releases of the key holding `layer` on
+    pub fn layer_key(
+        &mut self,
+        cfg: &TypingStatsConfig,
+        layer: StatsLayer,
+        key_pressed: bool,
+        time: u32,
+    ) {
+        if !cfg.enabled {
+            return;
+        }
+
+        if key_pressed {
+            self.held_since.entry(layer).or_insert(time);
+        } else if let Some(since) = self.held_since.remove(&layer) {
+            let ms = time.wrapping_sub(since) as u64;
+            let hold = self.holds.entry(layer).or_insert_with(Hold::default);
+            hold.count += 1;
+            hold.total_ms += ms;
+            hold.max_ms = cmp::max(hold.max_ms, ms);
+        }
+    }
+
+    pub fn needs_saving(&self, cfg: &TypingStatsConfig) -> bool {
+        self.unsaved >= cfg.save_every
+    }
+
+    // One count per line, sorted so the file diffs nicely
+    pub fn save(&mut self) -> String {
+        self.unsaved = 0;
+
+        let mut lines: Vec<String> = Vec::new();
+        for (&(vk, layer), &count) in self.keys.iter() {
+            lines.push(format!("key {} {} {}", vk, name_of(LAYERS, layer), count));
+        }
+        for (&(first, second), &count) in self.bigrams.iter() {
+            lines.push(format!("bigram {} {} {}", first, second, count));
+        }
+        for (&layout, stats) in self.layouts.iter() {
+            lines.push(format!(
+                "layout {} {} {} {}",
+                name_of(LAYOUTS, layout),
+                stats.presses,
+                stats.bigrams,
+                stats.same_finger_bigrams
+            ));
+        }
+        for (&layer, hold) in self.holds.iter() {
+            lines.push(format!(
+                "hold {} {} {} {}",
+                name_of(LAYERS, layer),
+                hold.count,
+                hold.total_ms,
+                hold.max_ms
+            ));
+        }
+
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    // Adds what `save` returned to the counts; malformed lines are skipped
+    pub fn load(&mut self, data: &str) {
+        for line in data.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let num = |idx: usize| fields.get(idx).and_then(|f| f.parse::<u64>().ok());
+
+            match (fields[0], fields.len()) {
+                ("key", 4) => {
+                    let vk = num(1).filter(|&vk| vk <= 0xFF);
+                    if let (Some(vk), Some(layer), Some(count)) =
+                        (vk, parse_name(LAYERS, fields[2]), num(3))
+                    {
+                        *self.keys.entry((vk as u8, layer)).or_insert(0) += count;
+                    }
+                }
+                ("bigram", 4) => {
+                    let first = num(1).filter(|&vk| vk <= 0xFF);
+                    let second = num(2).filter(|&vk| vk <= 0xFF);
+                    if let (Some(first), Some(second), Some(count)) = (first, second, num(3)) {
+                        *self.bigrams.entry((first as u8, second as u8)).or_insert(0) += count;
+                    }
+                }
+                ("layout", 5) => {
+                    if let (Some(layout), Some(presses), Some(bigrams), Some(same_finger)) =
+                        (parse_name(LAYOUTS, fields[1]), num(2), num(3), num(4))
+                    {
+                        let stats = self
+                            .layouts
+                            .entry(layout)
+                            .or_insert_with(LayoutStats::default);
+                        stats.presses += presses;
+                        stats.bigrams += bigrams;
+                        stats.same_finger_bigrams += same_finger;
+                    }
+                }
+                ("hold", 5) => {
+                    if let (Some(layer), Some(count), Some(total_ms), Some(max_ms)) =
+                        (parse_name(LAYERS, fields[1]), num(2), num(3), num(4))
+                    {
+                        let hold = self.holds.entry(layer).or_insert_with(Hold::default);
+                        hold.count += count;
+                        hold.total_ms += total_ms;
+                        hold.max_ms = cmp::max(hold.max_ms, max_ms);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Most used first
+    fn sorted<K: Copy + Ord, V: Copy>(map: &HashMap<K, V>, count: fn(&V) -> u64) -> Vec<(K, V)> {
+        let mut items: Vec<(K, V)> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        items.sort_by(|a, b| count(&b.1).cmp(&count(&a.1)).then(a.0.cmp(&b.0)));
+        items
+    }
+
+    fn sorted_keys(&self) -> Vec<((u8, StatsLayer), u64)> {
+        let mut items: Vec<((u8, StatsLayer), u64)> =
+            self.keys.iter().map(|(&k, &n)| (k, n)).collect();
+        items.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then((a.0).0.cmp(&(b.0).0))
+                .then(name_of(LAYERS, (a.0).1).cmp(name_of(LAYERS, (b.0).1)))
+        });
+        items
+    }
+
+    pub fn to_json(&self) -> String {
+        let keys: Vec<String> = self
+            .sorted_keys()
+            .iter()
+            .map(|&((vk, layer), count)| {
+                format!(
+                    "    {{\"key\": \"{}\", \"vk\": {}, \"layer\": \"{}\", \"count\": {}}}",
+                    json_escape(&key_name(vk)),
+                    vk,
+                    name_of(LAYERS, layer),
+                    count
+                )
+            })
+            .collect();
+
+        let bigrams: Vec<String> = Self::sorted(&self.bigrams, |&n| n)
+            .iter()
+            .map(|&((first, second), count)| {
+                format!(
+                    "    {{\"first\": \"{}\", \"second\": \"{}\", \"count\": {}}}",
+                    json_escape(&key_name(first)),
+                    json_escape(&key_name(second)),
+                    count
+                )
+            })
+            .collect();
+
+        let layouts: Vec<String> = LAYOUTS
+            .iter()
+            .filter_map(|&(layout, name)| self.layouts.get(&layout).map(|s| (name, s)))
+            .map(|(name, stats)| {
+                format!(
+                    "    {{\"layout\": \"{}\", \"presses\": {}, \"bigrams\": {}, \
+                     \"same_finger_bigrams\": {}}}",
+                    name, stats.presses, stats.bigrams, stats.same_finger_bigrams
+                )
+            })
+            .collect();
+
+        let holds: Vec<String> = LAYERS
+            .iter()
+            .filter_map(|&(layer, name)| self.holds.get(&layer).map(|h| (name, h)))
+            .map(|(name, hold)| {
+                format!(
+                    "    {{\"layer\": \"{}\", \"count\": {}, \"total_ms\": {}, \"max_ms\": {}}}",
+                    name, hold.count, hold.total_ms, hold.max_ms
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\n  \"keys\": [\n{}\n  ],\n  \"bigrams\": [\n{}\n  ],\n  \
+             \"layouts\": [\n{}\n  ],\n  \"layer_holds\": [\n{}\n  ]\n}}\n",
+            keys.join(",\n"),
+            bigrams.join(",\n"),
+            layouts.join(",\n"),
+            holds.join(",\n")
+        )
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("metric,key,next_key,layer,count,total_ms,max_ms\n");
+
+        for &((vk, layer), count) in self.sorted_keys().iter() {
+            csv += &format!(
+                "key,{},,{},{},,\n",
+                csv_field(&key_name(vk)),
+                name_of(LAYERS, layer),
+                count
+            );
+        }
+        for &((first, second), count) in Self::sorted(&self.bigrams, |&n| n).iter() {
+            csv += &format!(
+                "bigram,{},{},,{},,\n",
+                csv_field(&key_name(first)),
+                csv_field(&key_name(second)),
+                count
+            );
+        }
+        for &(layout, name) in LAYOUTS.iter() {
+            if let Some(stats) = self.layouts.get(&layout) {
+                csv += &format!("presses,,,{},{},,\n", name, stats.presses);
+                csv += &format!(
+                    "same_finger_bigrams,,,{},{},,\n",
+                    name, stats.same_finger_bigrams
+                );
+            }
+        }
+        for &(layer, name) in LAYERS.iter() {
+            if let Some(hold) = self.holds.get(&layer) {
+                csv += &format!(
+                    "layer_hold,,,{},{},{},{}\n",
+                    name, hold.count, hold.total_ms, hold.max_ms
+                );
+            }
+        }
+
+        csv
+    }
+
+    // The main block of the keyboard, each key shaded by how often it's pressed on any layer
+    pub fn heatmap_svg(&self) -> String {
+        const KEY: f32 = 48.0;
+        const GAP: f32 = 4.0;
+
+        let mut totals: HashMap<u8, u64> = HashMap::new();
+        for (&(vk, _), &count) in self.keys.iter() {
+            *totals.entry(vk).or_insert(0) += count;
+        }
+        let max = cmp::max(totals.values().cloned().max().unwrap_or(0), 1);
+
+        let mut keys: Vec<(f32, f32, f32, u8)> = Vec::new();
+        for (row, &(offset, vks)) in ROWS.iter().enumerate() {
+            for (col, &vk) in vks.iter().enumerate() {
+                keys.push((offset + col as f32, row as f32, 1.0, vk));
+            }
+        }
+        keys.push((0.0, 2.0, 1.75, VK_CAPITAL));
+        keys.push((4.0, 4.0, 6.0, VK_SPACE));
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"sans-serif\" font-size=\"12\">\n",
+            15.0 * KEY,
+            5.0 * KEY
+        );
+
+        for &(x, y, width, vk) in keys.iter() {
+            let count = totals.get(&vk).cloned().unwrap_or(0);
+            let heat = count as f32 / max as f32;
+            let shade = (255.0 - heat * 200.0) as u8;
+
+            svg += &format!(
+                "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"4\" \
+                 fill=\"rgb(255,{},{})\" stroke=\"#888\"><title>{}</title></rect>\n",
+                x * KEY,
+                y * KEY,
+                width * KEY - GAP,
+                KEY - GAP,
+                shade,
+                shade,
+                count
+            );
+            svg += &format!(
+                "  <text x=\"{}\" y=\"{}\">{}</text>\n",
+                x * KEY + 6.0,
+                y * KEY + 18.0,
+                key_name(vk)
+            );
+        }
+
+        svg + "</svg>\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: TypingStatsConfig = TypingStatsConfig {
+        enabled: true,
+        ..TYPING_STATS_CONFIG
+    };
+
+    // Taps each key 100 ms apart, starting at `time`
+    fn type_keys(stats: &mut TypingStats, cfg: &TypingStatsConfig, keys: &str, time: u32) {
+        for (i, vk) in keys.bytes().enumerate() {
+            let at = time + 100 * i as u32;
+            stats.key(cfg, vk, true, StatsLayer::Base, StatsLayout::Qwerty, at);
+            stats.key(
+                cfg,
+                vk,
+                false,
+                StatsLayer::Base,
+                StatsLayout::Qwerty,
+                at + 50,
+            );
+        }
+    }
+
+    #[test]
+    fn fingers() {
+        let fingers = |keys: &[u8]| keys.iter().map(|&vk| finger(vk)).collect::<Vec<_>>();
+        let all = |f: u8, n: usize| vec![Some(f); n];
+
+        assert_eq!(fingers(&[0xC0, b'1', b'Q', b'A', 0xE2, b'Z']), all(0, 6));
+        assert_eq!(fingers(b"2WSX"), all(1, 4));
+        assert_eq!(fingers(b"3EDC"), all(2, 4));
+        assert_eq!(fingers(b"45RTFGVB"), all(3, 8));
+        assert_eq!(fingers(&[VK_SPACE]), all(4, 1));
+        assert_eq!(fingers(b"67YUHJNM"), all(5, 8));
+        assert_eq!(fingers(&[b'8', b'I', b'K', 0xBC]), all(6, 4));
+        assert_eq!(fingers(&[b'9', b'O', b'L', 0xBE]), all(7, 4));
+        assert_eq!(
+            fingers(&[b'0', 0xBD, 0xBB, b'P', 0xDC, 0xBA, 0xDE, 0xBF]),
+            all(8, 8)
+        );
+        assert_eq!(finger(VK_CAPITAL), None);
+    }
+
+    #[test]
+    fn counts_presses_and_bigrams() {
+        let mut stats = TypingStats::new();
+        // D then E is the same finger, and the gap after the second D breaks the bigram
+        type_keys(&mut stats, &CFG, "DED", 0);
+        type_keys(&mut stats, &CFG, "A", 5000);
+
+        assert_eq!(stats.keys[&(b'D', StatsLayer::Base)], 2);
+        assert_eq!(stats.bigrams[&(b'D', b'E')], 1);
+        assert_eq!(stats.bigrams.get(&(b'D', b'A')), None);
+        assert_eq!(
+            stats.layouts[&StatsLayout::Qwerty],
+            LayoutStats {
+                presses: 4,
+                bigrams: 2,
+                same_finger_bigrams: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn auto_repeat_and_privacy_mode() {
+        let cfg = TypingStatsConfig {
+            privacy_mode: true,
+            ..CFG
+        };
+        let mut stats = TypingStats::new();
+        let (layer, layout) = (StatsLayer::Caps, StatsLayout::Colemak);
+        for &time in &[0, 500, 530] {
+            stats.key(&cfg, b'K', true, layer, layout, time);
+        }
+        stats.key(&cfg, b'K', false, layer, layout, 600);
+        type_keys(&mut stats, &cfg, "KK", 1000);
+
+        assert_eq!(stats.keys[&(b'K', StatsLayer::Caps)], 1);
+        assert_eq!(stats.keys[&(b'K', StatsLayer::Base)], 2);
+        assert!(stats.bigrams.is_empty());
+        assert!(stats.layouts.is_empty());
+    }
+
+    #[test]
+    fn layer_holds() {
+        let mut stats = TypingStats::new();
+        stats.layer_key(&CFG, StatsLayer::Caps, true, 1000);
+        stats.layer_key(&CFG, StatsLayer::Caps, true, 1200);
+        stats.layer_key(&CFG, StatsLayer::Caps, false, 1300);
+        stats.layer_key(&CFG, StatsLayer::Caps, true, 2000);
+        stats.layer_key(&CFG, StatsLayer::Caps, false, 2100);
+        // A release without a press
+        stats.layer_key(&CFG, StatsLayer::Pipe, false, 2200);
+
+        assert_eq!(
+            stats.holds[&StatsLayer::Caps],
+            Hold {
+                count: 2,
+                total_ms: 400,
+                max_ms: 300,
+            }
+        );
+        assert!(!stats.holds.contains_key(&StatsLayer::Pipe));
+    }
+
+    #[test]
+    fn saves_every_so_often() {
+        let cfg = TypingStatsConfig {
+            save_every: 3,
+            ..CFG
+        };
+        let mut stats = TypingStats::new();
+        type_keys(&mut stats, &cfg, "AB", 0);
+        assert!(!stats.needs_saving(&cfg));
+        type_keys(&mut stats, &cfg, "C", 1000);
+        assert!(stats.needs_saving(&cfg));
+        stats.save();
+        assert!(!stats.needs_saving(&cfg));
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let mut stats = TypingStats::new();
+        type_keys(&mut stats, &CFG, "HELLO", 0);
+        stats.key(
+            &CFG,
+            0xBA,
+            true,
+            StatsLayer::Pipe,
+            StatsLayout::Colemak,
+            900,
+        );
+        stats.layer_key(&CFG, StatsLayer::Pipe, true, 800);
+        stats.layer_key(&CFG, StatsLayer::Pipe, false, 1000);
+        let saved = stats.save();
+
+        let mut loaded = TypingStats::new();
+        loaded.load(&saved);
+        assert_eq!(loaded.save(), saved);
+        assert_eq!(loaded.keys, stats.keys);
+        assert_eq!(loaded.bigrams, stats.bigrams);
+        assert_eq!(loaded.layouts, stats.layouts);
+        assert_eq!(loaded.holds, stats.holds);
+    }
+
+    #[test]
+    fn load_adds_up() {
+        let mut stats = TypingStats::new();
+        stats.load("key 65 base 3\nhold caps 2 300 200\nlayout qwerty 10 8 1\n");
+        stats.load("key 65 base 4\nhold caps 1 500 500\nlayout qwerty 5 4 2\n");
+        // Malformed lines are skipped
+        stats.load("key 300 base 1\nkey 65 nowhere 1\nbigram 65\n\nhold caps x 1 1\n");
+
+        assert_eq!(stats.keys.len(), 1);
+        assert_eq!(stats.keys[&(b'A', StatsLayer::Base)], 7);
+        assert_eq!(
+            stats.holds[&StatsLayer::Caps],
+            Hold {
+                count: 3,
+                total_ms: 800,
+                max_ms: 500,
+            }
+        );
+        assert_eq!(
+            stats.layouts[&StatsLayout::Qwerty],
+            LayoutStats {
+                presses: 15,
+                bigrams: 12,
+                same_finger_bigrams: 3,
+            }
+        );
+    }
+}
@@ -0,0 +1,423 @@
+// Vim-style navigation mode, toggled with Caps+G.
+//
+// Keys are taken as the characters they type, so motions follow Colemak and Shift. Everything
+// turns into standard editing keys: motions into arrows, Home/End and their Ctrl variants, visual
+// mode into the same with Shift held, and operators into selecting the motion, then cut or copy.
+
+use std::cmp;
+
+pub const ESCAPE: char = '\u{1b}';
+
+const VK_BACK: u8 = 0x08;
+const VK_RETURN: u8 = 0x0D;
+const VK_PRIOR: u8 = 0x21;
+const VK_NEXT: u8 = 0x22;
+const VK_END: u8 = 0x23;
+const VK_HOME: u8 = 0x24;
+const VK_LEFT: u8 = 0x25;
+const VK_UP: u8 = 0x26;
+const VK_RIGHT: u8 = 0x27;
+const VK_DOWN: u8 = 0x28;
+const VK_DELETE: u8 = 0x2E;
+
+// Counts larger than this are clamped, so a stray run of digits can't type for minutes. This also
+// goes for an operator's count times its motion's, as every stroke is sent from within the hook.
+const MAX_COUNT: u32 = 999;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VimMode {
+    // Keys type as usual
+    Off,
+    Normal,
+    // Motions extend the selection
+    Visual,
+}
+
+// A key press, with exactly these modifiers held
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NavStroke {
+    pub vk: u8,
+    pub shift: bool,
+    pub ctrl: bool,
+}
+
+fn stroke(vk: u8) -> NavStroke {
+    NavStroke {
+        vk,
+        shift: false,
+        ctrl: false,
+    }
+}
+
+fn ctrl_stroke(vk: u8) -> NavStroke {
+    NavStroke {
+        ctrl: true,
+        ..stroke(vk)
+    }
+}
+
+fn shifted(stroke: NavStroke) -> NavStroke {
+    NavStroke {
+        shift: true,
+        ..stroke
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operator {
+    Delete,
+    Yank,
+    Change,
+}
+
+// The stroke a motion key moves with, and whether a count repeats it
+fn motion(c: char, ctrl: bool) -> Option<(NavStroke, bool)> {
+    let res = match (c, ctrl) {
+        ('h', false) => (stroke(VK_LEFT), true),
+        ('j', false) => (stroke(VK_DOWN), true),
+        ('k', false) => (stroke(VK_UP), true),
+        ('l', false) => (stroke(VK_RIGHT), true),
+        ('w', false) | ('W', false) | ('e', false) | ('E', false) => (ctrl_stroke(VK_RIGHT), true),
+        ('b', false) | ('B', false) => (ctrl_stroke(VK_LEFT), true),
+        ('{', false) => (ctrl_stroke(VK_UP), true),
+        ('}', false) => (ctrl_stroke(VK_DOWN), true),
+        ('0', false) | ('^', false) => (stroke(VK_HOME), false),
+        ('$', false) => (stroke(VK_END), false),
+        ('G', false) => (ctrl_stroke(VK_END), false),
+        // Whole and half pages alike
+        ('f', true) | ('d', true) => (stroke(VK_NEXT), true),
+        ('b', true) | ('u', true) => (stroke(VK_PRIOR), true),
+        _ => return None,
+    };
+
+    Some(res)
+}
+
+fn repeat(strokes: &[NavStroke], count: u32) -> Vec<NavStroke> {
+    let mut res = Vec::new();
+    for _ in 0..count {
+        res.extend_from_slice(strokes);
+    }
+    res
+}
+
+pub struct VimNav {
+    mode: VimMode,
+    count: Option<u32>,
+    // Waiting for a motion, with the count typed before it
+    operator: Option<(Operator, u32)>,
+    // The first g of gg was typed
+    g_pending: bool,
+}
+
+impl VimNav {
+    pub fn new() -> VimNav {
+        VimNav {
+            mode: VimMode::Off,
+            count: None,
+            operator: None,
+            g_pending: false,
+        }
+    }
+
+    pub fn mode(&self) -> VimMode {
+        self.mode
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self.mode {
+            VimMode::Off => "Navigation mode off",
+            VimMode::Normal => "Navigation mode",
+            VimMode::Visual => "Visual mode",
+        }
+    }
+
+    pub fn set_on(&mut self, on: bool) {
+        self.mode = if on { VimMode::Normal } else { VimMode::Off };
+        self.clear_pending();
+    }
+
+    fn clear_pending(&mut self) {
+        self.count = None;
+        self.operator = None;
+        self.g_pending = false;
+    }
+
+    // Cuts or copies the selection made for an operator
+    fn apply(&mut self, op: Operator) -> Vec<NavStroke> {
+        match op {
+            Operator::Delete => vec![ctrl_stroke(b'X')],
+            // Copying leaves the caret where the selection started, as in vim
+            Operator::Yank => vec![ctrl_stroke(b'C'), stroke(VK_LEFT)],
+            Operator::Change => {
+                self.set_on(false);
+                vec![ctrl_stroke(b'X')]
+            }
+        }
+    }
+
+    // `c` is the character the key types, or ESCAPE, and `ctrl` whether Ctrl is held.
+    // Returns the strokes to send, or None if the key has no binding.
+    pub fn key(&mut self, c: char, ctrl: bool) -> Option<Vec<NavStroke>> {
+        if VimMode::Off == self.mode {
+            return None;
+        }
+
+        if ESCAPE == c {
+            self.clear_pending();
+            self.mode = VimMode::Normal;
+            return Some(Vec::new());
+        }
+
+        if !ctrl {
+            let digit = match c.to_digit(10) {
+                Some(0) if self.count.is_none() => None,
+                digit => digit,
+            };
+            if let Some(digit) = digit {
+                let count = self.count.unwrap_or(0) * 10 + digit;
+                self.count = Some(cmp::min(count, MAX_COUNT));
+                return Some(Vec::new());
+            }
+
+            if 'g' == c && !self.g_pending {
+                self.g_pending = true;
+                return Some(Vec::new());
+            }
+        }
+
+        let count = self.count.take().unwrap_or(1);
+        let gg = self.g_pending && 'g' == c && !ctrl;
+        self.g_pending = false;
+
+        let motion = if gg {
+            Some((ctrl_stroke(VK_HOME), false))
+        } else {
+            motion(c, ctrl)
+        };
+
+        if let Some((motion, repeats)) = motion {
+            let operator = self.operator.take();
+            let op_count = operator.map_or(1, |(_, op_count)| op_count);
+            let times = if repeats {
+                cmp::min(op_count * count, MAX_COUNT)
+            } else {
+                1
+            };
+
+            return Some(match (self.mode, operator) {
+                (VimMode::Normal, Some((op, _))) => {
+                    let mut strokes = repeat(&[shifted(motion)], times);
+                    strokes.extend(self.apply(op));
+                    strokes
+                }
+                (VimMode::Visual, _) => repeat(&[shifted(motion)], times),
+                _ => repeat(&[motion], times),
+            });
+        }
+
+        if let Some((op, op_count)) = self.operator.take() {
+            let doubled = match (op, c) {
+                (Operator::Delete, 'd') | (Operator::Yank, 'y') | (Operator::Change, 'c') => !ctrl,
+                _ => false,
+            };
+            if !doubled {
+                // Anything else cancels the operator
+                return Some(Vec::new());
+            }
+
+            // Whole lines; changing keeps the last line break
+            let lines = cmp::min(op_count * count, MAX_COUNT);
+            let mut strokes = vec![stroke(VK_HOME)];
+            if Operator::Change == op {
+                strokes.extend(repeat(&[shifted(stroke(VK_DOWN))], lines - 1));
+                strokes.push(shifted(stroke(VK_END)));
+            } else {
+                strokes.extend(repeat(&[shifted(stroke(VK_DOWN))], lines));
+            }
+            strokes.extend(self.apply(op));
+            return Some(strokes);
+        }
+
+        if VimMode::Visual == self.mode {
+            let strokes = match (c, ctrl) {
+                ('d', false) | ('x', false) => vec![ctrl_stroke(b'X')],
+                ('y', false) => vec![ctrl_stroke(b'C'), stroke(VK_LEFT)],
+                ('c', false) | ('s', false) => {
+                    self.set_on(false);
+                    return Some(vec![ctrl_stroke(b'X')]);
+                }
+                ('p', false) => vec![ctrl_stroke(b'V')],
+                ('v', false) | ('V', false) => Vec::new(),
+                _ => return None,
+            };
+
+            self.mode = VimMode::Normal;
+            return Some(strokes);
+        }
+
+        let strokes = match (c, ctrl) {
+            ('d', false) => {
+                self.operator = Some((Operator::Delete, count));
+                Vec::new()
+            }
+            ('y', false) => {
+                self.operator = Some((Operator::Yank, count));
+                Vec::new()
+            }
+            ('c', false) => {
+                self.operator = Some((Operator::Change, count));
+                Vec::new()
+            }
+            ('x', false) => repeat(&[stroke(VK_DELETE)], count),
+            ('X', false) => repeat(&[stroke(VK_BACK)], count),
+            ('p', false) | ('P', false) => repeat(&[ctrl_stroke(b'V')], count),
+            ('u', false) => repeat(&[ctrl_stroke(b'Z')], count),
+            ('r', true) => repeat(&[ctrl_stroke(b'Y')], count),
+            ('D', false) => vec![shifted(stroke(VK_END)), ctrl_stroke(b'X')],
+            ('Y', false) => {
+                let mut strokes = vec![stroke(VK_HOME)];
+                strokes.extend(repeat(&[shifted(stroke(VK_DOWN))], count));
+                strokes.extend(self.apply(Operator::Yank));
+                strokes
+            }
+            ('v', false) => {
+                self.mode = VimMode::Visual;
+                Vec::new()
+            }
+            ('V', false) => {
+                self.mode = VimMode::Visual;
+                vec![stroke(VK_HOME), shifted(stroke(VK_DOWN))]
+            }
+            // Back to typing
+            ('C', false) => {
+                self.set_on(false);
+                vec![shifted(stroke(VK_END)), ctrl_stroke(b'X')]
+            }
+            ('i', false) => {
+                self.set_on(false);
+                Vec::new()
+            }
+            ('a', false) => {
+                self.set_on(false);
+                vec![stroke(VK_RIGHT)]
+            }
+            ('I', false) => {
+                self.set_on(false);
+                vec![stroke(VK_HOME)]
+            }
+            ('A', false) => {
+                self.set_on(false);
+                vec![stroke(VK_END)]
+            }
+            ('o', false) => {
+                self.set_on(false);
+                vec![stroke(VK_END), stroke(VK_RETURN)]
+            }
+            ('O', false) => {
+                self.set_on(false);
+                vec![stroke(VK_HOME), stroke(VK_RETURN), stroke(VK_UP)]
+            }
+            _ => return None,
+        };
+
+        Some(strokes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(nav: &mut VimNav, text: &str) -> Vec<NavStroke> {
+        let mut strokes = Vec::new();
+        for c in text.chars() {
+            strokes.extend(nav.key(c, false).unwrap_or_default());
+        }
+        strokes
+    }
+
+    fn normal_mode() -> VimNav {
+        let mut nav = VimNav::new();
+        nav.set_on(true);
+        nav
+    }
+
+    #[test]
+    fn counted_motion() {
+        let mut nav = normal_mode();
+        assert_eq!(keys(&mut nav, "3j"), vec![stroke(VK_DOWN); 3]);
+        assert_eq!(keys(&mut nav, "5j"), vec![stroke(VK_DOWN); 5]);
+        // The count doesn't carry over
+        assert_eq!(keys(&mut nav, "k"), vec![stroke(VK_UP)]);
+    }
+
+    #[test]
+    fn operator_with_motion() {
+        let mut nav = normal_mode();
+        let word = shifted(ctrl_stroke(VK_RIGHT));
+        assert_eq!(keys(&mut nav, "dw"), vec![word, ctrl_stroke(b'X')]);
+        assert_eq!(keys(&mut nav, "d2w"), vec![word, word, ctrl_stroke(b'X')]);
+        assert_eq!(nav.mode(), VimMode::Normal);
+    }
+
+    #[test]
+    fn doubled_operator_takes_lines() {
+        let mut nav = normal_mode();
+        let home = stroke(VK_HOME);
+        let down = shifted(stroke(VK_DOWN));
+        assert_eq!(keys(&mut nav, "dd"), vec![home, down, ctrl_stroke(b'X')]);
+        assert_eq!(
+            keys(&mut nav, "2dd"),
+            vec![home, down, down, ctrl_stroke(b'X')]
+        );
+        assert_eq!(nav.mode(), VimMode::Normal);
+
+        // Keeps the line break, and goes back to typing
+        let end = shifted(stroke(VK_END));
+        assert_eq!(keys(&mut nav, "cc"), vec![home, end, ctrl_stroke(b'X')]);
+        assert_eq!(nav.mode(), VimMode::Off);
+    }
+
+    #[test]
+    fn visual_line_delete() {
+        let mut nav = normal_mode();
+        assert_eq!(
+            keys(&mut nav, "V"),
+            vec![stroke(VK_HOME), shifted(stroke(VK_DOWN))]
+        );
+        assert_eq!(nav.mode(), VimMode::Visual);
+        assert_eq!(keys(&mut nav, "j"), vec![shifted(stroke(VK_DOWN))]);
+        assert_eq!(keys(&mut nav, "d"), vec![ctrl_stroke(b'X')]);
+        assert_eq!(nav.mode(), VimMode::Normal);
+    }
+
+    #[test]
+    fn gg_goes_to_the_top() {
+        let mut nav = normal_mode();
+        assert_eq!(keys(&mut nav, "gg"), vec![ctrl_stroke(VK_HOME)]);
+        // Not repeated by a count
+        assert_eq!(keys(&mut nav, "3gg"), vec![ctrl_stroke(VK_HOME)]);
+        // A single g is dropped
+        assert_eq!(keys(&mut nav, "gj"), vec![stroke(VK_DOWN)]);
+    }
+
+    #[test]
+    fn operator_count_times_motion_count_is_clamped() {
+        let mut nav = normal_mode();
+
+        let strokes = keys(&mut nav, "999d999j");
+        assert_eq!(strokes.len(), MAX_COUNT as usize + 1);
+        assert_eq!(strokes[0], shifted(stroke(VK_DOWN)));
+        assert_eq!(strokes[strokes.len() - 1], ctrl_stroke(b'X'));
+    }
+
+    #[test]
+    fn operator_count_times_line_count_is_clamped() {
+        let mut nav = normal_mode();
+
+        // Home, the lines, then cut
+        let strokes = keys(&mut nav, "999y999y");
+        assert_eq!(strokes.len(), MAX_COUNT as usize + 3);
+    }
+}
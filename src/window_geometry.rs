@@ -0,0 +1,580 @@
+// Placement math for Caps+drag window move and resize: magnetic snapping, grid snapping,
+// edge tiling, and picking which edges a resize drags.
+//
+// Everything works on plain rectangles in physical screen pixels, with `right` and `bottom`
+// exclusive like in a winapi RECT, so it can be driven by synthetic monitor layouts.
+// Window rectangles are the visible frames, without the invisible resize borders.
+
+use std::cmp;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    pub fn new(left: i32, top: i32, right: i32, bottom: i32) -> Rect {
+        Rect {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> i32 {
+        self.bottom - self.top
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32) -> Rect {
+        Rect::new(
+            self.left + dx,
+            self.top + dy,
+            self.right + dx,
+            self.bottom + dy,
+        )
+    }
+
+    pub fn contains(&self, pt: (i32, i32)) -> bool {
+        pt.0 >= self.left && pt.0 < self.right && pt.1 >= self.top && pt.1 < self.bottom
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Monitor {
+    pub bounds: Rect,
+    // Bounds minus the taskbar and docked toolbars
+    pub work_area: Rect,
+    // 96 at 100% scaling
+    pub dpi: u32,
+}
+
+impl Monitor {
+    // Converts a size given at 100% scaling to this monitor's pixels
+    pub fn scale(&self, v: i32) -> i32 {
+        (v as i64 * self.dpi as i64 / 96) as i32
+    }
+}
+
+// Invisible borders around the visible frame of a window. Windows 10 puts the resize
+// handles there, and includes them in GetWindowRect.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct FrameMargins {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+pub fn frame_margins(window_rect: &Rect, visible_rect: &Rect) -> FrameMargins {
+    FrameMargins {
+        left: visible_rect.left - window_rect.left,
+        top: visible_rect.top - window_rect.top,
+        right: window_rect.right - visible_rect.right,
+        bottom: window_rect.bottom - visible_rect.bottom,
+    }
+}
+
+// The window rectangle to ask for, to get `visible_rect` on screen
+pub fn add_frame_margins(visible_rect: &Rect, margins: &FrameMargins) -> Rect {
+    Rect::new(
+        visible_rect.left - margins.left,
+        visible_rect.top - margins.top,
+        visible_rect.right + margins.right,
+        visible_rect.bottom + margins.bottom,
+    )
+}
+
+pub struct SnapConfig {
+    // Window edges closer than this many pixels to a monitor or window edge stick to it
+    pub snap_distance: i32,
+    // With no edge nearby, the top-left corner snaps to a grid of this many pixels; 0 disables it
+    pub grid_size: i32,
+    // Dropping a window with the cursor this close to a monitor edge tiles it to that half
+    pub tile_edge: i32,
+    // ... and this close to a corner along that edge tiles it to that quarter
+    pub tile_corner: i32,
+    // Resizing never makes windows smaller than this
+    pub min_size: (i32, i32),
+    // Moving keeps at least this much of the top of a window on screen, at 100% scaling,
+    // so that it can still be grabbed by the title bar
+    pub title_bar_height: i32,
+    pub min_title_visible: i32,
+}
+
+pub const SNAP_CONFIG: SnapConfig = SnapConfig {
+    snap_distance: 12,
+    grid_size: 0,
+    tile_edge: 2,
+    tile_corner: 64,
+    min_size: (120, 80),
+    title_bar_height: 32,
+    min_title_visible: 96,
+};
+
+pub fn monitor_at(monitors: &[Monitor], pt: (i32, i32)) -> Option<&Monitor> {
+    monitors.iter().find(|m| m.bounds.contains(pt))
+}
+
+fn ranges_overlap(a: (i32, i32), b: (i32, i32), slack: i32) -> bool {
+    a.0 < b.1 + slack && b.0 < a.1 + slack
+}
+
+// Edges which a window can stick to, split into vertical lines (x) and horizontal lines (y).
+// Only those alongside the window count, so that a window at the top of the screen
+// doesn't stick to the edge of another one at the bottom.
+fn snap_targets(
+    cfg: &SnapConfig,
+    rect: &Rect,
+    monitors: &[Monitor],
+    windows: &[Rect],
+) -> (Vec<i32>, Vec<i32>) {
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+
+    let areas = monitors.iter().map(|m| &m.work_area).chain(windows.iter());
+    for area in areas {
+        if ranges_overlap(
+            (rect.top, rect.bottom),
+            (area.top, area.bottom),
+            cfg.snap_distance,
+        ) {
+            xs.push(area.left);
+            xs.push(area.right);
+        }
+
+        if ranges_overlap(
+            (rect.left, rect.right),
+            (area.left, area.right),
+            cfg.snap_distance,
+        ) {
+            ys.push(area.top);
+            ys.push(area.bottom);
+        }
+    }
+
+    (xs, ys)
+}
+
+// Smallest adjustment which puts one of `edges` onto one of `targets`, if any is close enough
+fn snap_offset(edges: &[i32], targets: &[i32], max_distance: i32) -> Option<i32> {
+    let mut best: Option<i32> = None;
+
+    for &edge in edges {
+        for &target in targets {
+            let d = target - edge;
+            if d.abs() <= max_distance && best.map_or(true, |b| d.abs() < b.abs()) {
+                best = Some(d);
+            }
+        }
+    }
+
+    best
+}
+
+fn grid_offset(v: i32, grid_size: i32) -> i32 {
+    if grid_size > 0 {
+        let snapped = (v as f64 / grid_size as f64).round() as i32 * grid_size;
+        snapped - v
+    } else {
+        0
+    }
+}
+
+// Where a window goes while being dragged, keeping the grabbed point under the cursor.
+// `grab` is that point relative to the window size, so that it stays put even when
+// the window gets rescaled by crossing onto a monitor with a different DPI.
+pub fn drag_rect(cursor: (i32, i32), grab: (f32, f32), size: (i32, i32)) -> Rect {
+    let left = cursor.0 - (grab.0 * size.0 as f32).round() as i32;
+    let top = cursor.1 - (grab.1 * size.1 as f32).round() as i32;
+
+    Rect::new(left, top, left + size.0, top + size.1)
+}
+
+pub fn grab_point(rect: &Rect, cursor: (i32, i32)) -> (f32, f32) {
+    (
+        (cursor.0 - rect.left) as f32 / cmp::max(rect.width(), 1) as f32,
+        (cursor.1 - rect.top) as f32 / cmp::max(rect.height(), 1) as f32,
+    )
+}
+
+fn title_bar_reachable(cfg: &SnapConfig, rect: &Rect, monitor: &Monitor) -> bool {
+    let area = &monitor.work_area;
+    let visible = cmp::min(monitor.scale(cfg.min_title_visible), rect.width());
+    let overlap = cmp::min(rect.right, area.right) - cmp::max(rect.left, area.left);
+
+    overlap >= visible
+        && rect.top >= area.top
+        && rect.top + monitor.scale(cfg.title_bar_height) <= area.bottom
+}
+
+// Pulls the window back if its title bar would end up off every monitor,
+// keeping it on `fallback`, the monitor under the cursor
+pub fn clamp_title_bar(
+    cfg: &SnapConfig,
+    rect: &Rect,
+    monitors: &[Monitor],
+    fallback: &Monitor,
+) -> Rect {
+    if monitors.iter().any(|m| title_bar_reachable(cfg, rect, m)) {
+        return *rect;
+    }
+
+    let area = &fallback.work_area;
+    let visible = cmp::min(fallback.scale(cfg.min_title_visible), rect.width());
+    let title_bar_height = fallback.scale(cfg.title_bar_height);
+
+    let left = cmp::max(
+        area.left - rect.width() + visible,
+        cmp::min(rect.left, area.right - visible),
+    );
+    let top = cmp::max(area.top, cmp::min(rect.top, area.bottom - title_bar_height));
+
+    rect.offset(left - rect.left, top - rect.top)
+}
+
+// Where a window being dragged to `rect` should actually go
+pub fn snap_move(cfg: &SnapConfig, rect: &Rect, monitors: &[Monitor], windows: &[Rect]) -> Rect {
+    let (xs, ys) = snap_targets(cfg, rect, monitors, windows);
+
+    let dx = snap_offset(&[rect.left, rect.right], &xs, cfg.snap_distance)
+        .unwrap_or_else(|| grid_offset(rect.left, cfg.grid_size));
+    let dy = snap_offset(&[rect.top, rect.bottom], &ys, cfg.snap_distance)
+        .unwrap_or_else(|| grid_offset(rect.top, cfg.grid_size));
+
+    rect.offset(dx, dy)
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ResizeEdges {
+    pub left: bool,
+    pub right: bool,
+    pub top: bool,
+    pub bottom: bool,
+}
+
+// Picks the edges a resize drags from where the cursor grabbed the window:
+// the outer thirds along each axis pick the edge on that side, the middle third neither.
+// Grabbing the very middle of the window drags the bottom-right corner.
+pub fn nearest_edges(rect: &Rect, cursor: (i32, i32)) -> ResizeEdges {
+    let third_x = rect.width() / 3;
+    let third_y = rect.height() / 3;
+
+    let edges = ResizeEdges {
+        left: cursor.0 < rect.left + third_x,
+        right: cursor.0 >= rect.right - third_x,
+        top: cursor.1 < rect.top + third_y,
+        bottom: cursor.1 >= rect.bottom - third_y,
+    };
+
+    if edges == ResizeEdges::default() {
+        ResizeEdges {
+            right: true,
+            bottom: true,
+            ..edges
+        }
+    } else {
+        edges
+    }
+}
+
+// Moves the chosen edges of `start` by the cursor delta, keeping the opposite edges in place
+pub fn resize_rect(cfg: &SnapConfig, start: &Rect, edges: ResizeEdges, delta: (i32, i32)) -> Rect {
+    let mut rect = *start;
+
+    if edges.left {
+        rect.left = cmp::min(start.left + delta.0, start.right - cfg.min_size.0);
+    }
+    if edges.right {
+        rect.right = cmp::max(start.right + delta.0, start.left + cfg.min_size.0);
+    }
+    if edges.top {
+        rect.top = cmp::min(start.top + delta.1, start.bottom - cfg.min_size.1);
+    }
+    if edges.bottom {
+        rect.bottom = cmp::max(start.bottom + delta.1, start.top + cfg.min_size.1);
+    }
+
+    rect
+}
+
+// Sticks the edges being dragged to nearby monitor and window edges
+pub fn snap_resize(
+    cfg: &SnapConfig,
+    rect: &Rect,
+    edges: ResizeEdges,
+    monitors: &[Monitor],
+    windows: &[Rect],
+) -> Rect {
+    let (xs, ys) = snap_targets(cfg, rect, monitors, windows);
+    let snap =
+        |v: i32, targets: &[i32]| v + snap_offset(&[v], targets, cfg.snap_distance).unwrap_or(0);
+
+    let mut snapped = *rect;
+    if edges.left {
+        snapped.left = snap(rect.left, &xs);
+    }
+    if edges.right {
+        snapped.right = snap(rect.right, &xs);
+    }
+    if edges.top {
+        snapped.top = snap(rect.top, &ys);
+    }
+    if edges.bottom {
+        snapped.bottom = snap(rect.bottom, &ys);
+    }
+
+    // Snapping must not undo the minimum size
+    if snapped.width() < cfg.min_size.0 {
+        snapped.left = rect.left;
+        snapped.right = rect.right;
+    }
+    if snapped.height() < cfg.min_size.1 {
+        snapped.top = rect.top;
+        snapped.bottom = rect.bottom;
+    }
+
+    snapped
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tile {
+    LeftHalf,
+    RightHalf,
+    TopHalf,
+    BottomHalf,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    LeftThird,
+    CenterThird,
+    RightThird,
+}
+
+// The tile a window dropped with the cursor at `cursor` goes to, if the cursor is at a monitor edge
+pub fn tile_at(cfg: &SnapConfig, cursor: (i32, i32), monitor: &Monitor) -> Option<Tile> {
+    let b = &monitor.bounds;
+
+    let at_left = cursor.0 < b.left + cfg.tile_edge;
+    let at_right = cursor.0 >= b.right - cfg.tile_edge;
+    let at_top = cursor.1 < b.top + cfg.tile_edge;
+    let at_bottom = cursor.1 >= b.bottom - cfg.tile_edge;
+
+    let near_left = cursor.0 < b.left + cfg.tile_corner;
+    let near_right = cursor.0 >= b.right - cfg.tile_corner;
+    let near_top = cursor.1 < b.top + cfg.tile_corner;
+    let near_bottom = cursor.1 >= b.bottom - cfg.tile_corner;
+
+    let on_left = at_left || (near_left && (at_top || at_bottom));
+    let on_right = at_right || (near_right && (at_top || at_bottom));
+    let on_top = at_top || (near_top && (at_left || at_right));
+    let on_bottom = at_bottom || (near_bottom && (at_left || at_right));
+
+    match (on_left, on_right, on_top, on_bottom) {
+        (true, _, true, _) => Some(Tile::TopLeft),
+        (true, _, _, true) => Some(Tile::BottomLeft),
+        (_, true, true, _) => Some(Tile::TopRight),
+        (_, true, _, true) => Some(Tile::BottomRight),
+        (true, _, _, _) => Some(Tile::LeftHalf),
+        (_, true, _, _) => Some(Tile::RightHalf),
+        (_, _, true, _) => Some(Tile::TopHalf),
+        (_, _, _, true) => Some(Tile::BottomHalf),
+        _ => None,
+    }
+}
+
+pub fn tile_rect(tile: Tile, work_area: &Rect) -> Rect {
+    let a = work_area;
+    let mid_x = a.left + a.width() / 2;
+    let mid_y = a.top + a.height() / 2;
+    let third_x = a.left + a.width() / 3;
+    let two_thirds_x = a.left + a.width() * 2 / 3;
+
+    match tile {
+        Tile::LeftHalf => Rect::new(a.left, a.top, mid_x, a.bottom),
+        Tile::RightHalf => Rect::new(mid_x, a.top, a.right, a.bottom),
+        Tile::TopHalf => Rect::new(a.left, a.top, a.right, mid_y),
+        Tile::BottomHalf => Rect::new(a.left, mid_y, a.right, a.bottom),
+        Tile::TopLeft => Rect::new(a.left, a.top, mid_x, mid_y),
+        Tile::TopRight => Rect::new(mid_x, a.top, a.right, mid_y),
+        Tile::BottomLeft => Rect::new(a.left, mid_y, mid_x, a.bottom),
+        Tile::BottomRight => Rect::new(mid_x, mid_y, a.right, a.bottom),
+        Tile::LeftThird => Rect::new(a.left, a.top, third_x, a.bottom),
+        Tile::CenterThird => Rect::new(third_x, a.top, two_thirds_x, a.bottom),
+        Tile::RightThird => Rect::new(two_thirds_x, a.top, a.right, a.bottom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 1080p monitor with the taskbar at the bottom, and a 4K one at 150% to its right
+    fn monitors() -> Vec<Monitor> {
+        vec![
+            Monitor {
+                bounds: Rect::new(0, 0, 1920, 1080),
+                work_area: Rect::new(0, 0, 1920, 1040),
+                dpi: 96,
+            },
+            Monitor {
+                bounds: Rect::new(1920, 0, 5760, 2160),
+                work_area: Rect::new(1920, 0, 5760, 2160),
+                dpi: 144,
+            },
+        ]
+    }
+
+    #[test]
+    fn snap_move_to_monitor_edge() {
+        let rect = Rect::new(5, 300, 805, 900);
+        assert_eq!(
+            snap_move(&SNAP_CONFIG, &rect, &monitors(), &[]),
+            Rect::new(0, 300, 800, 900)
+        );
+
+        let rect = Rect::new(1100, 630, 1910, 1030);
+        assert_eq!(
+            snap_move(&SNAP_CONFIG, &rect, &monitors(), &[]),
+            Rect::new(1110, 640, 1920, 1040)
+        );
+    }
+
+    #[test]
+    fn snap_move_to_window_alongside() {
+        let windows = [Rect::new(1510, 100, 1900, 700)];
+        let rect = Rect::new(1000, 200, 1500, 600);
+        assert_eq!(
+            snap_move(&SNAP_CONFIG, &rect, &monitors(), &windows),
+            Rect::new(1010, 200, 1510, 600)
+        );
+
+        // Not alongside, so its edges don't count
+        let windows = [Rect::new(1510, 800, 1900, 1000)];
+        assert_eq!(snap_move(&SNAP_CONFIG, &rect, &monitors(), &windows), rect);
+    }
+
+    #[test]
+    fn snap_move_picks_the_closest_edge() {
+        let windows = [Rect::new(808, 100, 1200, 700)];
+        let rect = Rect::new(3, 200, 800, 600);
+        assert_eq!(
+            snap_move(&SNAP_CONFIG, &rect, &monitors(), &windows),
+            Rect::new(0, 200, 797, 600)
+        );
+    }
+
+    #[test]
+    fn snap_move_to_grid() {
+        let cfg = SnapConfig {
+            grid_size: 50,
+            ..SNAP_CONFIG
+        };
+        let rect = Rect::new(123, 270, 523, 570);
+        assert_eq!(
+            snap_move(&cfg, &rect, &monitors(), &[]),
+            Rect::new(100, 250, 500, 550)
+        );
+    }
+
+    #[test]
+    fn nearest_edges_by_thirds() {
+        let rect = Rect::new(0, 0, 300, 300);
+        let edges = |left, right, top, bottom| ResizeEdges {
+            left,
+            right,
+            top,
+            bottom,
+        };
+
+        assert_eq!(
+            nearest_edges(&rect, (10, 10)),
+            edges(true, false, true, false)
+        );
+        assert_eq!(
+            nearest_edges(&rect, (290, 150)),
+            edges(false, true, false, false)
+        );
+        assert_eq!(
+            nearest_edges(&rect, (150, 290)),
+            edges(false, false, false, true)
+        );
+        // The middle drags the bottom-right corner
+        assert_eq!(
+            nearest_edges(&rect, (150, 150)),
+            edges(false, true, false, true)
+        );
+    }
+
+    #[test]
+    fn snap_resize_only_dragged_edges() {
+        let edges = ResizeEdges {
+            right: true,
+            ..ResizeEdges::default()
+        };
+        let rect = Rect::new(5, 100, 1910, 700);
+        assert_eq!(
+            snap_resize(&SNAP_CONFIG, &rect, edges, &monitors(), &[]),
+            Rect::new(5, 100, 1920, 700)
+        );
+    }
+
+    #[test]
+    fn snap_resize_keeps_min_size() {
+        let edges = ResizeEdges {
+            right: true,
+            ..ResizeEdges::default()
+        };
+        let windows = [Rect::new(215, 100, 600, 500)];
+        let rect = Rect::new(100, 100, 225, 300);
+        assert_eq!(
+            snap_resize(&SNAP_CONFIG, &rect, edges, &monitors(), &windows),
+            rect
+        );
+    }
+
+    #[test]
+    fn tile_rects() {
+        let area = Rect::new(0, 0, 1920, 1040);
+        assert_eq!(tile_rect(Tile::LeftHalf, &area), Rect::new(0, 0, 960, 1040));
+        assert_eq!(
+            tile_rect(Tile::BottomRight, &area),
+            Rect::new(960, 520, 1920, 1040)
+        );
+        assert_eq!(
+            tile_rect(Tile::CenterThird, &area),
+            Rect::new(640, 0, 1280, 1040)
+        );
+
+        let area = Rect::new(1920, 0, 5760, 2160);
+        assert_eq!(
+            tile_rect(Tile::RightThird, &area),
+            Rect::new(4480, 0, 5760, 2160)
+        );
+        assert_eq!(
+            tile_rect(Tile::TopHalf, &area),
+            Rect::new(1920, 0, 5760, 1080)
+        );
+    }
+
+    #[test]
+    fn tile_at_edges_and_corners() {
+        let monitors = monitors();
+        let m = &monitors[0];
+        assert_eq!(tile_at(&SNAP_CONFIG, (0, 500), m), Some(Tile::LeftHalf));
+        assert_eq!(tile_at(&SNAP_CONFIG, (30, 0), m), Some(Tile::TopLeft));
+        assert_eq!(
+            tile_at(&SNAP_CONFIG, (1919, 1050), m),
+            Some(Tile::BottomRight)
+        );
+        assert_eq!(tile_at(&SNAP_CONFIG, (500, 500), m), None);
+    }
+}
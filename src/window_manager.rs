@@ -0,0 +1,306 @@
+// Keyboard-driven window placement: tiling, centering, nudging, monitor hopping and layout cycling.
+//
+// Commands only reach the desktop through `WindowManager`, so the placement logic
+// runs the same against a fake desktop as against the real one.
+
+use std::cmp;
+use window_geometry::{self, Monitor, Rect, Tile};
+
+pub trait WindowManager {
+    type Window: Copy;
+
+    fn foreground_window(&self) -> Option<Self::Window>;
+
+    fn window_rect(&self, window: Self::Window) -> Rect;
+    fn set_window_rect(&mut self, window: Self::Window, rect: &Rect);
+
+    fn is_maximized(&self, window: Self::Window) -> bool;
+    fn set_maximized(&mut self, window: Self::Window, maximized: bool);
+
+    fn monitors(&self) -> Vec<Monitor>;
+
+    // The monitor a window is mostly on
+    fn window_monitor(&self, window: Self::Window) -> Option<Monitor> {
+        monitor_for_rect(&self.monitors(), &self.window_rect(window)).cloned()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WindowCommand {
+    Tile(Tile),
+    ToggleMaximize,
+    Center,
+    Nudge(i32, i32),
+    NextMonitor,
+    CycleLayout,
+}
+
+// Pixels moved by a single nudge
+pub const NUDGE_STEP: i32 = 20;
+
+// Slots visited by CycleLayout, in order
+pub const LAYOUT: &[Tile] = &[
+    Tile::LeftHalf,
+    Tile::RightHalf,
+    Tile::LeftThird,
+    Tile::CenterThird,
+    Tile::RightThird,
+];
+
+fn overlap_area(a: &Rect, b: &Rect) -> i64 {
+    let w = cmp::min(a.right, b.right) - cmp::max(a.left, b.left);
+    let h = cmp::min(a.bottom, b.bottom) - cmp::max(a.top, b.top);
+
+    if w > 0 && h > 0 {
+        w as i64 * h as i64
+    } else {
+        0
+    }
+}
+
+pub fn monitor_for_rect<'a>(monitors: &'a [Monitor], rect: &Rect) -> Option<&'a Monitor> {
+    monitors
+        .iter()
+        .filter(|m| overlap_area(&m.bounds, rect) > 0)
+        .max_by_key(|m| overlap_area(&m.bounds, rect))
+}
+
+// The monitor after `current`, going left to right, then top to bottom, and wrapping around
+pub fn next_monitor(monitors: &[Monitor], current: &Monitor) -> Option<Monitor> {
+    let mut monitors = monitors.to_vec();
+    monitors.sort_by_key(|m| (m.bounds.left, m.bounds.top));
+
+    let idx = monitors.iter().position(|m| m.bounds == current.bounds)?;
+    if monitors.len() > 1 {
+        Some(monitors[(idx + 1) % monitors.len()])
+    } else {
+        None
+    }
+}
+
+// Keeps the window at the same relative spot on the new monitor, shrinking it if it doesn't fit
+pub fn move_to_monitor(rect: &Rect, from: &Monitor, to: &Monitor) -> Rect {
+    let (from, to) = (&from.work_area, &to.work_area);
+
+    let width = cmp::min(rect.width(), to.width());
+    let height = cmp::min(rect.height(), to.height());
+
+    let scale = |offset: i32, from_size: i32, to_size: i32| {
+        (offset as i64 * to_size as i64 / cmp::max(from_size, 1) as i64) as i32
+    };
+    let left = to.left + scale(rect.left - from.left, from.width(), to.width());
+    let top = to.top + scale(rect.top - from.top, from.height(), to.height());
+
+    let left = cmp::max(to.left, cmp::min(left, to.right - width));
+    let top = cmp::max(to.top, cmp::min(top, to.bottom - height));
+
+    Rect::new(left, top, left + width, top + height)
+}
+
+pub fn centered(rect: &Rect, work_area: &Rect) -> Rect {
+    let left = work_area.left + (work_area.width() - rect.width()) / 2;
+    let top = work_area.top + (work_area.height() - rect.height()) / 2;
+
+    Rect::new(left, top, left + rect.width(), top + rect.height())
+}
+
+// The slot after the one the window is in now, or the first one if it isn't in any
+pub fn next_layout_slot(rect: &Rect, work_area: &Rect, layout: &[Tile]) -> Option<Tile> {
+    let current = layout
+        .iter()
+        .position(|&tile| window_geometry::tile_rect(tile, work_area) == *rect);
+
+    match current {
+        Some(idx) => layout.get((idx + 1) % layout.len()).cloned(),
+        None => layout.first().cloned(),
+    }
+}
+
+pub fn run_command<W: WindowManager>(wm: &mut W, command: WindowCommand) {
+    let window = match wm.foreground_window() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let monitor = match wm.window_monitor(window) {
+        Some(monitor) => monitor,
+        None => return,
+    };
+
+    match command {
+        WindowCommand::ToggleMaximize => {
+            let maximized = wm.is_maximized(window);
+            wm.set_maximized(window, !maximized);
+            return;
+        }
+        WindowCommand::NextMonitor => {
+            let target = match next_monitor(&wm.monitors(), &monitor) {
+                Some(target) => target,
+                None => return,
+            };
+
+            // Maximized windows move by restoring, moving, and maximizing again
+            let maximized = wm.is_maximized(window);
+            if maximized {
+                wm.set_maximized(window, false);
+            }
+
+            let rect = move_to_monitor(&wm.window_rect(window), &monitor, &target);
+            wm.set_window_rect(window, &rect);
+
+            if maximized {
+                wm.set_maximized(window, true);
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    // The rest places the restored window
+    if wm.is_maximized(window) {
+        wm.set_maximized(window, false);
+    }
+
+    let rect = wm.window_rect(window);
+    let work_area = &monitor.work_area;
+
+    let rect = match command {
+        WindowCommand::Tile(tile) => window_geometry::tile_rect(tile, work_area),
+        WindowCommand::Center => centered(&rect, work_area),
+        WindowCommand::Nudge(dx, dy) => rect.offset(dx, dy),
+        WindowCommand::CycleLayout => match next_layout_slot(&rect, work_area, LAYOUT) {
+            Some(tile) => window_geometry::tile_rect(tile, work_area),
+            None => return,
+        },
+        WindowCommand::ToggleMaximize | WindowCommand::NextMonitor => return,
+    };
+
+    wm.set_window_rect(window, &rect);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A single window, on a desktop of 1080p monitors side by side
+    struct FakeDesktop {
+        monitors: Vec<Monitor>,
+        focused: bool,
+        rect: Rect,
+        maximized: bool,
+    }
+
+    impl FakeDesktop {
+        fn new(rect: Rect) -> FakeDesktop {
+            let monitor = |left| Monitor {
+                bounds: Rect::new(left, 0, left + 1920, 1080),
+                work_area: Rect::new(left, 0, left + 1920, 1040),
+                dpi: 96,
+            };
+
+            FakeDesktop {
+                monitors: vec![monitor(0), monitor(1920)],
+                focused: true,
+                rect,
+                maximized: false,
+            }
+        }
+    }
+
+    impl WindowManager for FakeDesktop {
+        type Window = ();
+
+        fn foreground_window(&self) -> Option<()> {
+            if self.focused {
+                Some(())
+            } else {
+                None
+            }
+        }
+
+        fn window_rect(&self, _: ()) -> Rect {
+            self.rect
+        }
+
+        fn set_window_rect(&mut self, _: (), rect: &Rect) {
+            self.rect = *rect;
+        }
+
+        fn is_maximized(&self, _: ()) -> bool {
+            self.maximized
+        }
+
+        fn set_maximized(&mut self, _: (), maximized: bool) {
+            self.maximized = maximized;
+        }
+
+        fn monitors(&self) -> Vec<Monitor> {
+            self.monitors.clone()
+        }
+    }
+
+    #[test]
+    fn nudge() {
+        let mut desktop = FakeDesktop::new(Rect::new(100, 100, 500, 400));
+        run_command(&mut desktop, WindowCommand::Nudge(NUDGE_STEP, 0));
+        run_command(&mut desktop, WindowCommand::Nudge(0, -NUDGE_STEP));
+        assert_eq!(desktop.rect, Rect::new(120, 80, 520, 380));
+    }
+
+    #[test]
+    fn tile_on_the_window_monitor() {
+        let mut desktop = FakeDesktop::new(Rect::new(2000, 100, 2500, 400));
+        desktop.maximized = true;
+
+        run_command(&mut desktop, WindowCommand::Tile(Tile::RightHalf));
+        assert!(!desktop.maximized);
+        assert_eq!(desktop.rect, Rect::new(2880, 0, 3840, 1040));
+    }
+
+    #[test]
+    fn cycle_layout() {
+        let mut desktop = FakeDesktop::new(Rect::new(100, 100, 500, 400));
+
+        let mut slots = Vec::new();
+        for _ in 0..LAYOUT.len() + 1 {
+            run_command(&mut desktop, WindowCommand::CycleLayout);
+            slots.push(desktop.rect);
+        }
+
+        assert_eq!(
+            slots,
+            vec![
+                Rect::new(0, 0, 960, 1040),
+                Rect::new(960, 0, 1920, 1040),
+                Rect::new(0, 0, 640, 1040),
+                Rect::new(640, 0, 1280, 1040),
+                Rect::new(1280, 0, 1920, 1040),
+                Rect::new(0, 0, 960, 1040),
+            ]
+        );
+    }
+
+    #[test]
+    fn next_monitor_keeps_relative_spot() {
+        let mut desktop = FakeDesktop::new(Rect::new(1500, 100, 1800, 400));
+        run_command(&mut desktop, WindowCommand::NextMonitor);
+        assert_eq!(desktop.rect, Rect::new(3420, 100, 3720, 400));
+
+        // Wraps around
+        run_command(&mut desktop, WindowCommand::NextMonitor);
+        assert_eq!(desktop.rect, Rect::new(1500, 100, 1800, 400));
+
+        // Mostly on the second monitor, so that's the one it's on
+        let mut desktop = FakeDesktop::new(Rect::new(1800, 100, 2100, 400));
+        run_command(&mut desktop, WindowCommand::NextMonitor);
+        assert_eq!(desktop.rect, Rect::new(0, 100, 300, 400));
+    }
+
+    #[test]
+    fn nothing_focused() {
+        let mut desktop = FakeDesktop::new(Rect::new(100, 100, 500, 400));
+        desktop.focused = false;
+        run_command(&mut desktop, WindowCommand::Center);
+        assert_eq!(desktop.rect, Rect::new(100, 100, 500, 400));
+    }
+}
@@ -0,0 +1,148 @@
+// Which windows Caps+drag and the keyboard window commands are allowed to move around.
+//
+// Rules are checked in order, and the first one matching a window decides.
+// Windows which don't match any rule are allowed.
+
+// What rules can ask about a window. Answers can take opening the window's process, so they're
+// only worked out for the rules which get as far as asking.
+pub trait WindowInfo {
+    fn class_name(&self) -> String;
+    // Executable file name, e.g. "explorer.exe"
+    fn process_name(&self) -> String;
+    fn title(&self) -> String;
+    // Covers a whole monitor without a caption, like games and video players do
+    fn fullscreen(&self) -> bool;
+    // Runs elevated while h3keys3 doesn't, so SetWindowPos can't touch it
+    fn elevated(&self) -> bool;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WindowMatch {
+    // Exact, ignoring case
+    ClassName(&'static str),
+    // Exact, ignoring case
+    ProcessName(&'static str),
+    // Substring, ignoring case
+    Title(&'static str),
+    FullScreen,
+    Elevated,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WindowRule {
+    Include(WindowMatch),
+    Exclude(WindowMatch),
+}
+
+pub const WINDOW_RULES: &[WindowRule] = &[
+    // Let's not move the Desktop...
+    WindowRule::Exclude(WindowMatch::ClassName("Progman")),
+    WindowRule::Exclude(WindowMatch::ClassName("WorkerW")),
+    // ... nor the taskbars
+    WindowRule::Exclude(WindowMatch::ClassName("Shell_TrayWnd")),
+    WindowRule::Exclude(WindowMatch::ClassName("Shell_SecondaryTrayWnd")),
+    WindowRule::Exclude(WindowMatch::FullScreen),
+    WindowRule::Exclude(WindowMatch::Elevated),
+];
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+pub fn matches<I: WindowInfo>(m: &WindowMatch, info: &I) -> bool {
+    match *m {
+        WindowMatch::ClassName(name) => info.class_name().eq_ignore_ascii_case(name),
+        WindowMatch::ProcessName(name) => info.process_name().eq_ignore_ascii_case(name),
+        WindowMatch::Title(text) => contains_ignore_case(&info.title(), text),
+        WindowMatch::FullScreen => info.fullscreen(),
+        WindowMatch::Elevated => info.elevated(),
+    }
+}
+
+pub fn is_window_allowed<I: WindowInfo>(rules: &[WindowRule], info: &I) -> bool {
+    for rule in rules {
+        match *rule {
+            WindowRule::Include(ref m) if matches(m, info) => return true,
+            WindowRule::Exclude(ref m) if matches(m, info) => return false,
+            _ => (),
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct FakeWindow {
+        class_name: &'static str,
+        elevated: bool,
+        // How many times elevation was asked for
+        elevation_checks: Cell<u32>,
+    }
+
+    impl FakeWindow {
+        fn new(class_name: &'static str, elevated: bool) -> FakeWindow {
+            FakeWindow {
+                class_name,
+                elevated,
+                elevation_checks: Cell::new(0),
+            }
+        }
+    }
+
+    impl WindowInfo for FakeWindow {
+        fn class_name(&self) -> String {
+            self.class_name.to_owned()
+        }
+
+        fn process_name(&self) -> String {
+            "notepad.exe".to_owned()
+        }
+
+        fn title(&self) -> String {
+            "Untitled - Notepad".to_owned()
+        }
+
+        fn fullscreen(&self) -> bool {
+            false
+        }
+
+        fn elevated(&self) -> bool {
+            self.elevation_checks.set(self.elevation_checks.get() + 1);
+            self.elevated
+        }
+    }
+
+    #[test]
+    fn default_rules() {
+        assert!(is_window_allowed(
+            WINDOW_RULES,
+            &FakeWindow::new("Notepad", false)
+        ));
+        assert!(!is_window_allowed(
+            WINDOW_RULES,
+            &FakeWindow::new("Notepad", true)
+        ));
+    }
+
+    #[test]
+    fn only_asks_what_the_rules_get_to() {
+        let window = FakeWindow::new("Shell_TrayWnd", true);
+        assert!(!is_window_allowed(WINDOW_RULES, &window));
+        assert_eq!(window.elevation_checks.get(), 0);
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let rules = [
+            WindowRule::Include(WindowMatch::Title("notepad")),
+            WindowRule::Exclude(WindowMatch::ProcessName("NOTEPAD.EXE")),
+        ];
+        let window = FakeWindow::new("Notepad", true);
+        assert!(is_window_allowed(&rules, &window));
+        assert!(!is_window_allowed(&rules[1..], &window));
+    }
+}
@@ -0,0 +1,259 @@
+// Caps+Tab window switcher.
+//
+// Windows are listed most recently used first, narrowed down by what's typed while
+// the switcher is open, and the selection gets activated when Caps is released.
+// Nothing in here knows about HWNDs, so the ranking works the same on a fake window list.
+
+use std::cmp;
+
+pub struct SwitcherConfig {
+    // Entries listed in the notification, around the selected one
+    pub max_shown: usize,
+}
+
+pub const SWITCHER_CONFIG: SwitcherConfig = SwitcherConfig { max_shown: 5 };
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SwitcherEntry<W> {
+    pub window: W,
+    pub title: String,
+    // Executable file name, e.g. "firefox.exe"
+    pub process_name: String,
+}
+
+// How well `text` matches the query; None if it doesn't contain it at all.
+// Matches at the start of a word beat ones in the middle, and earlier ones beat later ones.
+pub fn match_score(query: &str, text: &str) -> Option<u32> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let query = query.to_lowercase();
+    let text = text.to_lowercase();
+
+    text.match_indices(&query[..])
+        .map(|(pos, _)| {
+            let word_start = text[..pos]
+                .chars()
+                .next_back()
+                .map_or(true, |c| !c.is_alphanumeric());
+
+            let position_bonus = 100u32.saturating_sub(pos as u32);
+            if word_start {
+                1000 + position_bonus
+            } else {
+                position_bonus
+            }
+        })
+        .max()
+}
+
+fn entry_score<W>(query: &str, entry: &SwitcherEntry<W>) -> Option<u32> {
+    let title = match_score(query, &entry.title);
+    // A hit in the title counts for more than one in the executable name
+    let process = match_score(query, &entry.process_name).map(|score| score / 2);
+
+    cmp::max(title, process)
+}
+
+// Indices of the entries matching `query`, best first.
+// Equally good matches keep their most recently used order.
+pub fn rank<W>(entries: &[SwitcherEntry<W>], query: &str) -> Vec<usize> {
+    let mut ranked: Vec<(usize, u32)> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, entry)| entry_score(query, entry).map(|score| (idx, score)))
+        .collect();
+
+    // Stable, so ties stay in MRU order
+    ranked.sort_by(|a, b| b.1.cmp(&a.1));
+    ranked.into_iter().map(|(idx, _)| idx).collect()
+}
+
+pub struct WindowSwitcher<W> {
+    // Most recently used first
+    entries: Vec<SwitcherEntry<W>>,
+    query: String,
+    ranked: Vec<usize>,
+    // Index into `ranked`
+    selected: usize,
+}
+
+impl<W: Copy> WindowSwitcher<W> {
+    // `entries` must be in most recently used order, with the current window first
+    pub fn new(entries: Vec<SwitcherEntry<W>>) -> WindowSwitcher<W> {
+        let mut switcher = WindowSwitcher {
+            entries,
+            query: String::new(),
+            ranked: Vec::new(),
+            selected: 0,
+        };
+        switcher.update();
+
+        // Like Alt+Tab, start on the previous window
+        if switcher.ranked.len() > 1 {
+            switcher.selected = 1;
+        }
+
+        switcher
+    }
+
+    fn update(&mut self) {
+        self.ranked = rank(&self.entries, &self.query);
+        self.selected = 0;
+    }
+
+    pub fn push_char(&mut self, c: char) {
+        self.query.push(c);
+        self.update();
+    }
+
+    pub fn pop_char(&mut self) {
+        if self.query.pop().is_some() {
+            self.update();
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.ranked.is_empty() {
+            self.selected = (self.selected + 1) % self.ranked.len();
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        if !self.ranked.is_empty() {
+            self.selected = (self.selected + self.ranked.len() - 1) % self.ranked.len();
+        }
+    }
+
+    // Entries matching the query, best first
+    pub fn matches(&self) -> Vec<&SwitcherEntry<W>> {
+        self.ranked.iter().map(|&idx| &self.entries[idx]).collect()
+    }
+
+    pub fn selected_index(&self) -> Option<usize> {
+        if self.ranked.is_empty() {
+            None
+        } else {
+            Some(self.selected)
+        }
+    }
+
+    pub fn selection(&self) -> Option<W> {
+        self.selected_index()
+            .map(|selected| self.entries[self.ranked[selected]].window)
+    }
+
+    // Text listing up to `cfg.max_shown` matches around the selection, the selected one marked
+    pub fn describe(&self, cfg: &SwitcherConfig) -> String {
+        let matches = self.matches();
+        let header = format!("Switch to: {}_", self.query);
+
+        let selected = match self.selected_index() {
+            Some(selected) => selected,
+            None => return format!("{}\n(no matches)", header),
+        };
+
+        let shown = if cfg.max_shown > 0 { cfg.max_shown } else { 1 };
+        let first = if selected >= shown {
+            selected + 1 - shown
+        } else {
+            0
+        };
+
+        let mut text = header;
+        for (idx, entry) in matches.iter().enumerate().skip(first).take(shown) {
+            let marker = if idx == selected { "> " } else { "  " };
+            text.push_str(&format!("\n{}{}", marker, entry.title));
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(window: u32, title: &str, process_name: &str) -> SwitcherEntry<u32> {
+        SwitcherEntry {
+            window,
+            title: title.to_owned(),
+            process_name: process_name.to_owned(),
+        }
+    }
+
+    // Most recently used first
+    fn windows() -> Vec<SwitcherEntry<u32>> {
+        vec![
+            entry(1, "main.rs - h3keys3 - Visual Studio Code", "Code.exe"),
+            entry(2, "Inbox - Mozilla Thunderbird", "thunderbird.exe"),
+            entry(
+                3,
+                "Rust Programming Language - Mozilla Firefox",
+                "firefox.exe",
+            ),
+            entry(4, "Downloads", "explorer.exe"),
+            entry(5, "Untitled - Notepad", "notepad.exe"),
+        ]
+    }
+
+    #[test]
+    fn match_score_prefers_word_starts_and_early_hits() {
+        assert_eq!(match_score("", "anything"), Some(0));
+        assert_eq!(match_score("fox", "Firefox"), Some(96));
+        assert_eq!(match_score("fire", "Firefox"), Some(1100));
+        assert_eq!(match_score("FIRE", "firefox"), Some(1100));
+        assert_eq!(match_score("fox", "Mozilla Firefox"), Some(88));
+        assert!(match_score("fox", "a fox") > match_score("fox", "a big fox"));
+        assert!(match_score("fox", "Firefox - fox") > match_score("fox", "Firefox"));
+        assert_eq!(match_score("chrome", "Mozilla Firefox"), None);
+    }
+
+    #[test]
+    fn rank_without_query_keeps_mru_order() {
+        assert_eq!(rank(&windows(), ""), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn rank_best_match_first() {
+        // A word start beats a hit in the middle, whatever the MRU order
+        assert_eq!(rank(&windows(), "fox"), vec![2]);
+        assert_eq!(rank(&windows(), "in"), vec![1, 0, 2]);
+        assert_eq!(rank(&windows(), "no"), vec![4]);
+    }
+
+    #[test]
+    fn rank_ties_keep_mru_order() {
+        assert_eq!(rank(&windows(), "mozilla"), vec![1, 2]);
+    }
+
+    #[test]
+    fn rank_title_beats_process_name() {
+        let entries = vec![
+            entry(1, "Downloads", "firefox.exe"),
+            entry(2, "Firefox Privacy Notice", "explorer.exe"),
+        ];
+        assert_eq!(rank(&entries, "firefox"), vec![1, 0]);
+        // Found by the executable name alone
+        assert_eq!(rank(&entries, "explorer"), vec![1]);
+    }
+
+    #[test]
+    fn switcher_narrows_down_and_selects() {
+        let mut switcher = WindowSwitcher::new(windows());
+        // Starts on the previous window
+        assert_eq!(switcher.selection(), Some(2));
+
+        switcher.push_char('m');
+        switcher.push_char('o');
+        assert_eq!(switcher.selection(), Some(2));
+        switcher.select_prev();
+        assert_eq!(switcher.selection(), Some(3));
+
+        switcher.push_char('x');
+        assert_eq!(switcher.selection(), None);
+        switcher.pop_char();
+        assert_eq!(switcher.matches().len(), 2);
+    }
+}